@@ -0,0 +1,705 @@
+//! One line per forwarded request, in the Common or Combined Log Format read
+//! by most log analysers, or as JSON with every field of an `AccessRecord`.
+//!
+//! Lines are written to stdout or to a file that is rotated once it grows
+//! past a size limit. Other destinations can be added by implementing `LogSink`.
+
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::TcpIpError;
+use crate::har::Recording;
+use crate::header_item::HeaderItem;
+use crate::request::request_header::RequestHeader;
+use crate::util::{json_option, json_string, SharedFiles};
+use crate::Result;
+
+pub const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_LOG_MAX_FILES: usize = 5;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum LogFormat {
+    /// `client - - [time] "request line" status bytes_out`
+    Common,
+    /// The Common Log Format followed by `"referer" "user agent"`.
+    #[default]
+    Combined,
+    /// A JSON object per line with every field of the record.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = TcpIpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "common" => Ok(LogFormat::Common),
+            "combined" => Ok(LogFormat::Combined),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(TcpIpError::parse(format!(
+                "Unknown log format '{}', expected 'common', 'combined' or 'json'",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            LogFormat::Common => "common",
+            LogFormat::Combined => "combined",
+            LogFormat::Json => "json",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+/// Where and how a server writes its access log.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LogOptions {
+    pub format: LogFormat,
+    /// Written to stdout if not set.
+    pub file: Option<PathBuf>,
+    /// Size in bytes after which the file is rotated.
+    pub max_size: u64,
+    /// Number of rotated files kept next to the current one.
+    pub max_files: usize,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::default(),
+            file: None,
+            max_size: DEFAULT_LOG_MAX_SIZE,
+            max_files: DEFAULT_LOG_MAX_FILES,
+        }
+    }
+}
+
+/// The method, URI and version a request was sent with.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RequestLine {
+    pub method: String,
+    pub uri: String,
+    pub version: String,
+}
+
+impl std::fmt::Display for RequestLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.method, self.uri, self.version)
+    }
+}
+
+/// A request and the response sent for it.
+#[derive(Debug, Clone)]
+pub struct AccessRecord {
+    /// When the request was received.
+    pub time: SystemTime,
+    pub server: String,
+    pub client_address: Option<SocketAddr>,
+    /// Not set if the request couldn't be parsed.
+    pub request: Option<RequestLine>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub status_code: u16,
+    /// Bytes read from the client, including the header.
+    pub bytes_in: u64,
+    /// Bytes sent to the client, including the header.
+    pub bytes_out: u64,
+    /// Time from sending the request to the remote server to receiving the
+    /// header of its response. Not set if the remote server wasn't reached.
+    pub upstream_latency: Option<Duration>,
+    /// Why the request failed, if it did. Only the JSON format includes it,
+    /// as the fields of the others are fixed.
+    pub error: Option<String>,
+}
+
+impl AccessRecord {
+    /// Creates a record for a request received now.
+    pub fn new<T: AsRef<str>>(server: T, client_address: Option<SocketAddr>) -> Self {
+        Self {
+            time: SystemTime::now(),
+            server: server.as_ref().to_owned(),
+            client_address,
+            request: None,
+            referer: None,
+            user_agent: None,
+            status_code: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            upstream_latency: None,
+            error: None,
+        }
+    }
+
+    /// Records the request line and the headers logged from `header`.
+    pub fn set_request(&mut self, header: &RequestHeader) {
+        let get = |key| {
+            header
+                .headers()
+                .as_ref()
+                .and_then(|h| h.get(key))
+                .map(str::to_owned)
+        };
+
+        self.request = Some(RequestLine {
+            method: header.method.to_string(),
+            uri: header.uri.to_string(),
+            version: header.version.to_string(),
+        });
+        self.referer = get("Referer");
+        self.user_agent = get("User-Agent");
+    }
+
+    /// Formats the record as a single line, without the line ending.
+    pub fn format(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Common => self.common(),
+            LogFormat::Combined => format!(
+                "{} \"{}\" \"{}\"",
+                self.common(),
+                quoted(self.referer.as_deref()),
+                quoted(self.user_agent.as_deref())
+            ),
+            LogFormat::Json => self.json(),
+        }
+    }
+
+    fn common(&self) -> String {
+        let client = match self.client_address {
+            Some(address) => address.ip().to_string(),
+            None => "-".to_owned(),
+        };
+
+        let request = self.request.as_ref().map(RequestLine::to_string);
+
+        let bytes_out = match self.bytes_out {
+            0 => "-".to_owned(),
+            bytes => bytes.to_string(),
+        };
+
+        format!(
+            "{} - - [{}] \"{}\" {} {}",
+            client,
+            clf_time(self.time),
+            quoted(request.as_deref()),
+            self.status_code,
+            bytes_out
+        )
+    }
+
+    fn json(&self) -> String {
+        let request = self.request.as_ref();
+
+        let mut line = String::from("{");
+
+        let _ = write!(line, "\"time\":{}", json_string(&rfc3339_time(self.time)));
+        let _ = write!(line, ",\"server\":{}", json_string(&self.server));
+        let _ = write!(
+            line,
+            ",\"client\":{}",
+            json_option(self.client_address.map(|a| a.to_string()).as_deref())
+        );
+        let _ = write!(
+            line,
+            ",\"method\":{}",
+            json_option(request.map(|r| r.method.as_str()))
+        );
+        let _ = write!(
+            line,
+            ",\"uri\":{}",
+            json_option(request.map(|r| r.uri.as_str()))
+        );
+        let _ = write!(
+            line,
+            ",\"version\":{}",
+            json_option(request.map(|r| r.version.as_str()))
+        );
+        let _ = write!(line, ",\"status\":{}", self.status_code);
+        let _ = write!(line, ",\"bytes_in\":{}", self.bytes_in);
+        let _ = write!(line, ",\"bytes_out\":{}", self.bytes_out);
+
+        match self.upstream_latency {
+            Some(latency) => {
+                let _ = write!(
+                    line,
+                    ",\"upstream_latency_ms\":{:.3}",
+                    latency.as_secs_f64() * 1000.0
+                );
+            }
+            None => line.push_str(",\"upstream_latency_ms\":null"),
+        }
+
+        let _ = write!(
+            line,
+            ",\"referer\":{}",
+            json_option(self.referer.as_deref())
+        );
+        let _ = write!(
+            line,
+            ",\"user_agent\":{}",
+            json_option(self.user_agent.as_deref())
+        );
+        let _ = write!(line, ",\"error\":{}", json_option(self.error.as_deref()));
+
+        line.push('}');
+        line
+    }
+}
+
+/// A destination for formatted access log lines.
+pub trait LogSink: Send + Sync {
+    /// Writes `line` followed by a line ending. Lines from different
+    /// connections must not be interleaved.
+    fn write_line(&self, line: &str) -> io::Result<()>;
+}
+
+pub struct Stdout;
+
+impl LogSink for Stdout {
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+
+        writeln!(stdout, "{}", line)?;
+        stdout.flush()
+    }
+}
+
+struct OpenFile {
+    file: File,
+    size: u64,
+}
+
+/// Appends lines to a file, renaming it to `<path>.1` once it would grow past
+/// `max_size`. Older files are renamed to `<path>.2` and so on, keeping at
+/// most `max_files`.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: Mutex<OpenFile>,
+}
+
+impl RotatingFile {
+    pub fn open<P: AsRef<Path>>(path: P, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let file = append(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            file: Mutex::new(OpenFile { file, size }),
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    fn rotate(&self, open: &mut OpenFile) -> io::Result<()> {
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated_path(index);
+
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(index + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        open.file = append(&self.path)?;
+        open.size = 0;
+
+        Ok(())
+    }
+}
+
+impl LogSink for RotatingFile {
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        let mut open = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        let len = line.len() as u64 + 1;
+
+        if open.size > 0 && open.size + len > self.max_size {
+            self.rotate(&mut open)?;
+        }
+
+        writeln!(open.file, "{}", line)?;
+        open.size += len;
+
+        Ok(())
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Formats access records and writes them to a sink.
+#[derive(Clone)]
+pub struct AccessLog {
+    format: LogFormat,
+    sink: Arc<dyn LogSink>,
+}
+
+impl AccessLog {
+    pub fn new(format: LogFormat, sink: Arc<dyn LogSink>) -> Self {
+        Self { format, sink }
+    }
+
+    /// Opens the log described by `options`. Servers logging to the same file
+    /// share it, so that it is rotated once for all of them.
+    pub fn open(options: &LogOptions) -> Result<Self> {
+        let path = match &options.file {
+            Some(path) => path,
+            None => return Ok(Self::new(options.format, Arc::new(Stdout))),
+        };
+
+        static OPEN_FILES: SharedFiles<RotatingFile> = SharedFiles::new();
+
+        let file = OPEN_FILES
+            .get_or_open(path, |path| {
+                RotatingFile::open(path, options.max_size, options.max_files)
+            })
+            .map_err(|e| {
+                TcpIpError::config(format!(
+                    "Failed to open log file '{}' - {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+
+        Ok(Self::new(options.format, file))
+    }
+
+    pub fn log(&self, record: &AccessRecord) {
+        if let Err(e) = self.sink.write_line(&record.format(self.format)) {
+            eprintln!("Failed to write access log - {}", e);
+        }
+    }
+}
+
+/// Counts the bytes consumed from a reader, which for a `BufRead` excludes
+/// bytes that have been buffered but not yet consumed. The bytes can also be
+/// recorded for traffic capture.
+pub struct CountingReader<R> {
+    pub(crate) inner: R,
+    pub(crate) count: u64,
+    pub(crate) recording: Option<Recording>,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            count: 0,
+            recording: None,
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Starts recording up to `limit` bytes, discarding any earlier recording.
+    pub fn record(&mut self, limit: usize) {
+        self.recording = Some(Recording::new(limit));
+    }
+
+    pub fn take_recording(&mut self) -> Option<Recording> {
+        self.recording.take()
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+
+        self.count += read as u64;
+
+        if let Some(recording) = &mut self.recording {
+            recording.push(&buf[..read]);
+        }
+
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let buffered = self.inner.fill_buf()?;
+
+        if let Some(recording) = &mut self.recording {
+            recording.buffered(buffered);
+        }
+
+        Ok(buffered)
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.inner.consume(amount);
+        self.count += amount as u64;
+
+        if let Some(recording) = &mut self.recording {
+            recording.consumed(amount);
+        }
+    }
+}
+
+/// Counts the bytes written to a writer, which can also be recorded for
+/// traffic capture.
+pub struct CountingWriter<W> {
+    pub(crate) inner: W,
+    pub(crate) count: u64,
+    pub(crate) recording: Option<Recording>,
+}
+
+impl<W> CountingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            count: 0,
+            recording: None,
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Starts recording up to `limit` bytes, discarding any earlier recording.
+    pub fn record(&mut self, limit: usize) {
+        self.recording = Some(Recording::new(limit));
+    }
+
+    pub fn take_recording(&mut self) -> Option<Recording> {
+        self.recording.take()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+
+        self.count += written as u64;
+
+        if let Some(recording) = &mut self.recording {
+            recording.push(&buf[..written]);
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Escapes a value quoted in the Common Log Format the way Apache does
+fn quoted(value: Option<&str>) -> String {
+    let value = match value {
+        Some(value) => value,
+        None => return "-".to_owned(),
+    };
+
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\x{:02x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+struct UtcTime {
+    year: i64,
+    month: usize,
+    day: u64,
+    hour: u64,
+    minute: u64,
+    second: u64,
+    millis: u32,
+}
+
+impl UtcTime {
+    // Converts days since the epoch to a date with the algorithm from
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    fn new(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seconds = since_epoch.as_secs();
+
+        let days = (seconds / 86400) as i64 + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month + 2) / 5 + 1) as u64;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        Self {
+            year,
+            month: month as usize,
+            day,
+            hour: seconds % 86400 / 3600,
+            minute: seconds % 3600 / 60,
+            second: seconds % 60,
+            millis: since_epoch.subsec_millis(),
+        }
+    }
+}
+
+// `10/Oct/2000:13:55:36 +0000`
+fn clf_time(time: SystemTime) -> String {
+    let t = UtcTime::new(time);
+
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        t.day,
+        MONTHS[t.month - 1],
+        t.year,
+        t.hour,
+        t.minute,
+        t.second
+    )
+}
+
+// `2000-10-10T13:55:36.000Z`
+pub(crate) fn rfc3339_time(time: SystemTime) -> String {
+    let t = UtcTime::new(time);
+
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        t.year, t.month, t.day, t.hour, t.minute, t.second, t.millis
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::net::SocketAddr;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::access_log::{
+        AccessLog, AccessRecord, LogFormat, LogOptions, LogSink, RotatingFile,
+    };
+    use crate::header_item::HeaderItem;
+    use crate::request::request_header::RequestHeader;
+
+    fn record() -> AccessRecord {
+        let client: SocketAddr = "127.0.0.1:50000".parse().expect("Invalid address");
+
+        let mut record = AccessRecord::new("api", Some(client));
+        record.time = UNIX_EPOCH + Duration::from_millis(971_186_136_250);
+        record.set_request(
+            &RequestHeader::from_bytes(
+                b"GET /a.txt?q=\"x\" HTTP/1.1\r\nHost: test\r\nUser-Agent: curl/8.0",
+            )
+            .expect("Failed to parse header"),
+        );
+        record.status_code = 200;
+        record.bytes_in = 60;
+        record.bytes_out = 2326;
+        record.upstream_latency = Some(Duration::from_micros(12_345));
+        record
+    }
+
+    #[test]
+    fn test_format() {
+        let record = record();
+
+        assert_eq!(
+            record.format(LogFormat::Common),
+            r#"127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /a.txt?q=\"x\" HTTP/1.1" 200 2326"#
+        );
+        assert_eq!(
+            record.format(LogFormat::Combined),
+            r#"127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /a.txt?q=\"x\" HTTP/1.1" 200 2326 "-" "curl/8.0""#
+        );
+        assert_eq!(
+            record.format(LogFormat::Json),
+            r#"{"time":"2000-10-10T13:55:36.250Z","server":"api","client":"127.0.0.1:50000","method":"GET","uri":"/a.txt?q=\"x\"","version":"HTTP/1.1","status":200,"bytes_in":60,"bytes_out":2326,"upstream_latency_ms":12.345,"referer":null,"user_agent":"curl/8.0","error":null}"#
+        );
+
+        let unparsed = AccessRecord {
+            request: None,
+            bytes_out: 0,
+            upstream_latency: None,
+            status_code: 400,
+            ..record
+        };
+
+        assert_eq!(
+            unparsed.format(LogFormat::Common),
+            r#"127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "-" 400 -"#
+        );
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = std::env::temp_dir().join(format!("access_log_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Failed to create directory");
+
+        let path = dir.join("access.log");
+        let file = RotatingFile::open(&path, 10, 2).expect("Failed to open log");
+
+        for line in &["first", "second", "third", "fourth"] {
+            file.write_line(line).expect("Failed to write");
+        }
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap_or_default();
+
+        assert_eq!(read("access.log"), "fourth\n");
+        assert_eq!(read("access.log.1"), "third\n");
+        assert_eq!(read("access.log.2"), "second\n");
+        assert!(!dir.join("access.log.3").exists());
+
+        // Servers logging to the same path share one file
+        let options = LogOptions {
+            format: LogFormat::Common,
+            file: Some(path.clone()),
+            ..LogOptions::default()
+        };
+        let first = AccessLog::open(&options).expect("Failed to open log");
+        let second = AccessLog::open(&options).expect("Failed to open log");
+
+        assert!(std::sync::Arc::ptr_eq(&first.sink, &second.sink));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt::Formatter;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+use crate::error::TcpIpError;
+use crate::Result;
+
+/// A host and port, where the host is an IPv4 address, an IPv6 address or a DNS name.
+///
+/// Names are resolved each time the address is used, so a server whose records
+/// change is picked up without a restart.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Address {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Address {
+    pub fn new<T: AsRef<str>>(host: T, port: u16) -> Self {
+        Self {
+            host: host.as_ref().to_owned(),
+            port,
+        }
+    }
+
+    /// Parses `host:port`, or just a port in which case `default_host` is used.
+    pub fn parse_with_default_host(s: &str, default_host: &str) -> Result<Self> {
+        match s.parse::<u16>() {
+            Ok(port) => Ok(Self::new(default_host, port)),
+            Err(_) => s.parse(),
+        }
+    }
+
+    /// Parses `host:port`, or just a host in which case `default_port` is used.
+    pub fn parse_with_default_port(s: &str, default_port: u16) -> Result<Self> {
+        // The colons of an IPv6 address are in brackets
+        let has_port = s.rfind(':').is_some_and(|i| !s[i..].contains(']'));
+
+        if has_port {
+            s.parse()
+        } else {
+            format!("{}:{}", s, default_port).parse()
+        }
+    }
+
+    /// Resolves the address, returning every result in the order given by the resolver.
+    pub fn resolve(&self) -> std::io::Result<Vec<SocketAddr>> {
+        if let Ok(ip) = self.host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, self.port)]);
+        }
+
+        let addresses = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .collect::<Vec<_>>();
+
+        if addresses.is_empty() {
+            Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("No addresses found for '{}'", self.host),
+            ))
+        } else {
+            Ok(addresses)
+        }
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(address: SocketAddr) -> Self {
+        Self::new(address.ip().to_string(), address.port())
+    }
+}
+
+impl FromStr for Address {
+    type Err = TcpIpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| TcpIpError::parse(format!("Missing port in address '{}'", s)))?;
+
+        let port = port
+            .parse::<u16>()
+            .map_err(|e| TcpIpError::parse(format!("Invalid port in address '{}' - {}", s, e)))?;
+
+        let host = if let Some(ip) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+            ip.parse::<Ipv6Addr>().map_err(|e| {
+                TcpIpError::parse(format!("Invalid IPv6 address in '{}' - {}", s, e))
+            })?;
+
+            ip
+        } else if host.contains(':') {
+            return Err(TcpIpError::parse(format!(
+                "IPv6 addresses must be written in brackets such as '[::1]:80', found '{}'",
+                s
+            )));
+        } else {
+            host
+        };
+
+        let valid_host = !host.is_empty()
+            && host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' || c == ':');
+
+        if valid_host {
+            Ok(Self::new(host, port))
+        } else {
+            Err(TcpIpError::parse(format!(
+                "Invalid host in address '{}'",
+                s
+            )))
+        }
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::address::Address;
+
+    #[test]
+    fn test_from_str_to_string() {
+        for address in &[
+            "127.0.0.1:80",
+            "[::1]:8080",
+            "backend.internal:80",
+            "0.0.0.0:1",
+        ] {
+            let parsed = address.parse::<Address>().expect("Failed to parse address");
+
+            assert_eq!(parsed.to_string(), *address);
+        }
+
+        assert_eq!(
+            "[::1]:8080"
+                .parse::<Address>()
+                .expect("Failed to parse address"),
+            Address::new("::1", 8080)
+        );
+
+        assert!("::1:8080".parse::<Address>().is_err());
+        assert!("localhost".parse::<Address>().is_err());
+        assert!("local host:80".parse::<Address>().is_err());
+        assert!("[localhost]:80".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn test_default_host() {
+        let address =
+            Address::parse_with_default_host("1234", "127.0.0.1").expect("Failed to parse");
+
+        assert_eq!(address, Address::new("127.0.0.1", 1234));
+    }
+
+    #[test]
+    fn test_default_port() {
+        let parse = |s: &str| Address::parse_with_default_port(s, 80).expect("Failed to parse");
+
+        assert_eq!(parse("a.test"), Address::new("a.test", 80));
+        assert_eq!(parse("a.test:8080"), Address::new("a.test", 8080));
+        assert_eq!(parse("[::1]"), Address::new("::1", 80));
+        assert!(Address::parse_with_default_port("a.test:x", 80).is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let address = Address::new("::1", 80);
+
+        assert_eq!(
+            address.resolve().expect("Failed to resolve"),
+            vec!["[::1]:80".parse::<SocketAddr>().expect("Invalid address")]
+        );
+
+        let resolved = Address::new("localhost", 80)
+            .resolve()
+            .expect("Failed to resolve localhost");
+
+        assert!(resolved.iter().all(|a| a.ip().is_loopback()));
+    }
+}
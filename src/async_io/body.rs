@@ -0,0 +1,384 @@
+use std::io::{self, ErrorKind};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::async_io::timed;
+use crate::body::{parse_chunk_size, strip_chunk_line_ending, MAX_CHUNK_LINE_LENGTH};
+use crate::body_type::BodyType;
+use crate::error::TcpIpError;
+use crate::Result;
+
+/// Async equivalent of `BodyType::read_body`, reading the whole body into memory.
+pub async fn read_body<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    body_type: &BodyType,
+) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+
+    copy_body(reader, body_type, &mut body, false, None).await?;
+
+    Ok(body)
+}
+
+/// Reads a request body into memory like `read_body`, failing with
+/// `TcpIpError::BodyTooLarge` rather than reading more than `max_body_size` bytes.
+///
+/// Errors reading the body are returned as client errors, and each read fails
+/// with `ErrorKind::TimedOut` if it takes longer than `timeout`.
+pub(crate) async fn read_client_body<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    body_type: &BodyType,
+    max_body_size: u64,
+    timeout: Option<Duration>,
+) -> Result<Vec<u8>> {
+    let too_large = || TcpIpError::BodyTooLarge {
+        limit: max_body_size,
+    };
+
+    if let Some(content_length) = body_type.content_length() {
+        if content_length > max_body_size {
+            return Err(too_large());
+        }
+    }
+
+    let mut body = Vec::new();
+    let mut writer = BodyWriter {
+        inner: &mut body,
+        chunked: false,
+        timeout,
+        write_failed: false,
+        max_size: Some(max_body_size),
+        size: 0,
+    };
+
+    match copy_framed(reader, body_type, &mut writer).await {
+        Ok(_) => Ok(body),
+        Err(_) if writer.size > max_body_size => Err(too_large()),
+        Err(e) => Err(TcpIpError::from_client_body(e)),
+    }
+}
+
+/// Streams a body framed by `body_type` from `reader` to `writer`, returning
+/// the number of body bytes copied.
+///
+/// A chunked body is decoded, and the body is encoded as chunks if `chunked`
+/// is set. Each read and write fails with `ErrorKind::TimedOut` if it takes
+/// longer than `timeout`.
+pub async fn copy_body<R, W>(
+    reader: &mut R,
+    body_type: &BodyType,
+    writer: &mut W,
+    chunked: bool,
+    timeout: Option<Duration>,
+) -> io::Result<u64>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut writer = BodyWriter {
+        inner: writer,
+        chunked,
+        timeout,
+        write_failed: false,
+        max_size: None,
+        size: 0,
+    };
+
+    copy_framed(reader, body_type, &mut writer).await
+}
+
+/// Copies a request body like `copy_body`, but errors reading it from the client
+/// are returned as client errors so they aren't blamed on the remote server.
+pub(crate) async fn copy_client_body<R, W>(
+    reader: &mut R,
+    body_type: &BodyType,
+    writer: &mut W,
+    chunked: bool,
+    timeout: Option<Duration>,
+) -> Result<u64>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut writer = BodyWriter {
+        inner: writer,
+        chunked,
+        timeout,
+        write_failed: false,
+        max_size: None,
+        size: 0,
+    };
+
+    match copy_framed(reader, body_type, &mut writer).await {
+        Ok(copied) => Ok(copied),
+        Err(e) if writer.write_failed => Err(e.into()),
+        Err(e) => Err(TcpIpError::from_client_body(e)),
+    }
+}
+
+async fn copy_framed<R, W>(
+    reader: &mut R,
+    body_type: &BodyType,
+    writer: &mut BodyWriter<'_, W>,
+) -> io::Result<u64>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let timeout = writer.timeout;
+
+    let copied = match *body_type {
+        BodyType::Fixed(content_length) => {
+            copy_fixed(reader, content_length as u64, writer, "body").await?
+        }
+        BodyType::Chunked => {
+            let mut copied = 0;
+
+            loop {
+                let size = parse_chunk_size(&read_chunk_line(reader, timeout).await?)?;
+
+                // 0 indicates the end of the chunks, followed by optional trailers
+                if size == 0 {
+                    while !read_chunk_line(reader, timeout).await?.is_empty() {}
+                    break;
+                }
+
+                copied += copy_fixed(reader, size, writer, "chunk").await?;
+
+                if !read_chunk_line(reader, timeout).await?.is_empty() {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "Missing CRLF after chunk data",
+                    ));
+                }
+            }
+
+            copied
+        }
+        BodyType::UntilClose => {
+            let mut copied = 0;
+
+            loop {
+                let data = timed(timeout, reader.fill_buf()).await?;
+
+                if data.is_empty() {
+                    break;
+                }
+
+                let len = data.len();
+
+                writer.write(data).await?;
+                reader.consume(len);
+                copied += len as u64;
+            }
+
+            copied
+        }
+    };
+
+    writer.finish().await?;
+
+    Ok(copied)
+}
+
+// Copies exactly `length` bytes, `part` names what is being copied in errors
+async fn copy_fixed<R, W>(
+    reader: &mut R,
+    length: u64,
+    writer: &mut BodyWriter<'_, W>,
+    part: &str,
+) -> io::Result<u64>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut remaining = length;
+
+    while remaining > 0 {
+        let data = timed(writer.timeout, reader.fill_buf()).await?;
+
+        if data.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Connection closed before the end of the {}", part),
+            ));
+        }
+
+        let len = data.len().min(remaining as usize);
+
+        writer.write(&data[..len]).await?;
+        reader.consume(len);
+        remaining -= len as u64;
+    }
+
+    Ok(length)
+}
+
+async fn read_chunk_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    timeout: Option<Duration>,
+) -> io::Result<Vec<u8>> {
+    let mut line = Vec::new();
+    let mut limited = reader.take(MAX_CHUNK_LINE_LENGTH);
+
+    timed(timeout, limited.read_until(b'\n', &mut line)).await?;
+    strip_chunk_line_ending(&mut line)?;
+
+    Ok(line)
+}
+
+// Async equivalent of `ChunkedWriter`, which passes data through unless `chunked` is set
+struct BodyWriter<'a, W> {
+    inner: &'a mut W,
+    chunked: bool,
+    timeout: Option<Duration>,
+    /// Set if an error came from writing rather than reading.
+    write_failed: bool,
+    /// Fails writes once more than this many bytes have been written.
+    max_size: Option<u64>,
+    size: u64,
+}
+
+impl<W: AsyncWrite + Unpin> BodyWriter<'_, W> {
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.size += data.len() as u64;
+
+        if self.max_size.is_some_and(|max_size| self.size > max_size) {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Body is too large"));
+        }
+
+        let written = self.write_data(data).await;
+
+        self.write_failed |= written.is_err();
+        written
+    }
+
+    async fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.chunked {
+            if data.is_empty() {
+                return Ok(());
+            }
+
+            let size = format!("{:x}\r\n", data.len());
+
+            timed(self.timeout, self.inner.write_all(size.as_bytes())).await?;
+            timed(self.timeout, self.inner.write_all(data)).await?;
+            timed(self.timeout, self.inner.write_all(b"\r\n")).await
+        } else {
+            timed(self.timeout, self.inner.write_all(data)).await
+        }
+    }
+
+    // Writes the terminating zero length chunk
+    async fn finish(&mut self) -> io::Result<()> {
+        if self.chunked {
+            let written = timed(self.timeout, self.inner.write_all(b"0\r\n\r\n")).await;
+
+            self.write_failed |= written.is_err();
+            written
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use tokio::io::BufReader;
+
+    use crate::async_io::block_on;
+    use crate::async_io::body::{copy_body, read_body, read_client_body};
+    use crate::body_type::BodyType;
+
+    #[test]
+    fn test_read_chunked() {
+        let raw =
+            b"7;ext=1\r\nMozilla\r\n9\r\nDeveloper\r\n7\r\nNetwork\r\n0\r\nTrailer: 1\r\n\r\nnext";
+
+        block_on(async {
+            let mut reader = BufReader::with_capacity(5, &raw[..]);
+
+            let body = read_body(&mut reader, &BodyType::Chunked)
+                .await
+                .expect("Failed to read body");
+
+            assert_eq!(body, b"MozillaDeveloperNetwork");
+            assert_eq!(reader.buffer(), b"next");
+        });
+    }
+
+    #[test]
+    fn test_copy_body_chunked() {
+        block_on(async {
+            let mut reader = BufReader::with_capacity(4, &b"Wikipedia"[..]);
+            let mut chunks = Vec::new();
+
+            let copied = copy_body(&mut reader, &BodyType::Fixed(9), &mut chunks, true, None)
+                .await
+                .expect("Failed to copy body");
+
+            assert_eq!(copied, 9);
+            assert_eq!(chunks, b"4\r\nWiki\r\n4\r\npedi\r\n1\r\na\r\n0\r\n\r\n");
+
+            let body = read_body(&mut BufReader::new(chunks.as_slice()), &BodyType::Chunked)
+                .await
+                .expect("Failed to read body");
+
+            assert_eq!(body, b"Wikipedia");
+        });
+    }
+
+    #[test]
+    fn test_read_client_body_limit() {
+        block_on(async {
+            let read = |raw: &'static [u8], body_type: BodyType| async move {
+                read_client_body(&mut BufReader::new(raw), &body_type, 4, None)
+                    .await
+                    .map_err(|e| e.to_string())
+            };
+
+            assert_eq!(
+                read(b"4\r\nabcd\r\n0\r\n\r\n", BodyType::Chunked).await,
+                Ok(b"abcd".to_vec())
+            );
+            assert_eq!(
+                read(b"3\r\nabc\r\n2\r\nde\r\n", BodyType::Chunked).await,
+                Err("Body exceeds the limit of 4 bytes".to_owned())
+            );
+            assert_eq!(
+                read(b"", BodyType::Fixed(5)).await,
+                Err("Body exceeds the limit of 4 bytes".to_owned())
+            );
+            assert_eq!(
+                read(b"abc", BodyType::Fixed(4)).await,
+                Err("Failed to read the request body from the client - Connection closed before the end of the body".to_owned())
+            );
+        });
+    }
+
+    #[test]
+    fn test_truncated_body() {
+        block_on(async {
+            let e = read_body(&mut BufReader::new(&b"abc"[..]), &BodyType::Fixed(5))
+                .await
+                .expect_err("Read a truncated body");
+
+            assert!(e.to_string().contains("before the end of the body"));
+
+            let e = copy_body(
+                &mut BufReader::new(&b"5\r\nab"[..]),
+                &BodyType::Chunked,
+                &mut Vec::new(),
+                false,
+                None,
+            )
+            .await
+            .expect_err("Copied a truncated chunk");
+
+            assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+        });
+    }
+}
@@ -0,0 +1,728 @@
+use std::future::Future;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncBufRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::TcpStream;
+
+use crate::address::Address;
+use crate::async_io::body::{copy_body, copy_client_body, read_client_body};
+use crate::async_io::{connect_remote, connect_resolved, read_header, resolve, timed};
+use crate::body::Body;
+use crate::body_type::BodyType;
+use crate::error::TcpIpError;
+use crate::forward_proxy::Destinations;
+use crate::header_item::HeaderItem;
+use crate::header_parser::DEFAULT_MAX_HEADER_SIZE;
+use crate::http_item::HttpItem;
+use crate::http_version::HttpVersion;
+use crate::replay::Replay;
+use crate::request::request_header::RequestHeader;
+use crate::request::Request;
+use crate::response::response_header::ResponseHeader;
+use crate::response::Response;
+use crate::router::Router;
+use crate::stream_helper::{
+    client_error, prepare_request, set_connection_headers, upstream_connect_error, upstream_error,
+    ConnectionPool, Forwarded, Forwarder, KeepAlive, PooledConnection, Relayed,
+};
+use crate::Result;
+
+impl ConnectionPool<TcpStream> {
+    /// Async equivalent of `ConnectionPool::checkout`.
+    pub async fn checkout_async(
+        &self,
+        address: &Address,
+        timeout_seconds: u64,
+    ) -> Result<PooledConnection<TcpStream>> {
+        self.checkout_or_async(address, connect_remote(address, timeout_seconds))
+            .await
+    }
+
+    /// Async equivalent of `ConnectionPool::checkout_resolved`.
+    pub async fn checkout_resolved_async(
+        &self,
+        address: &Address,
+        resolved: &[SocketAddr],
+        timeout_seconds: u64,
+    ) -> Result<PooledConnection<TcpStream>> {
+        self.checkout_or_async(
+            address,
+            connect_resolved(address, resolved, timeout_seconds),
+        )
+        .await
+    }
+
+    // `connect` is only awaited if there is no healthy idle connection
+    async fn checkout_or_async<F>(
+        &self,
+        address: &Address,
+        connect: F,
+    ) -> Result<PooledConnection<TcpStream>>
+    where
+        F: Future<Output = Result<TcpStream>>,
+    {
+        while let Some(stream) = self.take_idle(address) {
+            if is_healthy(&stream) {
+                return Ok(PooledConnection {
+                    stream,
+                    reused: true,
+                });
+            }
+        }
+
+        Ok(PooledConnection {
+            stream: connect.await?,
+            reused: false,
+        })
+    }
+}
+
+// An idle connection is healthy if the remote server has neither closed it
+// nor sent anything unexpected on it
+fn is_healthy(stream: &TcpStream) -> bool {
+    matches!(stream.try_read(&mut [0; 1]), Err(e) if e.kind() == ErrorKind::WouldBlock)
+}
+
+impl Forwarder<TcpStream> {
+    fn timeout(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.timeout_seconds))
+    }
+
+    /// Async equivalent of `Forwarder::forward_request`, reading the body of the
+    /// request with `header` from `local_reader`.
+    ///
+    /// Requests are only sent again on a new connection if they have no body, as
+    /// the body is streamed rather than held in memory.
+    pub async fn forward_request_async<R, L>(
+        &self,
+        header: RequestHeader,
+        local_reader: &mut R,
+        local_writer: &mut L,
+        keep_alive: KeepAlive,
+    ) -> Result<Forwarded>
+    where
+        R: AsyncBufRead + Unpin,
+        L: AsyncWrite + Unpin,
+    {
+        self.forward_to_async(
+            &self.remote_address,
+            None,
+            header,
+            local_reader,
+            local_writer,
+            keep_alive,
+        )
+        .await
+    }
+
+    /// Async equivalent of `Forwarder::proxy_request`, reading the body of the
+    /// request with `header` from `local_reader`.
+    pub async fn proxy_request_async<R, L>(
+        &self,
+        destinations: &Destinations,
+        mut header: RequestHeader,
+        local_reader: &mut R,
+        local_writer: &mut L,
+        keep_alive: KeepAlive,
+    ) -> Result<Forwarded>
+    where
+        R: AsyncBufRead + Unpin,
+        L: AsyncWrite + Unpin,
+    {
+        let address = match destinations.resolve(&mut header, &self.remote_address) {
+            Ok(address) => address,
+            Err(e) => return self.forward_error(&e, local_writer, header.version).await,
+        };
+
+        // The remote server is trusted, others are connected to at the addresses
+        // that were checked, as resolving the name again could give different ones
+        let resolved = if address == self.remote_address {
+            None
+        } else {
+            let resolved = match resolve(&address).await {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    let e = upstream_connect_error(&address, e);
+                    return self.forward_error(&e, local_writer, header.version).await;
+                }
+            };
+
+            if let Err(e) = destinations.check_resolved(&address, &resolved) {
+                return self.forward_error(&e, local_writer, header.version).await;
+            }
+
+            Some(resolved)
+        };
+
+        self.forward_to_async(
+            &address,
+            resolved.as_deref(),
+            header,
+            local_reader,
+            local_writer,
+            keep_alive,
+        )
+        .await
+    }
+
+    // Async equivalent of `Forwarder::connect`
+    async fn connect_async(
+        &self,
+        remote_address: &Address,
+        resolved: Option<&[SocketAddr]>,
+    ) -> Result<TcpStream> {
+        match resolved {
+            Some(resolved) => {
+                connect_resolved(remote_address, resolved, self.timeout_seconds).await
+            }
+            None => connect_remote(remote_address, self.timeout_seconds).await,
+        }
+    }
+
+    async fn forward_to_async<R, L>(
+        &self,
+        remote_address: &Address,
+        resolved: Option<&[SocketAddr]>,
+        header: RequestHeader,
+        local_reader: &mut R,
+        local_writer: &mut L,
+        keep_alive: KeepAlive,
+    ) -> Result<Forwarded>
+    where
+        R: AsyncBufRead + Unpin,
+        L: AsyncWrite + Unpin,
+    {
+        let body_type = match header.body_type() {
+            Ok(body_type) => body_type,
+            Err(e) => return self.forward_error(&e, local_writer, header.version).await,
+        };
+
+        let reuse_local = header.is_keep_alive() && keep_alive.remaining_requests > 1;
+
+        // The body is streamed, so only the header is held across awaits
+        let (mut request_header, request_version) = {
+            let mut request = Request::new(header, Body::Empty);
+            let request_version = prepare_request(&mut request);
+
+            (request.header, request_version)
+        };
+
+        // Framed for the version sent upstream, which can always carry a chunked body
+        let chunked = match &body_type {
+            Some(body_type) => {
+                let upstream_version = request_header.version;
+
+                request_header.frame_body(body_type.content_length(), upstream_version)
+            }
+            None => false,
+        };
+
+        let checkout = match resolved {
+            Some(resolved) => {
+                self.pool
+                    .checkout_resolved_async(remote_address, resolved, self.timeout_seconds)
+                    .await
+            }
+            None => {
+                self.pool
+                    .checkout_async(remote_address, self.timeout_seconds)
+                    .await
+            }
+        };
+
+        let mut connection = match checkout {
+            Ok(connection) => connection,
+            Err(e) => return self.forward_error(&e, local_writer, request_version).await,
+        };
+
+        loop {
+            let (remote_read, remote_write) = connection.stream.split();
+            let mut remote_reader = BufReader::new(remote_read);
+            let mut remote_writer = BufWriter::new(remote_write);
+            let sent = Instant::now();
+
+            let exchanged = self
+                .exchange_header(
+                    &mut remote_reader,
+                    &mut remote_writer,
+                    &request_header,
+                    body_type.as_ref().map(|b| (b, chunked, &mut *local_reader)),
+                    local_writer,
+                    request_version,
+                )
+                .await;
+
+            let response_header = match exchanged {
+                Ok(response_header) => response_header,
+                // A pooled connection may have been closed by the remote server while it was
+                // idle, in which case requests that are safe to repeat are sent on a new one
+                Err(TcpIpError::UpstreamClosed)
+                    if connection.reused
+                        && body_type.is_none()
+                        && request_header.method.is_idempotent() =>
+                {
+                    drop(remote_reader);
+                    drop(remote_writer);
+
+                    connection = match self.connect_async(remote_address, resolved).await {
+                        Ok(stream) => PooledConnection {
+                            stream,
+                            reused: false,
+                        },
+                        Err(e) => {
+                            return self.forward_error(&e, local_writer, request_version).await
+                        }
+                    };
+
+                    continue;
+                }
+                Err(e) => return self.forward_error(&e, local_writer, request_version).await,
+            };
+
+            let upstream_latency = sent.elapsed();
+            let local_keep_alive = if reuse_local { Some(keep_alive) } else { None };
+
+            let relayed = self
+                .relay_response_async(
+                    &request_header,
+                    response_header,
+                    &mut remote_reader,
+                    local_writer,
+                    request_version,
+                    local_keep_alive,
+                )
+                .await?;
+
+            let reusable = relayed.remote_keep_alive && remote_reader.buffer().is_empty();
+
+            drop(remote_reader);
+            drop(remote_writer);
+
+            if reusable {
+                self.pool.checkin(remote_address, connection.stream);
+            }
+
+            return Ok(Forwarded {
+                status_code: relayed.status_code,
+                upstream_latency: Some(upstream_latency),
+                keep_alive: relayed.local_keep_alive,
+                error: None,
+            });
+        }
+    }
+
+    /// Async equivalent of `Forwarder::replay_request`, reading the body of the
+    /// request with `header` from `local_reader`.
+    pub async fn replay_request_async<R, L>(
+        &self,
+        replay: &Replay,
+        header: RequestHeader,
+        local_reader: &mut R,
+        local_writer: &mut L,
+        keep_alive: KeepAlive,
+    ) -> Result<Forwarded>
+    where
+        R: AsyncBufRead + Unpin,
+        L: AsyncWrite + Unpin,
+    {
+        self.respond_locally_async(
+            header,
+            local_reader,
+            local_writer,
+            keep_alive,
+            |request, keep_alive| replay.respond(request, keep_alive, &self.error_page),
+        )
+        .await
+    }
+
+    /// Async equivalent of `Forwarder::route_request`, reading the body of the
+    /// request with `header` from `local_reader`.
+    ///
+    /// Handlers are called on the runtime, so they shouldn't block for long.
+    pub async fn route_request_async<R, L>(
+        &self,
+        router: &Router,
+        header: RequestHeader,
+        local_reader: &mut R,
+        local_writer: &mut L,
+        keep_alive: KeepAlive,
+    ) -> Result<Forwarded>
+    where
+        R: AsyncBufRead + Unpin,
+        L: AsyncWrite + Unpin,
+    {
+        self.respond_locally_async(
+            header,
+            local_reader,
+            local_writer,
+            keep_alive,
+            |request, keep_alive| router.respond(request, keep_alive, &self.error_page),
+        )
+        .await
+    }
+
+    // Sends the response `respond` builds for the request with `header` once
+    // its body has been read
+    async fn respond_locally_async<R, L, F>(
+        &self,
+        header: RequestHeader,
+        local_reader: &mut R,
+        local_writer: &mut L,
+        keep_alive: KeepAlive,
+        respond: F,
+    ) -> Result<Forwarded>
+    where
+        R: AsyncBufRead + Unpin,
+        L: AsyncWrite + Unpin,
+        F: FnOnce(&mut Request, Option<KeepAlive>) -> Result<Response<'static>>,
+    {
+        let request_version = header.version;
+        let body = match header.body_type() {
+            Ok(Some(body_type)) => {
+                match read_client_body(local_reader, &body_type, self.max_body_size, self.timeout())
+                    .await
+                {
+                    Ok(body) => body,
+                    Err(e) => return self.forward_error(&e, local_writer, request_version).await,
+                }
+            }
+            Ok(None) => Vec::new(),
+            Err(e) => return self.forward_error(&e, local_writer, request_version).await,
+        };
+
+        let local_keep_alive = if header.is_keep_alive() && keep_alive.remaining_requests > 1 {
+            Some(keep_alive)
+        } else {
+            None
+        };
+
+        // Requests and responses can't be held across awaits
+        let responded = {
+            let mut request = Request::new(header, Body::from(body));
+
+            respond(&mut request, local_keep_alive).and_then(|mut response| {
+                let mut bytes = Vec::new();
+
+                response.write_to(&mut bytes, request_version)?;
+
+                let forwarded = Forwarded {
+                    status_code: response.header.status_code,
+                    upstream_latency: None,
+                    keep_alive: !response.header.has_connection_option("close"),
+                    error: None,
+                };
+
+                Ok((bytes, forwarded))
+            })
+        };
+
+        let (response, forwarded) = match responded {
+            Ok(responded) => responded,
+            Err(e) => return self.forward_error(&e, local_writer, request_version).await,
+        };
+
+        timed(self.timeout(), local_writer.write_all(&response)).await?;
+        timed(self.timeout(), local_writer.flush()).await?;
+
+        Ok(forwarded)
+    }
+
+    async fn forward_error<L: AsyncWrite + Unpin>(
+        &self,
+        e: &TcpIpError,
+        local_writer: &mut L,
+        request_version: HttpVersion,
+    ) -> Result<Forwarded> {
+        self.write_error_response(e, local_writer, request_version)
+            .await?;
+
+        Ok(Forwarded::failed(e))
+    }
+
+    // Sends the request header and streams the body from the local connection, then
+    // reads the header of the final response, relaying any interim 1xx responses.
+    // Errors are tagged with the side they happened on like `exchange_header`.
+    async fn exchange_header<R, W, B, L>(
+        &self,
+        remote_reader: &mut R,
+        remote_writer: &mut W,
+        request_header: &RequestHeader,
+        body: Option<(&BodyType, bool, &mut B)>,
+        local_writer: &mut L,
+        request_version: HttpVersion,
+    ) -> Result<ResponseHeader>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+        B: AsyncBufRead + Unpin,
+        L: AsyncWrite + Unpin,
+    {
+        let sent = async {
+            timed(
+                self.timeout(),
+                remote_writer.write_all(&request_header.to_bytes()?),
+            )
+            .await?;
+
+            if let Some((body_type, chunked, local_reader)) = body {
+                copy_client_body(
+                    local_reader,
+                    body_type,
+                    remote_writer,
+                    chunked,
+                    self.timeout(),
+                )
+                .await?;
+            }
+
+            timed(self.timeout(), remote_writer.flush()).await?;
+
+            Ok(())
+        };
+
+        sent.await.map_err(|e| upstream_error(e, false))?;
+
+        let mut responded = false;
+
+        loop {
+            let mut response_header = timed(
+                self.timeout(),
+                read_header::<ResponseHeader, _>(remote_reader, DEFAULT_MAX_HEADER_SIZE),
+            )
+            .await
+            .map_err(|e| upstream_error(e, responded))?;
+
+            if !response_header.is_informational() || response_header.status_code == 101 {
+                return Ok(response_header);
+            }
+
+            responded = true;
+
+            if request_version >= HttpVersion::Http11 {
+                response_header.strip_hop_by_hop();
+                response_header.version = HttpVersion::Http11;
+
+                let bytes = response_header.to_bytes()?;
+                let relayed = async {
+                    timed(self.timeout(), local_writer.write_all(&bytes)).await?;
+                    timed(self.timeout(), local_writer.flush()).await
+                };
+
+                relayed.await.map_err(client_error)?;
+            }
+        }
+    }
+
+    /// Async equivalent of `Forwarder::relay_response`.
+    pub async fn relay_response_async<R, L>(
+        &self,
+        request_header: &RequestHeader,
+        mut response_header: ResponseHeader,
+        remote_reader: &mut R,
+        local_writer: &mut L,
+        request_version: HttpVersion,
+        keep_alive: Option<KeepAlive>,
+    ) -> Result<Relayed>
+    where
+        R: AsyncBufRead + Unpin,
+        L: AsyncWrite + Unpin,
+    {
+        let body_type = match response_header.body_type_for(&request_header.method) {
+            Ok(body_type) => body_type,
+            Err(e) => {
+                let e = e.into_upstream();
+
+                self.write_error_response(&e, local_writer, request_version)
+                    .await?;
+
+                return Ok(Relayed {
+                    status_code: e.to_status() as u16,
+                    remote_keep_alive: false,
+                    local_keep_alive: false,
+                });
+            }
+        };
+
+        // The connection can only be reused once the whole response has been read
+        // and if the remote server has not closed it to end the body
+        let remote_keep_alive =
+            response_header.is_keep_alive() && body_type != Some(BodyType::UntilClose);
+
+        response_header.strip_hop_by_hop();
+        response_header.version = HttpVersion::Http11;
+
+        set_connection_headers(&mut response_header, keep_alive);
+
+        let chunked = match &body_type {
+            Some(body_type) => {
+                response_header.frame_body(body_type.content_length(), request_version)
+            }
+            None => false,
+        };
+
+        timed(
+            self.timeout(),
+            local_writer.write_all(&response_header.to_bytes()?),
+        )
+        .await?;
+
+        if let Some(body_type) = &body_type {
+            copy_body(
+                remote_reader,
+                body_type,
+                local_writer,
+                chunked,
+                self.timeout(),
+            )
+            .await?;
+        }
+
+        timed(self.timeout(), local_writer.flush()).await?;
+
+        // A body without a known length may also have been delimited by closing the connection
+        let local_keep_alive = !response_header.has_connection_option("close");
+
+        Ok(Relayed {
+            status_code: response_header.status_code,
+            remote_keep_alive,
+            local_keep_alive,
+        })
+    }
+
+    /// Async equivalent of sending the error page for `e`, after which the local
+    /// connection is always closed.
+    pub(crate) async fn write_error_response<L: AsyncWrite + Unpin>(
+        &self,
+        e: &TcpIpError,
+        local_writer: &mut L,
+        request_version: HttpVersion,
+    ) -> Result<()> {
+        let mut response = Vec::new();
+
+        self.send_error_response(e, &mut response, request_version)?;
+
+        timed(self.timeout(), local_writer.write_all(&response)).await?;
+        timed(self.timeout(), local_writer.flush()).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::address::Address;
+    use crate::async_io::block_on;
+    use crate::error_page::ErrorPage;
+    use crate::header_item::HeaderItem;
+    use crate::request::request_header::RequestHeader;
+    use crate::stream_helper::{ConnectionPool, Forwarder, KeepAlive, DEFAULT_MAX_BODY_SIZE};
+
+    fn forwarder(remote_address: Address) -> Forwarder<tokio::net::TcpStream> {
+        Forwarder {
+            name: "test".to_owned(),
+            remote_address,
+            timeout_seconds: 4,
+            pool: ConnectionPool::default(),
+            error_page: ErrorPage::new("text/plain", "{status_code} {reason_phrase}"),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    const KEEP_ALIVE: KeepAlive = KeepAlive {
+        timeout_seconds: 4,
+        remaining_requests: 10,
+    };
+
+    #[test]
+    fn test_forward_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+        let address = Address::from(listener.local_addr().expect("Missing local address"));
+
+        // Echoes the body of each request as a chunked response on one connection
+        let remote = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("Failed to accept");
+            let mut reader = BufReader::new(&stream);
+            let mut writer = &stream;
+
+            for _ in 0..2 {
+                let mut header = String::new();
+
+                while !header.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut header).expect("Failed to read");
+                }
+
+                let mut body = [0; 5];
+                reader.read_exact(&mut body).expect("Failed to read body");
+
+                assert!(header.contains("Content-Length: 5\r\n"));
+
+                write!(
+                    writer,
+                    "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n{}\r\n0\r\n\r\n",
+                    String::from_utf8_lossy(&body)
+                )
+                .expect("Failed to write");
+            }
+        });
+
+        let forwarder = forwarder(address.clone());
+
+        block_on(async {
+            for body in &["hello", "again"] {
+                let header = RequestHeader::from_bytes(
+                    b"POST /echo HTTP/1.1\r\nConnection: keep-alive\r\nContent-Length: 5",
+                )
+                .expect("Failed to parse header");
+
+                let mut local_reader = tokio::io::BufReader::new(body.as_bytes());
+                let mut local_writer = Vec::new();
+
+                let forwarded = forwarder
+                    .forward_request_async(header, &mut local_reader, &mut local_writer, KEEP_ALIVE)
+                    .await
+                    .expect("Failed to forward request");
+
+                assert_eq!(forwarded.status_code, 200);
+                assert!(forwarded.upstream_latency.is_some());
+                assert!(forwarded.keep_alive);
+                assert_eq!(
+                    String::from_utf8_lossy(&local_writer),
+                    format!("HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nKeep-Alive: timeout=4, max=9\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n{}\r\n0\r\n\r\n", body)
+                );
+            }
+        });
+
+        remote.join().expect("Remote server failed");
+
+        assert_eq!(forwarder.pool.idle_count(&address), 1);
+    }
+
+    #[test]
+    fn test_bad_gateway() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+        let address = Address::from(listener.local_addr().expect("Missing local address"));
+
+        drop(listener);
+
+        let header = RequestHeader::from_bytes(b"GET / HTTP/1.1").expect("Failed to parse header");
+        let mut local_writer = Vec::new();
+
+        let forwarded = block_on(forwarder(address).forward_request_async(
+            header,
+            &mut tokio::io::BufReader::new(&b""[..]),
+            &mut local_writer,
+            KEEP_ALIVE,
+        ))
+        .expect("Failed to send error response");
+
+        assert_eq!(forwarded.status_code, 502);
+        assert!(!forwarded.keep_alive);
+        assert!(String::from_utf8_lossy(&local_writer).starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+    }
+}
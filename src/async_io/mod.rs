@@ -0,0 +1,280 @@
+//! Async equivalents of the header reader, forwarder and server over tokio's
+//! `AsyncBufRead` and `AsyncWrite`, enabled with the `tokio` feature.
+//!
+//! Messages are parsed with the same `HeaderItem` implementations as the
+//! blocking API, only reading and writing the bytes is async.
+
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{lookup_host, TcpStream};
+
+use crate::access_log::{CountingReader, CountingWriter};
+use crate::address::Address;
+use crate::error::TcpIpError;
+use crate::header_item::HeaderItem;
+use crate::header_parser::{HeaderParser, ParseStatus};
+use crate::stream_helper::upstream_connect_error;
+use crate::Result;
+
+pub mod body;
+pub mod forwarder;
+pub mod server;
+
+/// Async equivalent of `HeaderItem::from_reader_with_limit`.
+///
+/// There are no socket timeouts in tokio, so the caller should limit how long
+/// this may take with `tokio::time::timeout`.
+pub async fn read_header<H, R>(reader: &mut R, max_header_size: usize) -> Result<H>
+where
+    H: HeaderItem,
+    R: AsyncBufRead + Unpin,
+{
+    let mut parser = HeaderParser::new(max_header_size);
+
+    loop {
+        let data = match reader.fill_buf().await {
+            Ok(data) => data,
+            // The peer went silent part way through sending the header
+            Err(e) if !parser.is_empty() => {
+                return Err(TcpIpError::IncompleteHeader {
+                    received: parser.len(),
+                    source: e,
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if data.is_empty() {
+            return if parser.is_empty() {
+                Err(TcpIpError::ConnectionClosed)
+            } else {
+                Err(TcpIpError::IncompleteHeader {
+                    received: parser.len(),
+                    source: ErrorKind::UnexpectedEof.into(),
+                })
+            };
+        }
+
+        let len = data.len();
+
+        match parser.feed(data)? {
+            ParseStatus::Partial => reader.consume(len),
+            ParseStatus::Complete(consumed) => {
+                reader.consume(consumed);
+
+                return H::from_bytes(parser.header_bytes());
+            }
+        }
+    }
+}
+
+/// Async equivalent of `stream_helper::connect_remote`.
+pub async fn connect_remote(address: &Address, timeout_seconds: u64) -> Result<TcpStream> {
+    let resolved = resolve(address)
+        .await
+        .map_err(|e| upstream_connect_error(address, e))?;
+
+    connect_resolved(address, &resolved, timeout_seconds).await
+}
+
+/// Async equivalent of `stream_helper::connect_resolved`.
+pub async fn connect_resolved(
+    address: &Address,
+    resolved: &[SocketAddr],
+    timeout_seconds: u64,
+) -> Result<TcpStream> {
+    let timeout = Some(Duration::from_secs(timeout_seconds));
+    let mut last_error = None;
+
+    for socket_address in resolved {
+        match timed(timeout, TcpStream::connect(socket_address)).await {
+            Ok(remote_server) => {
+                remote_server.set_nodelay(true)?;
+
+                return Ok(remote_server);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(upstream_connect_error(
+        address,
+        last_error.unwrap_or_else(|| ErrorKind::NotFound.into()),
+    ))
+}
+
+/// Async equivalent of `Address::resolve`, which doesn't block the runtime
+/// while a host name is looked up.
+pub async fn resolve(address: &Address) -> io::Result<Vec<SocketAddr>> {
+    let addresses = lookup_host((address.host.as_str(), address.port))
+        .await?
+        .collect::<Vec<_>>();
+
+    if addresses.is_empty() {
+        Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("No addresses found for '{}'", address.host),
+        ))
+    } else {
+        Ok(addresses)
+    }
+}
+
+// Runs an I/O operation, failing with `ErrorKind::TimedOut` if it takes longer
+// than `timeout`. This stands in for the socket timeouts of the blocking API.
+pub(crate) async fn timed<T, E, F>(timeout: Option<Duration>, f: F) -> std::result::Result<T, E>
+where
+    E: From<io::Error>,
+    F: Future<Output = std::result::Result<T, E>>,
+{
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, f)
+            .await
+            .unwrap_or_else(|_| Err(io::Error::from(ErrorKind::TimedOut).into())),
+        None => f.await,
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let polled = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = &buf.filled()[filled..];
+
+        self.count += read.len() as u64;
+
+        if let Some(recording) = &mut self.recording {
+            recording.push(read);
+        }
+
+        polled
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncBufRead for CountingReader<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        let polled = Pin::new(&mut this.inner).poll_fill_buf(cx);
+
+        if let (Poll::Ready(Ok(buffered)), Some(recording)) = (&polled, &mut this.recording) {
+            recording.buffered(buffered);
+        }
+
+        polled
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amount: usize) {
+        Pin::new(&mut self.inner).consume(amount);
+        self.count += amount as u64;
+
+        if let Some(recording) = &mut self.recording {
+            recording.consumed(amount);
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CountingWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let polled = Pin::new(&mut self.inner).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(written)) = polled {
+            self.count += written as u64;
+
+            if let Some(recording) = &mut self.recording {
+                recording.push(&buf[..written]);
+            }
+        }
+
+        polled
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn block_on<F: Future>(f: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to build runtime")
+        .block_on(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, BufReader};
+
+    use crate::address::Address;
+    use crate::async_io::{block_on, connect_remote, read_header};
+    use crate::error::TcpIpError;
+    use crate::request::request_header::RequestHeader;
+    use crate::request::request_method::RequestMethod;
+
+    #[test]
+    fn test_read_header_split_across_reads() {
+        let raw = b"\r\nGET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\nbody".to_vec();
+
+        block_on(async {
+            let mut reader = BufReader::with_capacity(4, raw.as_slice());
+
+            let header = read_header::<RequestHeader, _>(&mut reader, 1024)
+                .await
+                .expect("Failed to read header");
+
+            assert_eq!(header.method, RequestMethod::Get);
+            assert_eq!(header.uri, "/index.html");
+
+            let mut rest = Vec::new();
+            reader.read_to_end(&mut rest).await.expect("Failed to read");
+
+            assert_eq!(rest, b"body");
+
+            let e = read_header::<RequestHeader, _>(&mut BufReader::new(&raw[..10]), 1024)
+                .await
+                .expect_err("Read an incomplete header");
+
+            assert!(matches!(
+                e,
+                TcpIpError::IncompleteHeader { received: 8, .. }
+            ));
+
+            let e = read_header::<RequestHeader, _>(&mut BufReader::new(&raw[..]), 16)
+                .await
+                .expect_err("Read a header over the limit");
+
+            assert!(matches!(e, TcpIpError::HeaderTooLarge { limit: 16 }));
+        });
+    }
+
+    #[test]
+    fn test_connect_remote_refused() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+        let address = Address::from(listener.local_addr().expect("Missing local address"));
+
+        drop(listener);
+
+        let e = block_on(connect_remote(&address, 1)).expect_err("Connected to a closed port");
+
+        assert!(matches!(e, TcpIpError::UpstreamConnect { .. }));
+    }
+}
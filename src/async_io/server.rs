@@ -0,0 +1,706 @@
+use std::future::pending;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncBufRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+use tokio::task::{JoinError, JoinHandle, JoinSet};
+
+use crate::access_log::{AccessRecord, CountingReader, CountingWriter};
+use crate::async_io::{read_header, resolve, timed};
+use crate::config::Server;
+use crate::error::TcpIpError;
+use crate::forward_proxy::Destinations;
+use crate::http_version::HttpVersion;
+use crate::replay::Replay;
+use crate::request::request_header::RequestHeader;
+use crate::router::Router;
+use crate::server::{
+    forward_proxy_destinations, new_forwarder, open_replay, print_started, Journal, OverloadPolicy,
+};
+use crate::stream_helper::{Forwarder, KeepAlive};
+use crate::Result;
+
+impl Server {
+    /// Async equivalent of `Server::start`, serving each connection on a task
+    /// of the current tokio runtime.
+    ///
+    /// At most `max_connections` connections are served at once and up to
+    /// `accept_backlog` more wait for one to finish, before the overload
+    /// policy applies.
+    pub async fn start_async(self) -> Result<AsyncServerHandle> {
+        self.spawn_async(None).await
+    }
+
+    /// Async equivalent of `Server::serve`, answering requests with the
+    /// handlers of `router` on the tasks serving their connections.
+    pub async fn serve_async(self, router: Router) -> Result<AsyncServerHandle> {
+        router.check()?;
+
+        self.spawn_async(Some(Arc::new(router))).await
+    }
+
+    async fn spawn_async(self, router: Option<Arc<Router>>) -> Result<AsyncServerHandle> {
+        let addresses = resolve(&self.listen_address).await?;
+        let listener = TcpListener::bind(addresses.as_slice()).await?;
+        let local_address = listener.local_addr()?;
+
+        print_started(&self, router.as_deref(), local_address);
+
+        let (stop, stopped) = watch::channel(false);
+        let proxy = Arc::new(AsyncProxy::new(&self, router)?);
+
+        let accept_task = tokio::spawn(accept_loop(listener, proxy, stopped));
+
+        Ok(AsyncServerHandle {
+            local_address,
+            stop,
+            accept_task,
+        })
+    }
+}
+
+// Async equivalent of `Proxy`, with the state shared by every connection of a server
+struct AsyncProxy {
+    forwarder: Forwarder<TcpStream>,
+    max_header_size: usize,
+    max_requests: usize,
+    max_connections: usize,
+    overload: OverloadPolicy,
+    journal: Journal,
+    replay: Option<Replay>,
+    destinations: Option<Destinations>,
+    router: Option<Arc<Router>>,
+    /// Permits to serve a connection.
+    serving: Arc<Semaphore>,
+    /// Permits to accept a connection, either to serve it or to wait for a
+    /// serving permit.
+    accepted: Arc<Semaphore>,
+}
+
+impl AsyncProxy {
+    fn new(server: &Server, router: Option<Arc<Router>>) -> Result<Self> {
+        Ok(Self {
+            forwarder: new_forwarder(server),
+            max_header_size: server.max_header_size,
+            max_requests: server.max_requests,
+            max_connections: server.max_connections,
+            overload: server.overload,
+            journal: Journal::open(server)?,
+            replay: open_replay(server)?,
+            destinations: forward_proxy_destinations(server),
+            router,
+            serving: Arc::new(Semaphore::new(server.max_connections)),
+            accepted: Arc::new(Semaphore::new(
+                server.max_connections + server.accept_backlog,
+            )),
+        })
+    }
+
+    // Tells the client the server is too busy, without reading its request
+    async fn reject(&self, mut stream: TcpStream) {
+        let e = TcpIpError::Overloaded {
+            max_connections: self.max_connections,
+        };
+
+        if self
+            .forwarder
+            .write_error_response(&e, &mut stream, HttpVersion::Http11)
+            .await
+            .is_ok()
+        {
+            let _ = stream.shutdown().await;
+        }
+    }
+
+    async fn handle_connection(
+        self: Arc<Self>,
+        mut stream: TcpStream,
+        accepted: OwnedSemaphorePermit,
+        mut stopped: watch::Receiver<bool>,
+    ) {
+        let serving = tokio::select! {
+            permit = self.serving.clone().acquire_owned() => permit,
+            // Connections still waiting for a permit are closed when the server stops
+            _ = wait_stopped(&mut stopped) => return,
+        };
+
+        if let Err(e) = stream.set_nodelay(true) {
+            eprintln!("{}", e);
+            return;
+        }
+
+        let client_address = stream.peer_addr().ok();
+
+        let (local_read, local_write) = stream.split();
+        let mut local_reader = BufReader::new(local_read);
+        let mut local_writer = BufWriter::new(local_write);
+
+        self.serve(
+            &mut local_reader,
+            &mut local_writer,
+            &mut stopped,
+            client_address,
+        )
+        .await;
+
+        // Let the client see the end of the last response before the socket is closed
+        let _ = local_writer.shutdown().await;
+
+        drop(serving);
+        drop(accepted);
+    }
+
+    // Forwards requests read from a local connection until it should be closed
+    async fn serve<R, W>(
+        &self,
+        local_reader: &mut R,
+        local_writer: &mut W,
+        stopped: &mut watch::Receiver<bool>,
+        client_address: Option<SocketAddr>,
+    ) where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let timeout = Some(Duration::from_secs(self.forwarder.timeout_seconds));
+
+        let mut local_reader = CountingReader::new(local_reader);
+        let mut local_writer = CountingWriter::new(local_writer);
+
+        for served in 0..self.max_requests.max(1) {
+            let counted = self.journal.start(&mut local_reader, &mut local_writer);
+
+            // Stopping only interrupts connections waiting for a request
+            let read = tokio::select! {
+                biased;
+                _ = wait_stopped(stopped) => break,
+                read = timed(
+                    timeout,
+                    read_header::<RequestHeader, _>(&mut local_reader, self.max_header_size),
+                ) => read,
+            };
+
+            let header = match read {
+                Ok(header) => header,
+                Err(TcpIpError::ConnectionClosed) => break,
+                // The client kept the connection open without sending another request
+                Err(e @ TcpIpError::Io(_)) if e.is_timeout() => break,
+                Err(e) => {
+                    let e = if e.is_timeout() {
+                        TcpIpError::ClientTimeout
+                    } else {
+                        e
+                    };
+
+                    let mut record = AccessRecord::new(&self.forwarder.name, client_address);
+                    record.status_code = e.to_status() as u16;
+                    record.error = Some(e.to_string());
+
+                    let _ = self
+                        .forwarder
+                        .write_error_response(&e, &mut local_writer, HttpVersion::Http11)
+                        .await;
+
+                    self.journal.finish(
+                        record,
+                        counted,
+                        Instant::now(),
+                        &mut local_reader,
+                        &mut local_writer,
+                    );
+                    break;
+                }
+            };
+
+            let started = Instant::now();
+            let mut record = AccessRecord::new(&self.forwarder.name, client_address);
+            record.set_request(&header);
+
+            let keep_alive = KeepAlive {
+                timeout_seconds: self.forwarder.timeout_seconds,
+                remaining_requests: self.max_requests.saturating_sub(served),
+            };
+
+            let forwarded = match (&self.router, &self.replay, &self.destinations) {
+                (Some(router), _, _) => {
+                    self.forwarder
+                        .route_request_async(
+                            router,
+                            header,
+                            &mut local_reader,
+                            &mut local_writer,
+                            keep_alive,
+                        )
+                        .await
+                }
+                (None, Some(replay), _) => {
+                    self.forwarder
+                        .replay_request_async(
+                            replay,
+                            header,
+                            &mut local_reader,
+                            &mut local_writer,
+                            keep_alive,
+                        )
+                        .await
+                }
+                (None, None, Some(destinations)) => {
+                    self.forwarder
+                        .proxy_request_async(
+                            destinations,
+                            header,
+                            &mut local_reader,
+                            &mut local_writer,
+                            keep_alive,
+                        )
+                        .await
+                }
+                (None, None, None) => {
+                    self.forwarder
+                        .forward_request_async(
+                            header,
+                            &mut local_reader,
+                            &mut local_writer,
+                            keep_alive,
+                        )
+                        .await
+                }
+            };
+
+            let keep_alive = match forwarded {
+                Ok(forwarded) => {
+                    record.status_code = forwarded.status_code;
+                    record.upstream_latency = forwarded.upstream_latency;
+                    record.error = forwarded.error;
+                    forwarded.keep_alive
+                }
+                // Part of the response may have been sent so the connection can't be reused
+                Err(e) => {
+                    record.status_code = e.to_status() as u16;
+                    record.error = Some(e.to_string());
+                    false
+                }
+            };
+
+            self.journal.finish(
+                record,
+                counted,
+                started,
+                &mut local_reader,
+                &mut local_writer,
+            );
+
+            if !keep_alive {
+                break;
+            }
+        }
+    }
+}
+
+// Completes once the server is stopped, which is never if the handle was dropped
+async fn wait_stopped(stopped: &mut watch::Receiver<bool>) {
+    if stopped.wait_for(|stopping| *stopping).await.is_err() {
+        pending::<()>().await;
+    }
+}
+
+// Accepts connections until the server is stopped, returning the tasks of
+// the connections that are still open
+async fn accept_loop(
+    listener: TcpListener,
+    proxy: Arc<AsyncProxy>,
+    mut stopped: watch::Receiver<bool>,
+) -> JoinSet<()> {
+    let mut connections = JoinSet::new();
+
+    loop {
+        // Waiting for a permit leaves new connections in the OS listen queue
+        let queued = match proxy.overload {
+            OverloadPolicy::Queue => tokio::select! {
+                permit = proxy.accepted.clone().acquire_owned() => permit.ok(),
+                _ = wait_stopped(&mut stopped) => break,
+            },
+            _ => None,
+        };
+
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            },
+            _ = wait_stopped(&mut stopped) => break,
+        };
+
+        let accepted = match queued {
+            Some(permit) => permit,
+            None => match proxy.accepted.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    if proxy.overload == OverloadPolicy::Reject {
+                        let proxy = proxy.clone();
+
+                        connections.spawn(async move { proxy.reject(stream).await });
+                    }
+
+                    // Dropping the stream closes the connection
+                    continue;
+                }
+            },
+        };
+
+        connections.spawn(
+            proxy
+                .clone()
+                .handle_connection(stream, accepted, stopped.clone()),
+        );
+
+        // Forget connections that have closed
+        while connections.try_join_next().is_some() {}
+    }
+
+    connections
+}
+
+/// A server running on a tokio runtime, returned by `Server::start_async`.
+///
+/// Dropping the handle leaves the server running until the runtime shuts down.
+pub struct AsyncServerHandle {
+    local_address: SocketAddr,
+    stop: watch::Sender<bool>,
+    accept_task: JoinHandle<JoinSet<()>>,
+}
+
+impl AsyncServerHandle {
+    /// The address the server is listening on, which has the port chosen by
+    /// the OS if the server was configured with port 0.
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    /// Async equivalent of `ServerHandle::shutdown`.
+    pub async fn shutdown(self) {
+        self.stop(None).await;
+    }
+
+    /// Async equivalent of `ServerHandle::shutdown_timeout`, returning the
+    /// number of connections that were closed before their request finished.
+    pub async fn shutdown_timeout(self, timeout: Duration) -> usize {
+        self.stop(Some(timeout)).await
+    }
+
+    /// Waits while the server runs, which is until the runtime shuts down as
+    /// the handle is needed to stop it. Fails if the accept task panicked.
+    pub async fn join(self) -> std::result::Result<(), JoinError> {
+        let Self {
+            stop, accept_task, ..
+        } = self;
+
+        let joined = accept_task.await.map(drop);
+
+        drop(stop);
+
+        joined
+    }
+
+    async fn stop(self, timeout: Option<Duration>) -> usize {
+        let _ = self.stop.send(true);
+
+        let mut connections = match self.accept_task.await {
+            Ok(connections) => connections,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 0;
+            }
+        };
+
+        println!(
+            "Proxy service at 'http://{}' stopped accepting connections.\n",
+            self.local_address
+        );
+
+        let drained = timed(timeout, async {
+            while connections.join_next().await.is_some() {}
+
+            Ok::<_, std::io::Error>(())
+        })
+        .await;
+
+        if drained.is_ok() {
+            return 0;
+        }
+
+        // Aborting a task drops its stream, which closes the connection
+        let closed = connections.len();
+        connections.shutdown().await;
+
+        closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpStream;
+
+    use crate::address::Address;
+    use crate::async_io::block_on;
+    use crate::config::Server;
+    use crate::http_item::HttpItem;
+    use crate::request::Request;
+    use crate::response::ResponseBuilder;
+    use crate::router::Router;
+    use crate::server::OverloadPolicy;
+
+    // An upstream answering every request on a connection with "ok"
+    fn upstream() -> Address {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+        let address = Address::from(listener.local_addr().expect("Missing local address"));
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                thread::spawn(move || {
+                    let mut reader = std::io::BufReader::new(&stream);
+                    let mut writer = &stream;
+
+                    while let Ok(mut request) = Request::from_reader(&mut reader) {
+                        if request.buffer_body().is_err() {
+                            break;
+                        }
+
+                        let _ = writer.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+                    }
+                });
+            }
+        });
+
+        address
+    }
+
+    fn test_server() -> Server {
+        let mut server = Server::new("test", Address::new("127.0.0.1", 0), upstream());
+        server.log = false;
+        server
+    }
+
+    async fn read_response(reader: &mut BufReader<TcpStream>) -> String {
+        let mut response = String::new();
+
+        while !response.ends_with("\r\n\r\n") {
+            if reader
+                .read_line(&mut response)
+                .await
+                .expect("Failed to read")
+                == 0
+            {
+                return response;
+            }
+        }
+
+        let mut body = [0; 2];
+        reader
+            .read_exact(&mut body)
+            .await
+            .expect("Failed to read body");
+
+        response + &String::from_utf8_lossy(&body)
+    }
+
+    #[test]
+    fn test_serve_and_shutdown() {
+        block_on(async {
+            let running = test_server()
+                .start_async()
+                .await
+                .expect("Failed to start server");
+
+            let stream = TcpStream::connect(running.local_address())
+                .await
+                .expect("Failed to connect");
+            let mut reader = BufReader::new(stream);
+
+            for _ in 0..2 {
+                reader
+                    .get_mut()
+                    .write_all(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nabcde")
+                    .await
+                    .expect("Failed to write");
+
+                let response = read_response(&mut reader).await;
+
+                assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+                assert!(response.ends_with("\r\n\r\nok"));
+            }
+
+            let local_address = running.local_address();
+
+            // The idle connection is closed rather than waiting for another request
+            assert_eq!(running.shutdown_timeout(Duration::from_secs(2)).await, 0);
+
+            let mut rest = Vec::new();
+            reader.read_to_end(&mut rest).await.expect("Failed to read");
+
+            assert!(rest.is_empty());
+            assert!(TcpStream::connect(local_address).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_shutdown_timeout_closes_busy_connections() {
+        block_on(async {
+            let running = test_server()
+                .start_async()
+                .await
+                .expect("Failed to start server");
+
+            let mut stream = TcpStream::connect(running.local_address())
+                .await
+                .expect("Failed to connect");
+
+            stream
+                .write_all(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab")
+                .await
+                .expect("Failed to write");
+
+            tokio::time::sleep(Duration::from_millis(200)).await;
+
+            assert_eq!(
+                running.shutdown_timeout(Duration::from_millis(100)).await,
+                1
+            );
+        });
+    }
+
+    #[test]
+    fn test_client_stalls_during_body() {
+        let mut server = test_server();
+        server.timeout = 1;
+
+        block_on(async {
+            let running = server.start_async().await.expect("Failed to start server");
+
+            let mut stream = TcpStream::connect(running.local_address())
+                .await
+                .expect("Failed to connect");
+
+            stream
+                .write_all(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab")
+                .await
+                .expect("Failed to write");
+
+            // The client is blamed rather than the remote server
+            let response = read_response(&mut BufReader::new(stream)).await;
+
+            assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+
+            running.shutdown().await;
+        });
+    }
+
+    #[test]
+    fn test_serve_router() {
+        let router = Router::new()
+            .post("/echo/:value", |request| {
+                let body = request.body.as_bytes().unwrap_or_default();
+
+                ResponseBuilder::new()
+                    .status_code(200)
+                    .body([request.param("value").unwrap_or_default().as_bytes(), body].concat())
+                    .build()
+                    .expect("Failed to build response")
+            })
+            .get("/panic", |_| panic!("Failed to handle request"));
+
+        let mut server = test_server();
+        server.remote_address = Address::new("127.0.0.1", 9);
+
+        block_on(async {
+            let running = server
+                .serve_async(router)
+                .await
+                .expect("Failed to start server");
+
+            let stream = TcpStream::connect(running.local_address())
+                .await
+                .expect("Failed to connect");
+            let mut reader = BufReader::new(stream);
+
+            reader
+                .get_mut()
+                .write_all(
+                    b"POST /echo/o HTTP/1.1\r\nContent-Length: 1\r\n\r\nkGET /echo/o HTTP/1.1\r\n\r\n",
+                )
+                .await
+                .expect("Failed to write");
+
+            let response = read_response(&mut reader).await;
+
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.contains("Connection: keep-alive\r\n"));
+            assert!(response.ends_with("\r\n\r\nok"));
+
+            let response = read_response(&mut reader).await;
+
+            assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+            assert!(response.contains("Allow: POST\r\n"));
+
+            // A handler that panics fails its request rather than the connection task
+            let mut stream = TcpStream::connect(running.local_address())
+                .await
+                .expect("Failed to connect");
+
+            stream
+                .write_all(b"GET /panic HTTP/1.1\r\n\r\n")
+                .await
+                .expect("Failed to write");
+
+            let response = read_response(&mut BufReader::new(stream)).await;
+
+            assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+
+            running.shutdown().await;
+        });
+    }
+
+    #[test]
+    fn test_overload_reject() {
+        let mut server = test_server();
+        server.max_connections = 1;
+        server.accept_backlog = 1;
+        server.overload = OverloadPolicy::Reject;
+
+        block_on(async {
+            let running = server.start_async().await.expect("Failed to start server");
+
+            // One connection is served and one waits for it to close
+            let mut held = Vec::new();
+
+            for _ in 0..2 {
+                held.push(
+                    TcpStream::connect(running.local_address())
+                        .await
+                        .expect("Failed to connect"),
+                );
+            }
+
+            let stream = TcpStream::connect(running.local_address())
+                .await
+                .expect("Failed to connect");
+
+            let response = read_response(&mut BufReader::new(stream)).await;
+
+            assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+            running.shutdown().await;
+        });
+    }
+}
@@ -0,0 +1,393 @@
+use std::fmt::Formatter;
+use std::io::{self, BufRead, Cursor, Read, Take, Write};
+
+use crate::body_type::BodyType;
+
+// Upper bound on a single chunk-size or trailer line so a misbehaving
+// peer cannot make us buffer an unbounded amount of data
+pub(crate) const MAX_CHUNK_LINE_LENGTH: u64 = 4096;
+
+/// The body of a `Request` or `Response`.
+///
+/// Bodies parsed from a reader are not read into memory up front, instead they
+/// are decoded lazily through `Read` so they can be piped to another socket with
+/// bounded memory. Bodies created from a `Vec<u8>` are held in memory.
+#[derive(Default)]
+pub enum Body<'a> {
+    #[default]
+    Empty,
+    Buffered(Cursor<Vec<u8>>),
+    Fixed(Take<&'a mut dyn BufRead>),
+    Chunked(ChunkedReader<&'a mut dyn BufRead>),
+    UntilClose(&'a mut dyn BufRead),
+}
+
+impl<'a> Body<'a> {
+    pub fn from_reader(reader: &'a mut dyn BufRead, body_type: &BodyType) -> Self {
+        match *body_type {
+            BodyType::Fixed(content_length) => Body::Fixed(reader.take(content_length as u64)),
+            BodyType::Chunked => Body::Chunked(ChunkedReader::new(reader)),
+            BodyType::UntilClose => Body::UntilClose(reader),
+        }
+    }
+
+    /// Returns the number of bytes left to read if it is known before reading the body.
+    pub fn content_length(&self) -> Option<u64> {
+        match self {
+            Body::Empty => Some(0),
+            Body::Buffered(cursor) => {
+                Some((cursor.get_ref().len() as u64).saturating_sub(cursor.position()))
+            }
+            Body::Fixed(take) => Some(take.limit()),
+            Body::Chunked(_) | Body::UntilClose(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Body::Empty)
+    }
+
+    /// Returns true if the body can be sent again after `rewind`.
+    pub fn is_replayable(&self) -> bool {
+        matches!(self, Body::Empty | Body::Buffered(_))
+    }
+
+    /// Moves back to the start of a body held in memory so it can be sent again.
+    pub fn rewind(&mut self) {
+        if let Body::Buffered(cursor) = self {
+            cursor.set_position(0);
+        }
+    }
+
+    /// Returns the body bytes if the body is held in memory.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Buffered(cursor) => Some(cursor.get_ref()),
+            _ => None,
+        }
+    }
+
+    /// Reads the remainder of a streamed body into memory.
+    pub fn buffer(&mut self) -> io::Result<()> {
+        if let Body::Fixed(_) | Body::Chunked(_) | Body::UntilClose(_) = self {
+            let mut data = Vec::new();
+
+            self.read_to_end(&mut data)?;
+
+            *self = Body::Buffered(Cursor::new(data));
+        }
+
+        Ok(())
+    }
+
+    /// Copies the body to `writer`, encoding it as chunks if `chunked` is set.
+    pub fn write_to<W: Write>(&mut self, writer: &mut W, chunked: bool) -> io::Result<u64> {
+        if !chunked {
+            io::copy(self, writer)
+        } else {
+            let mut chunked_writer = ChunkedWriter::new(writer);
+
+            let written = io::copy(self, &mut chunked_writer)?;
+
+            chunked_writer.finish()?;
+
+            Ok(written)
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body<'_> {
+    fn from(data: Vec<u8>) -> Self {
+        Body::Buffered(Cursor::new(data))
+    }
+}
+
+impl Read for Body<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Body::Empty => Ok(0),
+            Body::Buffered(cursor) => cursor.read(buf),
+            Body::Fixed(take) => {
+                let limit = take.limit();
+                let read = take.read(buf)?;
+
+                if read == 0 && limit > 0 && !buf.is_empty() {
+                    Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Connection closed before the end of the body",
+                    ))
+                } else {
+                    Ok(read)
+                }
+            }
+            Body::Chunked(chunked) => chunked.read(buf),
+            Body::UntilClose(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl std::fmt::Debug for Body<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Body::Empty => write!(f, "Empty"),
+            Body::Buffered(cursor) => write!(f, "Buffered({} bytes)", cursor.get_ref().len()),
+            Body::Fixed(take) => write!(f, "Fixed({} bytes remaining)", take.limit()),
+            Body::Chunked(_) => write!(f, "Chunked"),
+            Body::UntilClose(_) => write!(f, "UntilClose"),
+        }
+    }
+}
+
+/// Decodes a `Transfer-Encoding: chunked` body as it is read.
+pub struct ChunkedReader<R> {
+    inner: R,
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            remaining: 0,
+            done: false,
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = Vec::new();
+
+        (&mut self.inner)
+            .take(MAX_CHUNK_LINE_LENGTH)
+            .read_until(b'\n', &mut line)?;
+
+        strip_chunk_line_ending(&mut line)?;
+
+        Ok(line)
+    }
+
+    fn read_chunk_size(&mut self) -> io::Result<u64> {
+        parse_chunk_size(&self.read_line()?)
+    }
+}
+
+// Removes the line ending from a line read from a chunked body, failing if
+// the line was cut short by the length limit or the end of the stream
+pub(crate) fn strip_chunk_line_ending(line: &mut Vec<u8>) -> io::Result<()> {
+    if line.last() != Some(&b'\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid or truncated chunk line",
+        ));
+    }
+
+    line.pop();
+
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+
+    Ok(())
+}
+
+pub(crate) fn parse_chunk_size(line: &[u8]) -> io::Result<u64> {
+    // Ignore any chunk extensions after ';'
+    let size = line.split(|b| *b == b';').next().unwrap_or_default();
+
+    u64::from_str_radix(String::from_utf8_lossy(size).trim(), 16).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid chunk size - {}", e),
+        )
+    })
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.remaining == 0 {
+            self.remaining = self.read_chunk_size()?;
+
+            // 0 indicates the end of the chunks, followed by optional trailers
+            if self.remaining == 0 {
+                while !self.read_line()?.is_empty() {}
+
+                self.done = true;
+
+                return Ok(0);
+            }
+        }
+
+        let max = buf.len().min(self.remaining as usize);
+
+        let read = self.inner.read(&mut buf[..max])?;
+
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed before the end of the chunk",
+            ));
+        }
+
+        self.remaining -= read as u64;
+
+        // consume \r\n bytes at the end of the chunk data
+        if self.remaining == 0 && !self.read_line()?.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Missing CRLF after chunk data",
+            ));
+        }
+
+        Ok(read)
+    }
+}
+
+/// Encodes everything written to it as `Transfer-Encoding: chunked`.
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Writes the terminating zero length chunk.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        write!(self.inner, "{:x}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Read};
+
+    use crate::body::{Body, ChunkedWriter};
+    use crate::body_type::BodyType;
+
+    #[test]
+    fn test_fixed_stops_at_length() {
+        let mut reader = "hello worldGET / HTTP/1.1".as_bytes();
+
+        let mut body = Body::from_reader(&mut reader, &BodyType::Fixed(11));
+
+        assert_eq!(body.content_length(), Some(11));
+
+        let mut data = String::new();
+        body.read_to_string(&mut data).expect("Failed to read body");
+
+        assert_eq!(data, "hello world");
+        assert_eq!(reader, "GET / HTTP/1.1".as_bytes());
+    }
+
+    #[test]
+    fn test_fixed_truncated() {
+        let mut reader = "hello".as_bytes();
+
+        let mut body = Body::from_reader(&mut reader, &BodyType::Fixed(11));
+
+        assert!(body.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_chunked_with_extensions_and_trailers() {
+        let mut reader =
+            "4;name=value\r\nWiki\r\n5\r\npedia\r\n0\r\nExpires: never\r\n\r\nnext".as_bytes();
+
+        let mut body = Body::from_reader(&mut reader, &BodyType::Chunked);
+
+        assert_eq!(body.content_length(), None);
+
+        let mut data = String::new();
+        body.read_to_string(&mut data).expect("Failed to read body");
+
+        assert_eq!(data, "Wikipedia");
+        assert_eq!(reader, "next".as_bytes());
+    }
+
+    #[test]
+    fn test_chunked_invalid_size() {
+        let mut reader = "zz\r\nWiki\r\n0\r\n\r\n".as_bytes();
+
+        let mut body = Body::from_reader(&mut reader, &BodyType::Chunked);
+
+        assert!(body.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_until_close() {
+        let mut reader = "all of the remaining data".as_bytes();
+
+        let mut body = Body::UntilClose(&mut reader);
+
+        body.buffer().expect("Failed to buffer body");
+
+        assert_eq!(
+            body.as_bytes(),
+            Some("all of the remaining data".as_bytes())
+        );
+        assert!(reader.fill_buf().expect("Failed to fill buffer").is_empty());
+    }
+
+    #[test]
+    fn test_write_chunked_round_trip() {
+        let mut reader = "all of the remaining data".as_bytes();
+
+        let mut encoded = Vec::new();
+
+        Body::UntilClose(&mut reader)
+            .write_to(&mut encoded, true)
+            .expect("Failed to write body");
+
+        assert_eq!(
+            encoded,
+            "19\r\nall of the remaining data\r\n0\r\n\r\n".as_bytes()
+        );
+
+        let mut encoded_reader = encoded.as_slice();
+        let mut decoded = Vec::new();
+
+        Body::from_reader(&mut encoded_reader, &BodyType::Chunked)
+            .read_to_end(&mut decoded)
+            .expect("Failed to read body");
+
+        assert_eq!(decoded, "all of the remaining data".as_bytes());
+    }
+
+    #[test]
+    fn test_chunked_writer_skips_empty_writes() {
+        let mut encoded = Vec::new();
+
+        let mut writer = ChunkedWriter::new(&mut encoded);
+
+        std::io::Write::write_all(&mut writer, b"").expect("Failed to write");
+
+        writer.finish().expect("Failed to finish");
+
+        assert_eq!(encoded, "0\r\n\r\n".as_bytes());
+    }
+}
@@ -1,72 +1,32 @@
-use std::io::{BufReader, Read};
+use std::io::{BufRead, Read};
 
-use crate::error::TcpIpError;
-use crate::util::slice_find_to_end;
+use crate::body::Body;
 use crate::Result;
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum BodyType {
     Fixed(usize),
     Chunked,
+    /// The body ends when the sender closes the connection.
+    UntilClose,
 }
 
 impl BodyType {
-    pub fn read_body<T>(&self, reader: &mut BufReader<T>) -> Result<Vec<u8>>
-    where
-        std::io::BufReader<T>: std::io::Read,
-    {
+    /// Returns the length of the body if it is known before reading it.
+    pub fn content_length(&self) -> Option<u64> {
         match *self {
-            BodyType::Fixed(content_length) => {
-                let mut body = Vec::with_capacity(content_length);
-
-                match reader.take(content_length as u64).read_to_end(&mut body) {
-                    Ok(_) => Ok(body),
-                    Err(e) => Err(TcpIpError::from(e)),
-                }
-            }
-            BodyType::Chunked => {
-                let mut data = Vec::new();
-                let mut chunk_data = Vec::new();
-
-                loop {
-                    let chunk_size_end = slice_find_to_end(chunk_data.as_slice(), &[13, 10]);
-
-                    if let Some(chunk_size_end) = chunk_size_end {
-                        let chunk_size_bytes = &chunk_data[..chunk_size_end];
-
-                        let chunk_size = match u64::from_str_radix(
-                            &String::from_utf8_lossy(chunk_size_bytes),
-                            16,
-                        ) {
-                            Ok(c) => c,
-                            Err(e) => {
-                                return Err(TcpIpError::from(e));
-                            }
-                        };
-
-                        // clear current chunk data which will remove the chunk size bytes
-                        chunk_data.clear();
-
-                        reader.take(chunk_size).read_to_end(&mut chunk_data)?;
+            BodyType::Fixed(content_length) => Some(content_length as u64),
+            BodyType::Chunked | BodyType::UntilClose => None,
+        }
+    }
 
-                        // consume /r/n bytes
-                        reader.take(2).read_to_end(&mut Vec::new())?;
+    /// Reads the whole body into memory, see `Body` for reading it as a stream.
+    pub fn read_body<R: BufRead>(&self, reader: &mut R) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
 
-                        // 0 indicates the end of the chunks
-                        if chunk_size != 0 {
-                            data.append(&mut chunk_data);
-                        } else {
-                            return Ok(data);
-                        }
-                    }
+        Body::from_reader(reader, self).read_to_end(&mut body)?;
 
-                    // Read 1 byte at a time to get the next chunk
-                    if let Err(e) = reader.take(1).read_to_end(&mut chunk_data) {
-                        return Err(TcpIpError::from(e));
-                    }
-                }
-            }
-        }
+        Ok(body)
     }
 }
 
@@ -0,0 +1,546 @@
+//! A blocking HTTP client for making requests to other servers, which keeps
+//! connections open between requests and follows redirects.
+//!
+//! ```no_run
+//! use http_lib::client::Client;
+//!
+//! let client = Client::new().timeout(10).max_redirects(3);
+//! let response = client.get("http://127.0.0.1:8080/users/1").expect("Request failed");
+//!
+//! println!("{}", response);
+//! ```
+
+use std::fmt::Formatter;
+use std::io::{self, BufReader, BufWriter, Read};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::address::Address;
+use crate::body::Body;
+use crate::body_type::BodyType;
+use crate::config::DEFAULT_TIMEOUT;
+use crate::error::TcpIpError;
+use crate::header_item::HeaderItem;
+use crate::http_version::HttpVersion;
+use crate::request::request_method::RequestMethod;
+use crate::request::uri::{TargetForm, Uri};
+use crate::request::{Request, RequestBuilder};
+use crate::response::Response;
+use crate::stream_helper::{
+    connect_remote, exchange_header, is_retryable, ConnectionPool, PooledConnection,
+};
+use crate::Result;
+
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+const DEFAULT_PORT: u16 = 80;
+
+/// An `http` URL split into the address to connect to and the request target,
+/// which is the path and query. Fragments are dropped as they aren't sent.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Url {
+    pub address: Address,
+    pub target: Uri,
+}
+
+impl Url {
+    /// Returns the value of the `Host` header for requests to this URL, which
+    /// leaves out the default port.
+    pub fn host(&self) -> String {
+        let host = &self.address.host;
+
+        if self.address.port != DEFAULT_PORT {
+            self.address.to_string()
+        } else if host.contains(':') {
+            format!("[{}]", host)
+        } else {
+            host.clone()
+        }
+    }
+
+    /// Resolves the `Location` of a redirect from this URL, which may be a URL,
+    /// an absolute path or a path relative to this one.
+    pub fn join(&self, location: &str) -> Result<Url> {
+        let location = location.split('#').next().unwrap_or_default();
+
+        let target = if location.contains("://") {
+            return location.parse();
+        } else if location.starts_with("//") {
+            return format!("http:{}", location).parse();
+        } else if location.starts_with('/') {
+            location.parse()?
+        } else {
+            let path = self.target.path();
+            let directory = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+
+            format!("{}{}", directory, location).parse()?
+        };
+
+        Ok(Url {
+            address: self.address.clone(),
+            target,
+        })
+    }
+}
+
+impl FromStr for Url {
+    type Err = TcpIpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let uri = match s.split('#').next().unwrap_or_default().parse::<Uri>() {
+            Ok(uri) if uri.form() == TargetForm::Absolute => uri,
+            Err(e) if s.contains("://") => {
+                return Err(TcpIpError::parse(format!("Invalid URL '{}' - {}", s, e)))
+            }
+            _ => {
+                return Err(TcpIpError::parse(format!(
+                    "URL '{}' must start with 'http://'",
+                    s
+                )))
+            }
+        };
+
+        let scheme = uri.scheme().unwrap_or_default();
+
+        if !scheme.eq_ignore_ascii_case("http") {
+            return Err(TcpIpError::parse(format!(
+                "Unsupported scheme '{}' in URL '{}'",
+                scheme, s
+            )));
+        }
+
+        let authority = uri.authority().unwrap_or_default();
+
+        if authority.contains('@') {
+            return Err(TcpIpError::parse(format!(
+                "Credentials in URL '{}' are not supported",
+                s
+            )));
+        }
+
+        let address = Address::parse_with_default_port(authority, DEFAULT_PORT)
+            .map_err(|e| TcpIpError::parse(format!("Invalid URL '{}' - {}", s, e)))?;
+
+        Ok(Url {
+            address,
+            target: uri.origin_form().parse()?,
+        })
+    }
+}
+
+impl std::fmt::Display for Url {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "http://{}{}", self.host(), self.target)
+    }
+}
+
+/// Sends requests and reads their responses into memory.
+///
+/// Connections are kept open after a response unless the server closes them,
+/// and reused for later requests to the same address. A client can be shared
+/// by several threads.
+pub struct Client {
+    timeout_seconds: u64,
+    max_redirects: usize,
+    pool: ConnectionPool,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self {
+            timeout_seconds: DEFAULT_TIMEOUT,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            pool: ConnectionPool::default(),
+        }
+    }
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the timeout in seconds for connecting and for each read and write.
+    pub fn timeout(mut self, timeout_seconds: u64) -> Self {
+        self.timeout_seconds = timeout_seconds;
+        self
+    }
+
+    /// Sets how many redirects are followed for one request. Redirects are
+    /// returned rather than followed if this is 0.
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Keeps up to `max_idle` connections open to each address, each for at
+    /// most `idle_timeout` seconds.
+    pub fn pool(mut self, max_idle: usize, idle_timeout: u64) -> Self {
+        self.pool = ConnectionPool::new(max_idle, Duration::from_secs(idle_timeout));
+        self
+    }
+
+    pub fn get(&self, url: &str) -> Result<Response<'static>> {
+        self.request(RequestMethod::Get, url, None)
+    }
+
+    pub fn post(&self, url: &str, body: Vec<u8>) -> Result<Response<'static>> {
+        self.request(RequestMethod::Post, url, Some(body))
+    }
+
+    pub fn put(&self, url: &str, body: Vec<u8>) -> Result<Response<'static>> {
+        self.request(RequestMethod::Put, url, Some(body))
+    }
+
+    pub fn delete(&self, url: &str) -> Result<Response<'static>> {
+        self.request(RequestMethod::Delete, url, None)
+    }
+
+    fn request(
+        &self,
+        method: RequestMethod,
+        url: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<Response<'static>> {
+        let mut builder = RequestBuilder::new().method(method).uri(url);
+
+        if let Some(body) = body {
+            builder = builder.body(body);
+        }
+
+        self.send(builder.build()?)
+    }
+
+    /// Sends `request` and reads the response, following redirects.
+    ///
+    /// The URI of the request is either a URL, or a path which is sent to the
+    /// server in its `Host` header. `Host` and `Content-Length` are set from
+    /// the URL and body.
+    pub fn send(&self, mut request: Request) -> Result<Response<'static>> {
+        // The body is held in memory so it can be sent again
+        request.body.buffer()?;
+
+        let mut url = request_url(&request)?;
+        let mut redirects = 0;
+
+        loop {
+            request.header.uri = url.target.clone();
+            request.header.insert_header("Host", &url.host());
+
+            if request.body.is_empty() {
+                if matches!(
+                    request.header.method,
+                    RequestMethod::Post | RequestMethod::Put | RequestMethod::Patch
+                ) {
+                    request.header.insert_header("Content-Length", "0");
+                }
+            } else {
+                request.body.rewind();
+            }
+
+            let response = self.exchange(&url.address, &mut request)?;
+
+            let location = match redirect_location(&response) {
+                Some(location) if self.max_redirects > 0 => location,
+                _ => return Ok(response),
+            };
+
+            if redirects == self.max_redirects {
+                return Err(TcpIpError::TooManyRedirects {
+                    limit: self.max_redirects,
+                });
+            }
+
+            redirects += 1;
+
+            let next = url.join(location)?;
+
+            // Credentials are only sent to the server they were meant for
+            if next.address != url.address {
+                request.header.remove_header("Authorization");
+                request.header.remove_header("Cookie");
+            }
+
+            // Like browsers, POST is changed to GET by a 301 or 302 as well as by a 303
+            let status_code = response.header.status_code;
+
+            if status_code == 303
+                || (matches!(status_code, 301 | 302)
+                    && request.header.method == RequestMethod::Post)
+            {
+                if request.header.method != RequestMethod::Head {
+                    request.header.method = RequestMethod::Get;
+                }
+
+                request.body = Body::Empty;
+                request.header.remove_header("Content-Length");
+                request.header.remove_header("Content-Type");
+                request.header.remove_header("Transfer-Encoding");
+            }
+
+            url = next;
+        }
+    }
+
+    // Sends the request on a pooled connection to `address` and reads the whole response
+    fn exchange(&self, address: &Address, request: &mut Request) -> Result<Response<'static>> {
+        let mut connection = self.pool.checkout(address, self.timeout_seconds)?;
+
+        loop {
+            let mut reader = BufReader::new(&connection.stream);
+            let mut writer = BufWriter::new(&connection.stream);
+
+            let header = match exchange_header(
+                &mut reader,
+                &mut writer,
+                request,
+                &mut io::sink(),
+                HttpVersion::Http11,
+            ) {
+                Ok(header) => header,
+                // The server may have closed a pooled connection while it was idle
+                Err(e) if connection.reused && is_retryable(request, &e) => {
+                    drop(writer);
+                    request.body.rewind();
+
+                    connection = PooledConnection {
+                        stream: connect_remote(address, self.timeout_seconds)?,
+                        reused: false,
+                    };
+
+                    continue;
+                }
+                Err(e) => return Err(e.into_upstream()),
+            };
+
+            drop(writer);
+
+            let body_type = header
+                .body_type_for(&request.header.method)
+                .map_err(TcpIpError::into_upstream)?;
+
+            let body = match &body_type {
+                Some(body_type) => {
+                    let mut data = Vec::new();
+
+                    Body::from_reader(&mut reader, body_type)
+                        .read_to_end(&mut data)
+                        .map_err(|e| TcpIpError::from(e).into_upstream())?;
+
+                    Body::from(data)
+                }
+                None => Body::Empty,
+            };
+
+            let reusable = header.is_keep_alive()
+                && header.status_code != 101
+                && body_type != Some(BodyType::UntilClose)
+                && reader.buffer().is_empty();
+
+            if reusable {
+                self.pool.checkin(address, connection.stream);
+            }
+
+            return Ok(Response { header, body });
+        }
+    }
+}
+
+// Returns the URL a request is sent to from its URI, or from its Host header
+// if the URI is only a path
+fn request_url(request: &Request) -> Result<Url> {
+    let uri = &request.header.uri;
+
+    if uri.form() == TargetForm::Absolute {
+        return uri.as_str().parse();
+    }
+
+    let host = request
+        .header
+        .headers
+        .as_ref()
+        .and_then(|headers| headers.get("Host"))
+        .ok_or_else(|| {
+            TcpIpError::InvalidInput(format!(
+                "Request to '{}' needs a URL or a Host header to be sent",
+                uri
+            ))
+        })?;
+
+    format!("http://{}{}", host, uri).parse()
+}
+
+fn redirect_location<'a>(response: &'a Response) -> Option<&'a str> {
+    if matches!(response.header.status_code, 301 | 302 | 303 | 307 | 308) {
+        response
+            .header
+            .headers
+            .as_ref()
+            .and_then(|headers| headers.get("Location"))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::address::Address;
+    use crate::client::{Client, Url};
+    use crate::http_item::HttpItem;
+    use crate::request::Request;
+    use crate::response::Response;
+
+    #[test]
+    fn test_parse_url() {
+        let url = "http://example.test/a/b?c=d#e"
+            .parse::<Url>()
+            .expect("Failed to parse URL");
+
+        assert_eq!(url.address, Address::new("example.test", 80));
+        assert_eq!(url.target, "/a/b?c=d");
+        assert_eq!(url.host(), "example.test");
+        assert_eq!(url.to_string(), "http://example.test/a/b?c=d");
+
+        let url = "HTTP://[::1]:8080?x"
+            .parse::<Url>()
+            .expect("Failed to parse URL");
+
+        assert_eq!(url.address, Address::new("::1", 8080));
+        assert_eq!(url.target, "/?x");
+        assert_eq!(url.host(), "[::1]:8080");
+
+        let host = "http://[::1]"
+            .parse::<Url>()
+            .expect("Failed to parse URL")
+            .host();
+
+        assert_eq!(host, "[::1]");
+
+        let error = |url: &str| {
+            url.parse::<Url>()
+                .expect_err("Parsed an invalid URL")
+                .to_string()
+        };
+
+        assert_eq!(
+            error("https://a.test/"),
+            "Unsupported scheme 'https' in URL 'https://a.test/'"
+        );
+        assert_eq!(error("a.test/"), "URL 'a.test/' must start with 'http://'");
+        assert_eq!(
+            error("http://a.test:x/"),
+            "Invalid URL 'http://a.test:x/' - Invalid port in address 'a.test:x' - invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn test_join() {
+        let url = "http://a.test:81/x/y?z"
+            .parse::<Url>()
+            .expect("Failed to parse URL");
+
+        let joined = |location: &str| url.join(location).expect("Failed to join").to_string();
+
+        assert_eq!(joined("/b#c"), "http://a.test:81/b");
+        assert_eq!(joined("b?c"), "http://a.test:81/x/b?c");
+        assert_eq!(joined("//b.test/c"), "http://b.test/c");
+        assert_eq!(joined("http://b.test:82"), "http://b.test:82/");
+    }
+
+    // A server that redirects `/old` to `/new`, counting the connections it accepts
+    fn server(accepted: Arc<AtomicUsize>) -> Address {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+        let address = Address::from(listener.local_addr().expect("Missing local address"));
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.expect("Failed to accept");
+                accepted.fetch_add(1, Ordering::SeqCst);
+
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(&stream);
+
+                    while let Ok(mut request) = Request::from_reader(&mut reader) {
+                        let mut body = Vec::new();
+                        let _ = request.body.read_to_end(&mut body);
+
+                        let header = &request.header;
+                        let host = header
+                            .headers
+                            .as_ref()
+                            .and_then(|h| h.get("Host"))
+                            .unwrap_or_default()
+                            .to_owned();
+
+                        let response = match header.uri.as_str() {
+                            "/old" => "HTTP/1.1 303 See Other\r\nLocation: /new\r\nContent-Length: 0\r\n\r\n".to_owned(),
+                            "/loop" => "HTTP/1.1 302 Found\r\nLocation: loop\r\nContent-Length: 0\r\n\r\n".to_owned(),
+                            uri => {
+                                let text = format!("{} {} {} {}", header.method, uri, host, body.len());
+
+                                format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", text.len(), text)
+                            }
+                        };
+
+                        let _ = (&stream).write_all(response.as_bytes());
+                    }
+                });
+            }
+        });
+
+        address
+    }
+
+    fn text(response: &Response) -> String {
+        String::from_utf8_lossy(response.body.as_bytes().unwrap_or_default()).into_owned()
+    }
+
+    #[test]
+    fn test_client() {
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let address = server(accepted.clone());
+        let client = Client::new();
+
+        let response = client
+            .get(&format!("http://{}/a?b", address))
+            .expect("Request failed");
+
+        assert_eq!(response.header.status_code, 200);
+        assert_eq!(text(&response), format!("GET /a?b {} 0", address));
+
+        let response = client
+            .post(&format!("http://{}/old", address), b"body".to_vec())
+            .expect("Request failed");
+
+        assert_eq!(text(&response), format!("GET /new {} 0", address));
+
+        let response = client
+            .put(&format!("http://{}/c", address), b"body".to_vec())
+            .expect("Request failed");
+
+        assert_eq!(text(&response), format!("PUT /c {} 4", address));
+
+        // Every request was sent on the same connection
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+        let e = Client::new()
+            .max_redirects(2)
+            .delete(&format!("http://{}/loop", address))
+            .expect_err("Followed redirects forever");
+
+        assert_eq!(e.to_string(), "Stopped after following 2 redirects");
+
+        let response = Client::new()
+            .max_redirects(0)
+            .get(&format!("http://{}/loop", address))
+            .expect("Request failed");
+
+        assert_eq!(response.header.status_code, 302);
+    }
+}
@@ -0,0 +1,954 @@
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::access_log::LogOptions;
+use crate::address::Address;
+use crate::config::toml::{Entry, Table, Value};
+use crate::error::TcpIpError;
+use crate::error_page::ErrorPage;
+use crate::forward_proxy::{ForwardProxyOptions, HostRule};
+use crate::har::CaptureOptions;
+use crate::header_parser::DEFAULT_MAX_HEADER_SIZE;
+use crate::replay::ReplayOptions;
+use crate::router::Router;
+use crate::server::{
+    open_replay, OverloadPolicy, ServerHandle, DEFAULT_ACCEPT_BACKLOG, DEFAULT_MAX_CONNECTIONS,
+};
+use crate::stream_helper::{
+    DEFAULT_MAX_BODY_SIZE, DEFAULT_POOL_IDLE_TIMEOUT, DEFAULT_POOL_MAX_IDLE,
+};
+use crate::Result;
+
+pub mod toml;
+
+const CONFIG_FILE_NAME: &str = "tcp_ip_monitor_config.toml";
+const LEGACY_CONFIG_FILE_NAME: &str = "tcp_ip_monitor_config.txt";
+
+const CONFIG_TEMPLATE: &str = r#"# Each [servers.<name>] table starts a proxy server that forwards requests
+# from `listen` to `remote`. Addresses may be IPv4, IPv6 in brackets or host
+# names, and `listen` may be just a port to listen on 127.0.0.1.
+#
+# Options may be set for each server, or in [defaults] for every server:
+#   timeout = 4                Socket timeout in seconds
+#   max_header_size = 65536    Largest request header accepted in bytes
+#   max_body_size = 10485760   Largest request body accepted in bytes when
+#                              replaying or routing, forwarded bodies are streamed
+#   max_requests = 100         Requests served on one client connection
+#   max_connections = 256      Client connections served at once, each on a thread
+#   accept_backlog = 64        Client connections waiting for a free thread
+#   overload = "queue"         When both are full: "queue", "reject" with a 503 or "close"
+#   pool_max_idle = 8          Idle connections kept open to the remote server
+#   pool_idle_timeout = 60     Seconds an idle remote connection is kept open
+#   log = true                 Write a line to the access log for each request
+#   log_format = "combined"    Access log format: "common", "combined" or "json"
+#   log_file = "access.log"    Access log file, which is stdout if not set
+#   log_max_size = 10485760    Size in bytes after which the log file is rotated
+#   log_max_files = 5          Rotated log files kept
+#   har_file = "capture.har"   Capture every exchange to an HTTP Archive file
+#   har_max_body_size = 1048576  Bytes of each body captured
+#   replay = "capture.har"     Answer requests with the responses in a captured
+#                              HAR file, without connecting to remote
+#   replay_match_headers = ["accept", "authorization"]
+#                              Headers a request must share with a recorded one
+#   replay_match_body = true   Whether the request bodies must also be the same
+#   forward_proxy = true       Forward each request to the server named by its
+#                              URL or Host header, using remote for requests
+#                              that name none
+#   proxy_allow = ["*.example.com", "10.0.0.1:8080"]
+#                              Servers the forward proxy may connect to, where
+#                              `*` matches any host or port
+#   proxy_deny = ["*:25"]      Servers it may not connect to, even if allowed,
+#                              where IP addresses are also checked after
+#                              resolving names
+#   error_content_type = "text/plain; charset=utf-8"
+#   error_template = "{status_code} {reason_phrase}\n\n{message}\n"
+#
+# Example:
+# [servers.api]
+# listen = 1234
+# remote = "127.0.0.1:5678"
+# timeout = 10
+"#;
+
+pub const DEFAULT_TIMEOUT: u64 = 4;
+pub const DEFAULT_MAX_REQUESTS: usize = 100;
+/// Host listened on when a server is only given a port.
+pub const DEFAULT_LISTEN_HOST: &str = "127.0.0.1";
+
+/// Options every server accepts, in the config file or as environment variables.
+const SERVER_OPTIONS: &[&str] = &[
+    "listen",
+    "remote",
+    "timeout",
+    "max_header_size",
+    "max_body_size",
+    "max_requests",
+    "max_connections",
+    "accept_backlog",
+    "overload",
+    "pool_max_idle",
+    "pool_idle_timeout",
+    "log",
+    "log_format",
+    "log_file",
+    "log_max_size",
+    "log_max_files",
+    "har_file",
+    "har_max_body_size",
+    "replay",
+    "replay_match_headers",
+    "replay_match_body",
+    "forward_proxy",
+    "proxy_allow",
+    "proxy_deny",
+    "error_content_type",
+    "error_template",
+];
+
+/// Environment variable naming the config file to read.
+pub const CONFIG_PATH_ENV: &str = "TCP_IP_MONITOR_CONFIG";
+/// Prefix of the environment variables that override server options.
+pub const ENV_PREFIX: &str = "TCP_IP_MONITOR_";
+
+/// How the config is found and whether it is only being checked.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct LoadOptions {
+    pub path: Option<PathBuf>,
+    /// Validate the config without creating a template or binding ports.
+    pub check: bool,
+}
+
+impl LoadOptions {
+    /// Reads `--config <path>` and `--check` from command line arguments,
+    /// excluding the program name.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "--check" {
+                options.check = true;
+            } else if arg == "--config" {
+                let path = args
+                    .next()
+                    .ok_or_else(|| TcpIpError::config("Missing path after '--config'"))?;
+
+                options.path = Some(PathBuf::from(path));
+            } else if let Some(path) = arg.strip_prefix("--config=") {
+                options.path = Some(PathBuf::from(path));
+            } else {
+                return Err(TcpIpError::config(format!(
+                    "Unknown argument '{}'. Usage: [--config <path>] [--check]",
+                    arg
+                )));
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+pub struct Config {
+    pub servers: Vec<Server>,
+}
+
+impl Config {
+    /// Reads `tcp_ip_monitor_config.toml`, or the legacy `tcp_ip_monitor_config.txt`
+    /// if it doesn't exist. If neither exist a template is created.
+    pub fn load() -> Result<Self> {
+        Self::load_with(&LoadOptions::default())
+    }
+
+    /// Reads the config file given by `options.path`, the `TCP_IP_MONITOR_CONFIG`
+    /// environment variable or one of the default names, then applies any
+    /// environment variable overrides.
+    ///
+    /// A template is only created if no path was given and no default file
+    /// exists, and never in check mode.
+    pub fn load_with(options: &LoadOptions) -> Result<Self> {
+        let mut config = if let Some(path) = Self::path(options) {
+            Self::read(path)?
+        } else if options.check {
+            return Err(TcpIpError::config(format!(
+                "Missing config file named '{}'",
+                CONFIG_FILE_NAME
+            )));
+        } else {
+            let current_dir = std::env::current_dir()?;
+
+            let mut f = fs::File::create(CONFIG_FILE_NAME).map_err(|e| {
+                TcpIpError::config(format!("Failed to create '{}' - {}", CONFIG_FILE_NAME, e))
+            })?;
+
+            f.write_all(CONFIG_TEMPLATE.as_bytes()).map_err(|e| {
+                TcpIpError::config(format!("Failed to write to '{}' - {}", CONFIG_FILE_NAME, e))
+            })?;
+
+            return Err(TcpIpError::config(format!("Missing config file named '{}'. One has been created at '{}'. Please modify it and then restart the tcp_ip_monitor.", CONFIG_FILE_NAME, current_dir.display())));
+        };
+
+        config.apply_env(|key| env::var(key).ok())?;
+
+        if options.check {
+            config.check()?;
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the config file `load_with` reads, if it was given or one of the
+    /// default files exists.
+    pub fn path(options: &LoadOptions) -> Option<PathBuf> {
+        options
+            .path
+            .clone()
+            .or_else(|| env::var_os(CONFIG_PATH_ENV).map(PathBuf::from))
+            .or_else(|| {
+                [CONFIG_FILE_NAME, LEGACY_CONFIG_FILE_NAME]
+                    .iter()
+                    .map(PathBuf::from)
+                    .find(|path| path.exists())
+            })
+    }
+
+    /// Reads a config file, using the structured format if it has a `.toml`
+    /// extension and the legacy format otherwise.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        let contents = fs::read_to_string(path).map_err(|e| {
+            TcpIpError::config(format!("Failed to read '{}' - {}", path.display(), e))
+        })?;
+
+        if path.extension().is_some_and(|e| e == "toml") {
+            Self::from_toml(&contents)
+        } else {
+            Self::from_legacy(&contents)
+        }
+    }
+
+    /// Overrides server options with environment variables, read with `var`.
+    ///
+    /// `TCP_IP_MONITOR_<OPTION>` sets an option for every server, and
+    /// `TCP_IP_MONITOR_<SERVER>_<OPTION>` for the server with that name, where
+    /// names are upper cased with other characters replaced by `_`. For example
+    /// `TCP_IP_MONITOR_TIMEOUT=10` or `TCP_IP_MONITOR_API_REMOTE=backend:80`.
+    pub fn apply_env<F: Fn(&str) -> Option<String>>(&mut self, var: F) -> Result<()> {
+        for server in &mut self.servers {
+            let server_prefix = format!("{}{}_", ENV_PREFIX, env_name(&server.name));
+
+            for option in SERVER_OPTIONS {
+                let global = match *option {
+                    "listen" | "remote" => None,
+                    _ => Some(format!("{}{}", ENV_PREFIX, env_name(option))),
+                };
+                let specific = format!("{}{}", server_prefix, env_name(option));
+
+                for key in global.iter().chain(Some(&specific)) {
+                    if let Some(value) = var(key) {
+                        server
+                            .set_option(&Entry::from_env(option, &value))
+                            .map_err(|e| match e {
+                                TcpIpError::Config { message, .. } => TcpIpError::config(format!(
+                                    "Environment variable '{}' - {}",
+                                    key, message
+                                )),
+                                e => e,
+                            })?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that every server can be started, without binding any ports.
+    pub fn check(&self) -> Result<()> {
+        for server in &self.servers {
+            server.listen_address.resolve().map_err(|e| {
+                TcpIpError::config(format!(
+                    "Server '{}' can't listen on '{}' - {}",
+                    server.name, server.listen_address, e
+                ))
+            })?;
+
+            let access_log = server.access_log.file.as_ref().filter(|_| server.log);
+            let files = access_log
+                .map(|file| ("access log", file))
+                .into_iter()
+                .chain(server.capture.file.as_ref().map(|file| ("HAR file", file)));
+
+            open_replay(server)?;
+
+            for (written, file) in files {
+                let dir = file
+                    .parent()
+                    .filter(|dir| !dir.as_os_str().is_empty())
+                    .unwrap_or_else(|| Path::new("."));
+
+                if !dir.is_dir() {
+                    return Err(TcpIpError::config(format!(
+                        "Server '{}' can't write its {} to '{}' - '{}' is not a directory",
+                        server.name,
+                        written,
+                        file.display(),
+                        dir.display()
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the structured config format, with a `[servers.<name>]` table for
+    /// each server and optional `[defaults]` for every server.
+    pub fn from_toml(contents: &str) -> Result<Self> {
+        let tables = toml::parse(contents)?;
+
+        let mut defaults: &[Entry] = &[];
+        let mut server_tables = Vec::new();
+
+        for table in &tables {
+            match table.name.as_slice() {
+                [] => {
+                    if let Some(entry) = table.entries.first() {
+                        return Err(TcpIpError::config(format!(
+                            "'{}' must be in a [servers.<name>] or [defaults] table",
+                            entry.key
+                        ))
+                        .at_column(entry.line, entry.key_column));
+                    }
+                }
+                [name] if name == "defaults" => {
+                    if let Some(entry) = table
+                        .entries
+                        .iter()
+                        .find(|e| e.key == "listen" || e.key == "remote")
+                    {
+                        return Err(TcpIpError::config(format!(
+                            "'{}' must be set for each server",
+                            entry.key
+                        ))
+                        .at_column(entry.line, entry.key_column));
+                    }
+
+                    defaults = &table.entries;
+                }
+                [servers, name] if servers == "servers" => server_tables.push((name, table)),
+                // Defined by a dotted key such as `servers.api.listen = 1234`
+                [servers] if servers == "servers" && table.entries.is_empty() => {}
+                name => {
+                    return Err(TcpIpError::config(format!(
+                        "Unknown table '{}', expected [servers.<name>] or [defaults]",
+                        name.join(".")
+                    ))
+                    .at_line(table.line))
+                }
+            }
+        }
+
+        let mut servers: Vec<Server> = Vec::new();
+
+        for (name, table) in server_tables {
+            let server = Server::from_entries(name, table, defaults)?;
+
+            if let Some(other) = servers
+                .iter()
+                .find(|s| s.listen_address == server.listen_address)
+            {
+                let listen = table
+                    .entries
+                    .iter()
+                    .find(|e| e.key == "listen")
+                    .expect("A server always has a listen entry");
+
+                return Err(listen.error(format!(
+                    "address '{}' is already used by server '{}'",
+                    server.listen_address, other.name
+                )));
+            }
+
+            servers.push(server);
+        }
+
+        if servers.is_empty() {
+            Err(TcpIpError::config(
+                "At least one [servers.<name>] table must be defined",
+            ))
+        } else {
+            Ok(Self { servers })
+        }
+    }
+
+    /// Reads the legacy config format, with a
+    /// `[listen port or address] [remote address] [timeout (optional)]` line for each server.
+    pub fn from_legacy(contents: &str) -> Result<Self> {
+        let servers = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| {
+                let l = l.trim();
+                !l.is_empty() && !l.starts_with('#')
+            })
+            .map(|(i, l)| Server::from_str(l).map_err(|e| e.at_line(i + 1)))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        if servers.is_empty() {
+            Err(TcpIpError::config(format!(
+                "'{}' must not be empty",
+                LEGACY_CONFIG_FILE_NAME
+            )))
+        } else {
+            Ok(Self { servers })
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Server {
+    pub listen_address: Address,
+    pub remote_address: Address,
+    pub timeout: u64,
+    pub name: String,
+    pub max_header_size: usize,
+    pub max_body_size: u64,
+    pub pool_max_idle: usize,
+    pub pool_idle_timeout: u64,
+    pub max_requests: usize,
+    pub max_connections: usize,
+    pub accept_backlog: usize,
+    pub overload: OverloadPolicy,
+    pub error_page: ErrorPage,
+    /// Write each request to the access log.
+    pub log: bool,
+    pub access_log: LogOptions,
+    pub capture: CaptureOptions,
+    pub replay: ReplayOptions,
+    pub forward_proxy: ForwardProxyOptions,
+}
+
+impl Server {
+    /// Creates a server with the default options.
+    pub fn new<T: AsRef<str>>(name: T, listen_address: Address, remote_address: Address) -> Self {
+        Self {
+            listen_address,
+            remote_address,
+            timeout: DEFAULT_TIMEOUT,
+            name: name.as_ref().to_owned(),
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            pool_max_idle: DEFAULT_POOL_MAX_IDLE,
+            pool_idle_timeout: DEFAULT_POOL_IDLE_TIMEOUT,
+            max_requests: DEFAULT_MAX_REQUESTS,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            accept_backlog: DEFAULT_ACCEPT_BACKLOG,
+            overload: OverloadPolicy::default(),
+            error_page: ErrorPage::default(),
+            log: true,
+            access_log: LogOptions::default(),
+            capture: CaptureOptions::default(),
+            replay: ReplayOptions::default(),
+            forward_proxy: ForwardProxyOptions::default(),
+        }
+    }
+
+    fn from_entries(name: &str, table: &Table, defaults: &[Entry]) -> Result<Self> {
+        let address = |key: &str| {
+            table
+                .entries
+                .iter()
+                .find(|e| e.key == key)
+                .ok_or_else(|| {
+                    TcpIpError::config(format!("Server '{}' is missing '{}'", name, key))
+                        .at_line(table.line)
+                })
+                .and_then(parse_address)
+        };
+
+        let mut server = Self::new(name, address("listen")?, address("remote")?);
+
+        for entry in defaults.iter().chain(&table.entries) {
+            server.set_option(entry)?;
+        }
+
+        Ok(server)
+    }
+
+    fn set_option(&mut self, entry: &Entry) -> Result<()> {
+        match entry.key.as_str() {
+            "listen" => self.listen_address = parse_address(entry)?,
+            "remote" => self.remote_address = parse_address(entry)?,
+            "timeout" => self.timeout = entry.as_positive()?,
+            "max_header_size" => self.max_header_size = entry.as_positive()?,
+            "max_body_size" => self.max_body_size = entry.as_positive()?,
+            "max_requests" => self.max_requests = entry.as_positive()?,
+            "max_connections" => self.max_connections = entry.as_positive()?,
+            "accept_backlog" => self.accept_backlog = entry.as_positive()?,
+            "overload" => {
+                self.overload = entry
+                    .as_str()?
+                    .parse()
+                    .map_err(|e| entry.error(format!("is invalid - {}", e)))?
+            }
+            "pool_max_idle" => self.pool_max_idle = entry.as_positive()?,
+            "pool_idle_timeout" => self.pool_idle_timeout = entry.as_positive()?,
+            "log" => self.log = entry.as_bool()?,
+            "log_format" => {
+                self.access_log.format = entry
+                    .as_str()?
+                    .parse()
+                    .map_err(|e| entry.error(format!("is invalid - {}", e)))?
+            }
+            "log_file" => self.access_log.file = Some(PathBuf::from(entry.as_str()?)),
+            "log_max_size" => self.access_log.max_size = entry.as_positive()?,
+            "log_max_files" => self.access_log.max_files = entry.as_positive()?,
+            "har_file" => self.capture.file = Some(PathBuf::from(entry.as_str()?)),
+            "har_max_body_size" => self.capture.max_body_size = entry.as_positive()?,
+            "replay" => self.replay.file = Some(PathBuf::from(entry.as_str()?)),
+            "replay_match_headers" => {
+                self.replay.matching.headers =
+                    entry.as_strings()?.into_iter().map(str::to_owned).collect()
+            }
+            "replay_match_body" => self.replay.matching.body = entry.as_bool()?,
+            "forward_proxy" => self.forward_proxy.enabled = entry.as_bool()?,
+            "proxy_allow" => self.forward_proxy.destinations.allow = parse_host_rules(entry)?,
+            "proxy_deny" => self.forward_proxy.destinations.deny = parse_host_rules(entry)?,
+            "error_content_type" => self.error_page.content_type = entry.as_str()?.to_owned(),
+            "error_template" => self.error_page.template = entry.as_str()?.to_owned(),
+            key => {
+                return Err(TcpIpError::config(format!("Unknown option '{}'", key))
+                    .at_column(entry.line, entry.key_column))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts accepting connections on a new thread, returning a handle to
+    /// shut the server down.
+    pub fn start(self) -> Result<ServerHandle> {
+        ServerHandle::spawn(self)
+    }
+
+    /// Starts the server like `start`, but answering requests with the
+    /// handlers of `router` rather than forwarding them to `remote_address`.
+    pub fn serve(self, router: Router) -> Result<ServerHandle> {
+        router.check()?;
+
+        ServerHandle::spawn_with(self, Some(Arc::new(router)))
+    }
+}
+
+// `listen` may be just a port, otherwise addresses are strings
+fn parse_address(entry: &Entry) -> Result<Address> {
+    match entry.value {
+        Value::Integer(port) if entry.key == "listen" => u16::try_from(port)
+            .map(|port| Address::new(DEFAULT_LISTEN_HOST, port))
+            .map_err(|_| entry.error(format!("port {} is out of range", port))),
+        _ => entry
+            .as_str()?
+            .parse::<Address>()
+            .map_err(|e| entry.error(format!("is invalid - {}", e))),
+    }
+}
+
+fn parse_host_rules(entry: &Entry) -> Result<Vec<HostRule>> {
+    entry
+        .as_strings()?
+        .into_iter()
+        .map(|rule| {
+            rule.parse()
+                .map_err(|e| entry.error(format!("is invalid - {}", e)))
+        })
+        .collect()
+}
+
+// Upper cases a name for use in an environment variable
+fn env_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+impl FromStr for Server {
+    type Err = TcpIpError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut items = s.split_whitespace();
+
+        let listen = items
+            .next()
+            .ok_or_else(|| TcpIpError::config("Missing listen port"))?;
+
+        let listen_address = Address::parse_with_default_host(listen, DEFAULT_LISTEN_HOST)
+            .map_err(|e| TcpIpError::config(format!("Failed to read listen address - {}", e)))?;
+
+        let remote = items
+            .next()
+            .ok_or_else(|| TcpIpError::config("Missing remote address"))?;
+
+        let remote_address = remote
+            .parse()
+            .map_err(|e| TcpIpError::config(format!("Failed to read remote address - {}", e)))?;
+
+        let mut server = Self::new(
+            format!("{} -> {}", listen, remote),
+            listen_address,
+            remote_address,
+        );
+
+        if let Some(timeout) = items.next() {
+            server.timeout = timeout
+                .parse()
+                .map_err(|e| TcpIpError::config(format!("Failed to read timeout - {}", e)))?;
+        }
+
+        Ok(server)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::address::Address;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::access_log::{LogFormat, LogOptions, DEFAULT_LOG_MAX_SIZE};
+    use crate::config::{Config, LoadOptions, Server, CONFIG_TEMPLATE};
+    use crate::forward_proxy::{ForwardProxyOptions, HostRule};
+    use crate::har::CaptureOptions;
+    use crate::replay::{Matching, ReplayOptions};
+    use crate::server::OverloadPolicy;
+    use crate::stream_helper::DEFAULT_MAX_BODY_SIZE;
+
+    #[test]
+    fn from_str_server() {
+        let config = r#"80 127.0.0.1:5000"#;
+
+        let c = Server::from_str(config).expect("Failed to parse server");
+
+        assert_eq!(c.listen_address, Address::new("127.0.0.1", 80));
+        assert_eq!(c.remote_address, Address::new("127.0.0.1", 5000));
+        assert_eq!(c.name, "80 -> 127.0.0.1:5000");
+
+        let c =
+            Server::from_str("[::]:8080 backend.internal:80 10").expect("Failed to parse server");
+
+        assert_eq!(c.listen_address, Address::new("::", 8080));
+        assert_eq!(c.remote_address, Address::new("backend.internal", 80));
+        assert_eq!(c.timeout, 10);
+
+        assert!(Server::from_str("80 ::1:5000").is_err());
+    }
+
+    #[test]
+    fn test_from_legacy() {
+        let config = Config::from_legacy("# Servers\n\n80  127.0.0.1:5000\n  81 [::1]:5001 10\n")
+            .expect("Failed to parse config");
+
+        assert_eq!(config.servers.len(), 2);
+        assert_eq!(config.servers[1].timeout, 10);
+
+        let e = Config::from_legacy("80 127.0.0.1:5000\n81 127.0.0.1\n")
+            .err()
+            .expect("Parsed invalid config");
+
+        assert!(e.to_string().starts_with("Config line 2 - "));
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let config = Config::from_toml(
+            "[defaults]\ntimeout = 10\nlog = false\n\n[servers.api]\nlisten = 1234\nremote = \"backend.internal:80\"\n\n[servers.admin]\nlisten = \"[::]:1235\"\nremote = \"127.0.0.1:5678\"\ntimeout = 2\nerror_content_type = \"text/html\"\noverload = \"reject\"\nmax_connections = 16\nmax_body_size = 1024\nlog_format = \"json\"\nlog_file = \"logs/admin.log\"\nlog_max_files = 2\nhar_file = \"admin.har\"\nhar_max_body_size = 4096\nreplay = \"recorded.har\"\nreplay_match_headers = [\"Accept\", \"authorization\"]\nreplay_match_body = false\nforward_proxy = true\nproxy_allow = [\n  \"*.example.com\",\n  \"[::1]:8080\",\n]\nproxy_deny = [\"*:25\"]\n",
+        )
+        .expect("Failed to parse config");
+
+        let api = &config.servers[0];
+
+        assert_eq!(api.name, "api");
+        assert_eq!(api.listen_address, Address::new("127.0.0.1", 1234));
+        assert_eq!(api.remote_address, Address::new("backend.internal", 80));
+        assert_eq!(api.timeout, 10);
+        assert!(!api.log);
+
+        let admin = &config.servers[1];
+
+        assert_eq!(admin.listen_address, Address::new("::", 1235));
+        assert_eq!(admin.timeout, 2);
+        assert_eq!(admin.error_page.content_type, "text/html");
+        assert_eq!(admin.overload, OverloadPolicy::Reject);
+        assert_eq!(admin.max_connections, 16);
+        assert_eq!(admin.max_body_size, 1024);
+        assert_eq!(api.max_body_size, DEFAULT_MAX_BODY_SIZE);
+        assert_eq!(
+            admin.access_log,
+            LogOptions {
+                format: LogFormat::Json,
+                file: Some(PathBuf::from("logs/admin.log")),
+                max_size: DEFAULT_LOG_MAX_SIZE,
+                max_files: 2,
+            }
+        );
+        assert_eq!(
+            admin.capture,
+            CaptureOptions {
+                file: Some(PathBuf::from("admin.har")),
+                max_body_size: 4096,
+            }
+        );
+        assert_eq!(api.capture, CaptureOptions::default());
+        assert_eq!(
+            admin.replay,
+            ReplayOptions {
+                file: Some(PathBuf::from("recorded.har")),
+                matching: Matching {
+                    headers: vec!["Accept".to_owned(), "authorization".to_owned()],
+                    body: false,
+                },
+            }
+        );
+        assert_eq!(api.replay, ReplayOptions::default());
+        assert!(admin.forward_proxy.enabled);
+        assert_eq!(
+            admin.forward_proxy.destinations.allow,
+            ["*.example.com", "[::1]:8080"]
+                .iter()
+                .map(|rule| rule.parse().expect("Invalid rule"))
+                .collect::<Vec<HostRule>>()
+        );
+        assert_eq!(admin.forward_proxy.destinations.deny.len(), 1);
+        assert_eq!(api.forward_proxy, ForwardProxyOptions::default());
+    }
+
+    #[test]
+    fn test_from_toml_errors() {
+        let error = |contents: &str| {
+            Config::from_toml(contents)
+                .err()
+                .expect("Parsed invalid config")
+                .to_string()
+        };
+
+        assert_eq!(
+            error("[servers.api]\nlisten = 1234\nremote = \"::1:80\""),
+            "Config line 3, column 10 - 'remote' is invalid - IPv6 addresses must be written in brackets such as '[::1]:80', found '::1:80'"
+        );
+        assert_eq!(
+            error("[servers.api]\nlisten = 1234"),
+            "Config line 1 - Server 'api' is missing 'remote'"
+        );
+        assert_eq!(
+            error("[servers.api]\nlisten = 1234\nremote = \"a:1\"\ntimeot = 4"),
+            "Config line 4, column 1 - Unknown option 'timeot'"
+        );
+        assert_eq!(
+            error("[defaults]\ntimeout = 0\n[servers.api]\nlisten = 1234\nremote = \"a:1\""),
+            "Config line 2, column 11 - 'timeout' must be greater than 0, found 0"
+        );
+        assert_eq!(
+            error("[servers.a]\nlisten = 1\nremote = \"a:1\"\n[servers.b]\nlisten = \"127.0.0.1:1\"\nremote = \"a:1\""),
+            "Config line 5, column 10 - 'listen' address '127.0.0.1:1' is already used by server 'a'"
+        );
+        assert_eq!(
+            error("[servers.api]\nlisten = 1234\nremote = \"a:1\"\noverload = \"drop\""),
+            "Config line 4, column 12 - 'overload' is invalid - Unknown overload policy 'drop', expected 'queue', 'reject' or 'close'"
+        );
+        assert_eq!(
+            error("[servers.api]\nlisten = 1234\nremote = \"a:1\"\nlog_format = \"xml\""),
+            "Config line 4, column 14 - 'log_format' is invalid - Unknown log format 'xml', expected 'common', 'combined' or 'json'"
+        );
+        assert_eq!(
+            error("[servers.api]\nlisten = 1234\nremote = \"a:1\"\nproxy_deny = [\"a.test:x\"]"),
+            "Config line 4, column 14 - 'proxy_deny' is invalid - Invalid host rule 'a.test:x' - invalid port - invalid digit found in string"
+        );
+        assert_eq!(
+            error("[servers.api]\nlisten = 1234\nremote = \"a:1\"\nproxy_allow = \"a.test\""),
+            "Config line 4, column 15 - 'proxy_allow' must be an array of strings, found string"
+        );
+        assert_eq!(
+            error("[servers]\napi = { listen = 1234, remote = \"a:1\", replay_match_headers = [1] }"),
+            "Config line 2, column 63 - 'replay_match_headers' must be an array of strings, found integer in the array"
+        );
+        assert_eq!(
+            error("[server.api]"),
+            "Config line 1 - Unknown table 'server.api', expected [servers.<name>] or [defaults]"
+        );
+        assert_eq!(
+            error("# Nothing"),
+            "Config - At least one [servers.<name>] table must be defined"
+        );
+    }
+
+    #[test]
+    fn test_load_options_from_args() {
+        let args = |args: &[&str]| LoadOptions::from_args(args.iter().map(|a| a.to_string()));
+
+        assert_eq!(
+            args(&["--config", "/etc/monitor.toml", "--check"]).expect("Failed to read args"),
+            LoadOptions {
+                path: Some(PathBuf::from("/etc/monitor.toml")),
+                check: true,
+            }
+        );
+        assert_eq!(
+            args(&["--config=monitor.txt"])
+                .expect("Failed to read args")
+                .path,
+            Some(PathBuf::from("monitor.txt"))
+        );
+        assert!(args(&["--config"]).is_err());
+        assert!(args(&["--verbose"]).is_err());
+    }
+
+    #[test]
+    fn test_apply_env() {
+        let mut config = Config::from_toml(
+            "[servers.api-v1]\nlisten = 1234\nremote = \"127.0.0.1:80\"\n[servers.admin]\nlisten = 1235\nremote = \"127.0.0.1:81\"\n",
+        )
+        .expect("Failed to parse config");
+
+        let vars = [
+            ("TCP_IP_MONITOR_TIMEOUT", "10"),
+            ("TCP_IP_MONITOR_LOG", "false"),
+            ("TCP_IP_MONITOR_API_V1_TIMEOUT", "20"),
+            ("TCP_IP_MONITOR_ADMIN_REMOTE", "[::1]:82"),
+            ("TCP_IP_MONITOR_ADMIN_PROXY_DENY", "[\"*:25\", \"a.test\"]"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect::<HashMap<_, _>>();
+
+        config
+            .apply_env(|key| vars.get(key).cloned())
+            .expect("Failed to apply environment");
+
+        assert_eq!(config.servers[0].timeout, 20);
+        assert_eq!(config.servers[1].timeout, 10);
+        assert!(!config.servers[0].log);
+        assert_eq!(config.servers[1].remote_address, Address::new("::1", 82));
+        assert_eq!(config.servers[1].forward_proxy.destinations.deny.len(), 2);
+
+        let e = config
+            .apply_env(|key| {
+                if key == "TCP_IP_MONITOR_MAX_REQUESTS" {
+                    Some("lots".to_owned())
+                } else {
+                    None
+                }
+            })
+            .expect_err("Applied invalid environment");
+
+        assert_eq!(
+            e.to_string(),
+            "Config - Environment variable 'TCP_IP_MONITOR_MAX_REQUESTS' - 'max_requests' must be an integer, found string"
+        );
+    }
+
+    #[test]
+    fn test_read_and_check() {
+        let dir = std::env::temp_dir().join(format!("http_lib_config_{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("Failed to create directory");
+
+        let toml_path = dir.join("monitor.toml");
+        fs::write(
+            &toml_path,
+            "[servers.api]\nlisten = 0\nremote = \"127.0.0.1:80\"\n",
+        )
+        .expect("Failed to write config");
+
+        let legacy_path = dir.join("monitor.txt");
+        fs::write(&legacy_path, "0 127.0.0.1:80\n").expect("Failed to write config");
+
+        for path in &[&toml_path, &legacy_path] {
+            let config = Config::load_with(&LoadOptions {
+                path: Some(path.to_path_buf()),
+                check: true,
+            })
+            .expect("Failed to check config");
+
+            assert_eq!(config.servers.len(), 1);
+        }
+
+        // A missing file is an error in check mode rather than a template being created
+        let missing_path = dir.join("missing.toml");
+
+        assert!(Config::load_with(&LoadOptions {
+            path: Some(missing_path.clone()),
+            check: true,
+        })
+        .is_err());
+        assert!(!missing_path.exists());
+
+        // Log files are only checked for a directory to be created in
+        let log_path = dir.join("missing").join("access.log");
+
+        fs::write(
+            &toml_path,
+            format!(
+                "[servers.api]\nlisten = 0\nremote = \"127.0.0.1:80\"\nlog_file = '{}'\n",
+                log_path.display()
+            ),
+        )
+        .expect("Failed to write config");
+
+        assert!(Config::load_with(&LoadOptions {
+            path: Some(toml_path.clone()),
+            check: true,
+        })
+        .is_err());
+        assert!(!log_path.exists());
+
+        fs::write(
+            &toml_path,
+            format!(
+                "[servers.api]\nlisten = 0\nremote = \"127.0.0.1:80\"\nhar_file = '{}'\n",
+                dir.join("missing").join("capture.har").display()
+            ),
+        )
+        .expect("Failed to write config");
+
+        let e = Config::load_with(&LoadOptions {
+            path: Some(toml_path.clone()),
+            check: true,
+        })
+        .err()
+        .expect("Checked HAR file in a missing directory");
+
+        assert!(e.to_string().contains("can't write its HAR file"));
+
+        fs::remove_dir_all(&dir).expect("Failed to remove directory");
+    }
+
+    #[test]
+    fn test_template() {
+        let example = CONFIG_TEMPLATE
+            .lines()
+            .skip_while(|l| *l != "# Example:")
+            .skip(1)
+            .map(|l| l.trim_start_matches("# "))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let config = Config::from_toml(&format!("{}\n{}", CONFIG_TEMPLATE, example))
+            .expect("Failed to parse template");
+
+        assert_eq!(config.servers.len(), 1);
+        assert_eq!(config.servers[0].timeout, 10);
+    }
+}
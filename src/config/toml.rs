@@ -0,0 +1,745 @@
+use crate::error::TcpIpError;
+use crate::Result;
+
+/// A value in a config file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    /// An inline table inside an array. Inline tables given to a key are moved
+    /// into the `Table`s they define, the same as dotted keys.
+    Table(Vec<Entry>),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+}
+
+/// A `key = value` pair, with the position of the key and of the value.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+    pub key_column: usize,
+    pub value_column: usize,
+}
+
+impl Entry {
+    /// Creates an entry from an environment variable, which is a boolean, an
+    /// integer or an array if it can be parsed as one and a string otherwise.
+    pub fn from_env(key: &str, value: &str) -> Self {
+        let value = match value {
+            "true" => Value::Boolean(true),
+            "false" => Value::Boolean(false),
+            _ if value.starts_with('[') => {
+                parse_value(value).unwrap_or_else(|_| Value::String(value.to_owned()))
+            }
+            _ => value
+                .parse::<i64>()
+                .map(Value::Integer)
+                .unwrap_or_else(|_| Value::String(value.to_owned())),
+        };
+
+        Self {
+            key: key.to_owned(),
+            value,
+            line: 0,
+            key_column: 0,
+            value_column: 0,
+        }
+    }
+
+    /// Returns an error pointing at the value of this entry.
+    pub fn error<T: AsRef<str>>(&self, message: T) -> TcpIpError {
+        TcpIpError::config(format!("'{}' {}", self.key, message.as_ref()))
+            .at_column(self.line, self.value_column)
+    }
+
+    pub fn as_str(&self) -> Result<&str> {
+        match &self.value {
+            Value::String(s) => Ok(s),
+            v => Err(self.error(format!("must be a string, found {}", v.type_name()))),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool> {
+        match self.value {
+            Value::Boolean(b) => Ok(b),
+            ref v => Err(self.error(format!("must be a boolean, found {}", v.type_name()))),
+        }
+    }
+
+    /// Returns the value as a positive integer that fits in `T`.
+    pub fn as_positive<T: std::convert::TryFrom<i64>>(&self) -> Result<T> {
+        match self.value {
+            Value::Integer(i) if i > 0 => {
+                T::try_from(i).map_err(|_| self.error(format!("{} is too large", i)))
+            }
+            Value::Integer(i) => Err(self.error(format!("must be greater than 0, found {}", i))),
+            ref v => Err(self.error(format!("must be an integer, found {}", v.type_name()))),
+        }
+    }
+
+    pub fn as_strings(&self) -> Result<Vec<&str>> {
+        let error =
+            |found: &str| self.error(format!("must be an array of strings, found {}", found));
+
+        match &self.value {
+            Value::Array(values) => values
+                .iter()
+                .map(|v| match v {
+                    Value::String(s) => Ok(s.as_str()),
+                    v => Err(error(&format!("{} in the array", v.type_name()))),
+                })
+                .collect(),
+            v => Err(error(v.type_name())),
+        }
+    }
+}
+
+/// A `[table]` and the entries below it. Entries before the first table
+/// header belong to a table with an empty name.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Table {
+    pub name: Vec<String>,
+    pub line: usize,
+    pub entries: Vec<Entry>,
+}
+
+/// Parses the subset of TOML used by config files.
+///
+/// Tables, bare, quoted and dotted keys, strings, integers, booleans, arrays,
+/// inline tables and comments are supported. Floats, dates, multi-line strings
+/// and arrays of tables are not.
+///
+/// Dotted keys and inline tables define tables, so `a.b = 1` and `a = { b = 1 }`
+/// add the entry `b` to the table named `a` as `[a]` followed by `b = 1` would.
+pub fn parse(contents: &str) -> Result<Vec<Table>> {
+    let mut tables = vec![Table {
+        name: Vec::new(),
+        line: 0,
+        entries: Vec::new(),
+    }];
+    let mut current = Vec::new();
+    let mut cursor = Cursor::new(contents);
+
+    loop {
+        cursor.skip_whitespace();
+
+        match cursor.peek() {
+            None => return Ok(tables),
+            Some('#') | Some('\r') | Some('\n') => {}
+            Some('[') => {
+                let column = cursor.column();
+                let name = cursor.table_header()?;
+
+                if tables.iter().any(|t| t.name == name) {
+                    return Err(cursor.error_at(
+                        column,
+                        format!("Table '{}' is defined more than once", name.join(".")),
+                    ));
+                }
+
+                tables.push(Table {
+                    name: name.clone(),
+                    line: cursor.line,
+                    entries: Vec::new(),
+                });
+                current = name;
+            }
+            Some(_) => {
+                let entry = cursor.entry()?;
+
+                insert(&mut tables, &current, entry)?;
+            }
+        }
+
+        cursor.end_of_line()?;
+    }
+}
+
+// Parses a single value, such as an array given in an environment variable
+fn parse_value(s: &str) -> Result<Value> {
+    let mut cursor = Cursor::new(s);
+    let value = cursor.value()?;
+
+    cursor.end_of_line()?;
+
+    match cursor.peek() {
+        None => Ok(value),
+        Some(c) => Err(cursor.error(format!("Unexpected '{}'", c))),
+    }
+}
+
+// Adds `entry` to the table named `name`, moving the entries of an inline table
+// or dotted key into the table they define
+fn insert(tables: &mut Vec<Table>, name: &[String], entry: Entry) -> Result<()> {
+    let duplicate = || {
+        TcpIpError::config(format!("Key '{}' is defined more than once", entry.key))
+            .at_column(entry.line, entry.key_column)
+    };
+
+    let mut child = name.to_vec();
+    child.push(entry.key.clone());
+
+    let table = tables
+        .iter()
+        .position(|t| t.name == name)
+        .expect("Tables are added before their entries");
+
+    if tables[table].entries.iter().any(|e| e.key == entry.key) {
+        return Err(duplicate());
+    }
+
+    match entry.value {
+        Value::Table(entries) => {
+            if !tables.iter().any(|t| t.name == child) {
+                tables.push(Table {
+                    name: child.clone(),
+                    line: entry.line,
+                    entries: Vec::new(),
+                });
+            }
+
+            for entry in entries {
+                insert(tables, &child, entry)?;
+            }
+
+            Ok(())
+        }
+        _ if tables.iter().any(|t| t.name == child) => Err(duplicate()),
+        _ => {
+            tables[table].entries.push(entry);
+
+            Ok(())
+        }
+    }
+}
+
+struct Cursor {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    line_start: usize,
+}
+
+impl Cursor {
+    fn new(contents: &str) -> Self {
+        Self {
+            chars: contents.chars().collect(),
+            pos: 0,
+            line: 1,
+            line_start: 0,
+        }
+    }
+
+    fn column(&self) -> usize {
+        self.pos - self.line_start + 1
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn error<T: AsRef<str>>(&self, message: T) -> TcpIpError {
+        self.error_at(self.column(), message)
+    }
+
+    fn error_at<T: AsRef<str>>(&self, column: usize, message: T) -> TcpIpError {
+        TcpIpError::config(message).at_column(self.line, column)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.pos += 1;
+            }
+        }
+    }
+
+    // Moves to the start of the next line, returning false if not at the end of one
+    fn line_ending(&mut self) -> bool {
+        match self.peek() {
+            Some('\n') => {}
+            Some('\r') if self.chars.get(self.pos + 1) == Some(&'\n') => self.pos += 1,
+            _ => return false,
+        }
+
+        self.pos += 1;
+        self.line += 1;
+        self.line_start = self.pos;
+
+        true
+    }
+
+    // Skips whitespace, comments and line endings, which may separate the values of an array
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_whitespace();
+            self.skip_comment();
+
+            if !self.line_ending() {
+                return;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(c) => Err(self.error(format!("Expected '{}', found '{}'", expected, c))),
+            None => Err(self.error(format!("Expected '{}'", expected))),
+        }
+    }
+
+    // Only whitespace or a comment may follow a table header or an entry
+    fn end_of_line(&mut self) -> Result<()> {
+        self.skip_whitespace();
+        self.skip_comment();
+
+        match self.peek() {
+            None => Ok(()),
+            Some(_) if self.line_ending() => Ok(()),
+            Some(c) => Err(self.error(format!("Unexpected '{}'", c))),
+        }
+    }
+
+    fn table_header(&mut self) -> Result<Vec<String>> {
+        self.expect('[')?;
+
+        if self.peek() == Some('[') {
+            return Err(self.error("Arrays of tables are not supported"));
+        }
+
+        let mut name = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            name.push(self.key()?);
+            self.skip_whitespace();
+
+            match self.next() {
+                Some('.') => continue,
+                Some(']') => return Ok(name),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("Expected '.' or ']' in table header"));
+                }
+            }
+        }
+    }
+
+    fn entry(&mut self) -> Result<Entry> {
+        let line = self.line;
+        let mut keys = vec![(self.column(), self.key()?)];
+
+        self.skip_whitespace();
+
+        while self.peek() == Some('.') {
+            self.pos += 1;
+            self.skip_whitespace();
+            keys.push((self.column(), self.key()?));
+            self.skip_whitespace();
+        }
+
+        self.expect('=')?;
+        self.skip_whitespace();
+
+        let value_column = self.column();
+        let value = self.value()?;
+
+        // `a.b = 1` is the same as `a = { b = 1 }`
+        let (key_column, key) = keys.pop().expect("There is always a key");
+        let mut entry = Entry {
+            key,
+            value,
+            line,
+            key_column,
+            value_column,
+        };
+
+        while let Some((key_column, key)) = keys.pop() {
+            entry = Entry {
+                key,
+                value: Value::Table(vec![entry]),
+                line,
+                key_column,
+                value_column,
+            };
+        }
+
+        Ok(entry)
+    }
+
+    fn key(&mut self) -> Result<String> {
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let start = self.pos;
+
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                {
+                    self.pos += 1;
+                }
+
+                if start == self.pos {
+                    Err(self.error("Expected a key"))
+                } else {
+                    Ok(self.chars[start..self.pos].iter().collect())
+                }
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.peek() {
+            Some('"') => {
+                if self.chars[self.pos..].starts_with(&['"', '"', '"']) {
+                    return Err(self.error("Multi-line strings are not supported"));
+                }
+
+                Ok(Value::String(self.basic_string()?))
+            }
+            Some('\'') => Ok(Value::String(self.literal_string()?)),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some(_) => {
+                let start = self.pos;
+
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+')
+                {
+                    self.pos += 1;
+                }
+
+                let word = self.chars[start..self.pos].iter().collect::<String>();
+
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    "" => Err(self.error("Expected a value")),
+                    _ => word
+                        .replace('_', "")
+                        .parse::<i64>()
+                        .map(Value::Integer)
+                        .map_err(|_| {
+                            self.error_at(
+                                start - self.line_start + 1,
+                                format!("Invalid value '{}'", word),
+                            )
+                        }),
+                }
+            }
+            None => Err(self.error("Expected a value")),
+        }
+    }
+
+    // Arrays may span lines and end with a comma
+    fn array(&mut self) -> Result<Value> {
+        self.expect('[')?;
+
+        let mut values = Vec::new();
+
+        loop {
+            self.skip_blank();
+
+            if self.peek() == Some(']') {
+                self.pos += 1;
+                return Ok(Value::Array(values));
+            }
+
+            values.push(self.value()?);
+            self.skip_blank();
+
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {}
+                _ => return Err(self.error("Expected ',' or ']' in array")),
+            }
+        }
+    }
+
+    // Inline tables must be on one line and may not end with a comma
+    fn inline_table(&mut self) -> Result<Value> {
+        self.expect('{')?;
+        self.skip_whitespace();
+
+        let mut entries = Vec::<Entry>::new();
+
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Value::Table(entries));
+        }
+
+        loop {
+            let entry = self.entry()?;
+
+            if entries.iter().any(|e| e.key == entry.key) && !matches!(entry.value, Value::Table(_))
+            {
+                return Err(self.error_at(
+                    entry.key_column,
+                    format!("Key '{}' is defined more than once", entry.key),
+                ));
+            }
+
+            entries.push(entry);
+            self.skip_whitespace();
+
+            match self.next() {
+                Some(',') => self.skip_whitespace(),
+                Some('}') => return Ok(Value::Table(entries)),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("Expected ',' or '}' in inline table"));
+                }
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String> {
+        let start = self.column();
+
+        self.expect('"')?;
+
+        let mut s = String::new();
+
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('u') => self.unicode_escape()?,
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error("Invalid escape sequence"));
+                        }
+                    };
+
+                    s.push(c);
+                }
+                Some('\n') | None => {
+                    self.pos -= 1;
+                    return Err(self.error_at(start, "Unterminated string"));
+                }
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self) -> Result<char> {
+        let column = self.column();
+        let end = (self.pos + 4).min(self.chars.len());
+        let hex = self.chars[self.pos..end].iter().collect::<String>();
+
+        self.pos = end;
+
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .filter(|_| hex.len() == 4)
+            .and_then(std::char::from_u32)
+            .ok_or_else(|| self.error_at(column, "Invalid unicode escape"))
+    }
+
+    fn literal_string(&mut self) -> Result<String> {
+        let column = self.column();
+
+        self.expect('\'')?;
+
+        let start = self.pos;
+
+        while let Some(c) = self.peek().filter(|c| *c != '\n') {
+            self.pos += 1;
+
+            if c == '\'' {
+                return Ok(self.chars[start..self.pos - 1].iter().collect());
+            }
+        }
+
+        Err(self.error_at(column, "Unterminated string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::toml::{parse, Value};
+
+    #[test]
+    fn test_parse() {
+        let tables = parse(
+            "# Servers\nname = \"monitor\"\n\n[servers.\"api v1\"]\nlisten = '0.0.0.0:80' # Public\ntimeout = 1_000\nlog = false\nremote = \"a\\u0062c\\\"\"\n",
+        )
+        .expect("Failed to parse");
+
+        assert_eq!(tables.len(), 2);
+        assert_eq!(
+            tables[0].entries[0].value,
+            Value::String("monitor".to_owned())
+        );
+
+        let table = &tables[1];
+
+        assert_eq!(table.name, ["servers", "api v1"]);
+        assert_eq!(table.line, 4);
+
+        let values = table
+            .entries
+            .iter()
+            .map(|e| (e.key.as_str(), e.value.clone()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            values,
+            [
+                ("listen", Value::String("0.0.0.0:80".to_owned())),
+                ("timeout", Value::Integer(1000)),
+                ("log", Value::Boolean(false)),
+                ("remote", Value::String("abc\"".to_owned())),
+            ]
+        );
+        assert_eq!(table.entries[1].value_column, 11);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |contents: &str| {
+            parse(contents)
+                .expect_err("Parsed invalid config")
+                .to_string()
+        };
+
+        assert_eq!(
+            error("[servers.a]\ntimeout = 4s"),
+            "Config line 2, column 11 - Invalid value '4s'"
+        );
+        assert_eq!(
+            error("a = 1\na = 2"),
+            "Config line 2, column 1 - Key 'a' is defined more than once"
+        );
+        assert_eq!(
+            error("[a]\n[a]"),
+            "Config line 2, column 1 - Table 'a' is defined more than once"
+        );
+        assert_eq!(
+            error("a = \"open"),
+            "Config line 1, column 5 - Unterminated string"
+        );
+        assert_eq!(error("a = 1 2"), "Config line 1, column 7 - Unexpected '2'");
+        assert_eq!(
+            error("a = [1 2]"),
+            "Config line 1, column 8 - Expected ',' or ']' in array"
+        );
+        assert_eq!(
+            error("a = [\n  1,\n  \"x\n]"),
+            "Config line 3, column 3 - Unterminated string"
+        );
+        assert_eq!(
+            error("a.b = 1\n[a]"),
+            "Config line 2, column 1 - Table 'a' is defined more than once"
+        );
+        assert_eq!(
+            error("a.b = 1\na = { b = 2 }"),
+            "Config line 2, column 7 - Key 'b' is defined more than once"
+        );
+        assert_eq!(
+            error("a = 1\na.b = 2"),
+            "Config line 2, column 1 - Key 'a' is defined more than once"
+        );
+        assert_eq!(
+            error("[[a]]"),
+            "Config line 1, column 2 - Arrays of tables are not supported"
+        );
+    }
+
+    #[test]
+    fn test_arrays_and_tables() {
+        let tables = parse(
+            "a = [\"x\", 'y',] # Comment\nb = [\n  1, # One\n\n  [true],\n  { c = 2 },\n]\n\n[servers]\napi.timeout = 1\nweb = { log = false, remote.port = 80 }\nend = 1\n",
+        )
+        .expect("Failed to parse");
+
+        let names = tables.iter().map(|t| t.name.join(".")).collect::<Vec<_>>();
+
+        assert_eq!(
+            names,
+            [
+                "",
+                "servers",
+                "servers.api",
+                "servers.web",
+                "servers.web.remote"
+            ]
+        );
+        assert_eq!(
+            tables[0].entries[0].value,
+            Value::Array(vec![
+                Value::String("x".to_owned()),
+                Value::String("y".to_owned())
+            ])
+        );
+
+        match &tables[0].entries[1].value {
+            Value::Array(values) => {
+                assert_eq!(values[0], Value::Integer(1));
+                assert_eq!(values[1], Value::Array(vec![Value::Boolean(true)]));
+                assert!(matches!(&values[2], Value::Table(entries) if entries[0].key == "c"));
+            }
+            v => panic!("Expected an array, found {:?}", v),
+        }
+
+        let entries = |table: &str| {
+            tables
+                .iter()
+                .find(|t| t.name.join(".") == table)
+                .expect("Missing table")
+                .entries
+                .iter()
+                .map(|e| (e.key.as_str(), e.value.clone(), e.line, e.value_column))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(entries("servers"), [("end", Value::Integer(1), 12, 7)]);
+        assert_eq!(
+            entries("servers.api"),
+            [("timeout", Value::Integer(1), 10, 15)]
+        );
+        assert_eq!(
+            entries("servers.web"),
+            [("log", Value::Boolean(false), 11, 15)]
+        );
+        assert_eq!(
+            entries("servers.web.remote"),
+            [("port", Value::Integer(80), 11, 36)]
+        );
+    }
+}
@@ -1,13 +1,89 @@
 use std::fmt::Formatter;
-use std::io::Error;
+use std::io::{Error, ErrorKind};
 
 use crate::convert_error;
+use crate::response::response_status::ResponseStatus;
 
-#[derive(Eq, PartialEq)]
 pub enum TcpIpError {
-    DataTimeout,
-    TcpTimeout,
-    Other(String),
+    /// An I/O error on a socket or file, including read and write timeouts.
+    Io(Error),
+    /// The peer closed the connection before sending a new message.
+    ConnectionClosed,
+    /// The peer timed out or closed the connection part way through sending a header.
+    IncompleteHeader {
+        received: usize,
+        source: Error,
+    },
+    /// A message could not be parsed, `position` is the byte offset of the
+    /// invalid part within the header if it is known.
+    Parse {
+        message: String,
+        position: Option<usize>,
+    },
+    HeaderTooLarge {
+        limit: usize,
+    },
+    BodyTooLarge {
+        limit: u64,
+    },
+    /// The client stopped sending part way through a request.
+    ClientTimeout,
+    /// Reading the body of a request from the client failed, other than by timing
+    /// out, while it was being forwarded.
+    IncompleteBody {
+        source: Error,
+    },
+    /// Every worker was busy and the accept backlog was full.
+    Overloaded {
+        max_connections: usize,
+    },
+    UpstreamConnect {
+        address: String,
+        source: Error,
+    },
+    UpstreamTimeout,
+    /// The remote server closed the connection before sending any of a response,
+    /// as it does with idle connections, so the request can be sent again.
+    UpstreamClosed,
+    /// The remote server sent an invalid response or closed the connection.
+    UpstreamResponse(Box<TcpIpError>),
+    /// A client request was still being redirected after `limit` redirects.
+    TooManyRedirects {
+        limit: usize,
+    },
+    /// A server replaying recorded exchanges has no response for a request.
+    NotRecorded {
+        method: String,
+        uri: String,
+    },
+    /// The path of a request matches no route of a router.
+    NoRoute {
+        method: String,
+        uri: String,
+    },
+    /// The path of a request only matches routes for the `allowed` methods.
+    MethodNotAllowed {
+        method: String,
+        uri: String,
+        allowed: Vec<String>,
+    },
+    HandlerPanicked {
+        method: String,
+        uri: String,
+    },
+    /// A forward proxy's allow and deny rules don't permit the server a request names.
+    DestinationDenied {
+        address: String,
+    },
+    /// Invalid configuration, `line` and `column` give the position in the
+    /// config file if they are known.
+    Config {
+        message: String,
+        line: Option<usize>,
+        column: Option<usize>,
+    },
+    /// An API was used incorrectly, such as building a request without a method.
+    InvalidInput(String),
 }
 
 pub trait ErrorExt<T> {
@@ -16,53 +92,391 @@ pub trait ErrorExt<T> {
 
 impl<T> ErrorExt<T> for Option<T> {
     fn context(self, msg: &str) -> Result<T, TcpIpError> {
-        self.ok_or_else(|| TcpIpError::Other(msg.to_owned()))
+        self.ok_or_else(|| TcpIpError::InvalidInput(msg.to_owned()))
     }
 }
 
-impl std::error::Error for TcpIpError {}
+impl std::error::Error for TcpIpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TcpIpError::Io(e) => Some(e),
+            TcpIpError::IncompleteHeader { source, .. } => Some(source),
+            TcpIpError::IncompleteBody { source } => Some(source),
+            TcpIpError::UpstreamConnect { source, .. } => Some(source),
+            TcpIpError::UpstreamResponse(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl std::fmt::Display for TcpIpError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            TcpIpError::DataTimeout => write!(f, "Data Timed out"),
-            TcpIpError::TcpTimeout => write!(f, "TCP Socket Timed out"),
-            TcpIpError::Other(e) => write!(f, "{}", e),
+            TcpIpError::Io(e) => write!(f, "{}", e),
+            TcpIpError::ConnectionClosed => write!(f, "Connection closed"),
+            TcpIpError::IncompleteHeader { received, source } => write!(
+                f,
+                "Connection failed after receiving {} bytes of the header - {}",
+                received, source
+            ),
+            TcpIpError::Parse {
+                message,
+                position: Some(position),
+            } => write!(f, "{} at byte {}", message, position),
+            TcpIpError::Parse { message, .. } => write!(f, "{}", message),
+            TcpIpError::HeaderTooLarge { limit } => {
+                write!(f, "Header exceeds the limit of {} bytes", limit)
+            }
+            TcpIpError::BodyTooLarge { limit } => {
+                write!(f, "Body exceeds the limit of {} bytes", limit)
+            }
+            TcpIpError::ClientTimeout => write!(f, "Client Timed out"),
+            TcpIpError::IncompleteBody { source } => {
+                write!(
+                    f,
+                    "Failed to read the request body from the client - {}",
+                    source
+                )
+            }
+            TcpIpError::Overloaded { max_connections } => write!(
+                f,
+                "Server is busy with the limit of {} connections",
+                max_connections
+            ),
+            TcpIpError::UpstreamConnect { address, source } => {
+                write!(f, "Failed to connect to '{}' - {}", address, source)
+            }
+            TcpIpError::UpstreamTimeout => write!(f, "Remote server Timed out"),
+            TcpIpError::UpstreamClosed => {
+                write!(f, "Remote server closed the connection before responding")
+            }
+            TcpIpError::UpstreamResponse(e) => {
+                write!(f, "Invalid response from remote server - {}", e)
+            }
+            TcpIpError::TooManyRedirects { limit } => {
+                write!(f, "Stopped after following {} redirects", limit)
+            }
+            TcpIpError::NotRecorded { method, uri } => {
+                write!(f, "No recorded response matches '{} {}'", method, uri)
+            }
+            TcpIpError::NoRoute { method, uri } => {
+                write!(f, "No route matches '{} {}'", method, uri)
+            }
+            TcpIpError::MethodNotAllowed {
+                method,
+                uri,
+                allowed,
+            } => write!(
+                f,
+                "'{}' only allows {}, not {}",
+                uri,
+                allowed.join(", "),
+                method
+            ),
+            TcpIpError::HandlerPanicked { method, uri } => {
+                write!(f, "The handler for '{} {}' panicked", method, uri)
+            }
+            TcpIpError::DestinationDenied { address } => {
+                write!(f, "Forwarding to '{}' is not allowed", address)
+            }
+            TcpIpError::Config {
+                message,
+                line: Some(line),
+                column: Some(column),
+            } => write!(f, "Config line {}, column {} - {}", line, column, message),
+            TcpIpError::Config {
+                message,
+                line: Some(line),
+                ..
+            } => write!(f, "Config line {} - {}", line, message),
+            TcpIpError::Config { message, .. } => write!(f, "Config - {}", message),
+            TcpIpError::InvalidInput(message) => write!(f, "{}", message),
         }
     }
 }
 
+// I/O errors can't be compared, so they are equal if they are of the same kind
+impl PartialEq for TcpIpError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (TcpIpError::Io(a), TcpIpError::Io(b)) => a.kind() == b.kind(),
+            (
+                TcpIpError::IncompleteHeader {
+                    received: a,
+                    source: a_source,
+                },
+                TcpIpError::IncompleteHeader {
+                    received: b,
+                    source: b_source,
+                },
+            ) => a == b && a_source.kind() == b_source.kind(),
+            (
+                TcpIpError::Parse {
+                    message: a,
+                    position: a_position,
+                },
+                TcpIpError::Parse {
+                    message: b,
+                    position: b_position,
+                },
+            ) => a == b && a_position == b_position,
+            (TcpIpError::HeaderTooLarge { limit: a }, TcpIpError::HeaderTooLarge { limit: b }) => {
+                a == b
+            }
+            (TcpIpError::BodyTooLarge { limit: a }, TcpIpError::BodyTooLarge { limit: b }) => {
+                a == b
+            }
+            (
+                TcpIpError::IncompleteBody { source: a },
+                TcpIpError::IncompleteBody { source: b },
+            ) => a.kind() == b.kind(),
+            (
+                TcpIpError::Overloaded { max_connections: a },
+                TcpIpError::Overloaded { max_connections: b },
+            ) => a == b,
+            (
+                TcpIpError::UpstreamConnect {
+                    address: a,
+                    source: a_source,
+                },
+                TcpIpError::UpstreamConnect {
+                    address: b,
+                    source: b_source,
+                },
+            ) => a == b && a_source.kind() == b_source.kind(),
+            (TcpIpError::UpstreamResponse(a), TcpIpError::UpstreamResponse(b)) => a == b,
+            (
+                TcpIpError::TooManyRedirects { limit: a },
+                TcpIpError::TooManyRedirects { limit: b },
+            ) => a == b,
+            (
+                TcpIpError::NotRecorded {
+                    method: a,
+                    uri: a_uri,
+                },
+                TcpIpError::NotRecorded {
+                    method: b,
+                    uri: b_uri,
+                },
+            )
+            | (
+                TcpIpError::NoRoute {
+                    method: a,
+                    uri: a_uri,
+                },
+                TcpIpError::NoRoute {
+                    method: b,
+                    uri: b_uri,
+                },
+            )
+            | (
+                TcpIpError::HandlerPanicked {
+                    method: a,
+                    uri: a_uri,
+                },
+                TcpIpError::HandlerPanicked {
+                    method: b,
+                    uri: b_uri,
+                },
+            ) => a == b && a_uri == b_uri,
+            (
+                TcpIpError::MethodNotAllowed {
+                    method: a,
+                    uri: a_uri,
+                    allowed: a_allowed,
+                },
+                TcpIpError::MethodNotAllowed {
+                    method: b,
+                    uri: b_uri,
+                    allowed: b_allowed,
+                },
+            ) => a == b && a_uri == b_uri && a_allowed == b_allowed,
+            (
+                TcpIpError::DestinationDenied { address: a },
+                TcpIpError::DestinationDenied { address: b },
+            ) => a == b,
+            (
+                TcpIpError::Config {
+                    message: a,
+                    line: a_line,
+                    column: a_column,
+                },
+                TcpIpError::Config {
+                    message: b,
+                    line: b_line,
+                    column: b_column,
+                },
+            ) => a == b && a_line == b_line && a_column == b_column,
+            (TcpIpError::InvalidInput(a), TcpIpError::InvalidInput(b)) => a == b,
+            (TcpIpError::ConnectionClosed, TcpIpError::ConnectionClosed)
+            | (TcpIpError::ClientTimeout, TcpIpError::ClientTimeout)
+            | (TcpIpError::UpstreamTimeout, TcpIpError::UpstreamTimeout)
+            | (TcpIpError::UpstreamClosed, TcpIpError::UpstreamClosed) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for TcpIpError {}
+
 impl std::fmt::Debug for TcpIpError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_string())
+        write!(f, "{}", self)
     }
 }
 
 impl TcpIpError {
-    pub fn new<T: AsRef<str>>(msg: T) -> Self {
-        TcpIpError::Other(msg.as_ref().to_owned())
+    pub fn parse<T: AsRef<str>>(message: T) -> Self {
+        TcpIpError::Parse {
+            message: message.as_ref().to_owned(),
+            position: None,
+        }
+    }
+
+    pub fn parse_at<T: AsRef<str>>(message: T, position: usize) -> Self {
+        TcpIpError::Parse {
+            message: message.as_ref().to_owned(),
+            position: Some(position),
+        }
+    }
+
+    /// Sets the byte offset on a parse error that doesn't have one.
+    pub fn at_position(self, position: usize) -> Self {
+        match self {
+            TcpIpError::Parse {
+                message,
+                position: None,
+            } => TcpIpError::Parse {
+                message,
+                position: Some(position),
+            },
+            e => e,
+        }
+    }
+
+    pub fn config<T: AsRef<str>>(message: T) -> Self {
+        TcpIpError::Config {
+            message: message.as_ref().to_owned(),
+            line: None,
+            column: None,
+        }
+    }
+
+    /// Sets the config file line number on a config error, other errors are
+    /// converted to config errors at that line.
+    pub fn at_line(self, line: usize) -> Self {
+        self.at_position_in_config(line, None)
+    }
+
+    /// Sets the config file line and column on a config error, other errors are
+    /// converted to config errors at that position.
+    pub fn at_column(self, line: usize, column: usize) -> Self {
+        self.at_position_in_config(line, Some(column))
+    }
+
+    fn at_position_in_config(self, line: usize, column: Option<usize>) -> Self {
+        let message = match self {
+            TcpIpError::Config { message, .. } => message,
+            e => e.to_string(),
+        };
+
+        TcpIpError::Config {
+            message,
+            line: Some(line),
+            column,
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            TcpIpError::Io(e) | TcpIpError::IncompleteHeader { source: e, .. } => {
+                is_timeout_kind(e.kind())
+            }
+            TcpIpError::ClientTimeout | TcpIpError::UpstreamTimeout => true,
+            TcpIpError::UpstreamResponse(e) => e.is_timeout(),
+            _ => false,
+        }
+    }
+
+    /// Converts an error reading a request body from the client while forwarding it.
+    pub(crate) fn from_client_body(source: Error) -> Self {
+        if is_timeout_kind(source.kind()) {
+            TcpIpError::ClientTimeout
+        } else {
+            TcpIpError::IncompleteBody { source }
+        }
+    }
+
+    /// Converts an error that happened while talking to the remote server into the
+    /// matching upstream error. Errors reading the request body from the client
+    /// are left as they are.
+    pub fn into_upstream(self) -> Self {
+        match self {
+            e @ TcpIpError::UpstreamConnect { .. }
+            | e @ TcpIpError::UpstreamTimeout
+            | e @ TcpIpError::UpstreamClosed
+            | e @ TcpIpError::UpstreamResponse(_)
+            | e @ TcpIpError::ClientTimeout
+            | e @ TcpIpError::IncompleteBody { .. } => e,
+            e if e.is_timeout() => TcpIpError::UpstreamTimeout,
+            e => TcpIpError::UpstreamResponse(Box::new(e)),
+        }
+    }
+
+    /// Returns the status of the response a proxy should send to its client
+    /// when a request fails with this error.
+    pub fn to_status(&self) -> ResponseStatus {
+        match self {
+            TcpIpError::Io(_)
+            | TcpIpError::Config { .. }
+            | TcpIpError::InvalidInput(_)
+            | TcpIpError::HandlerPanicked { .. } => ResponseStatus::InternalServerError,
+            TcpIpError::ConnectionClosed => ResponseStatus::ClientClosedRequest,
+            TcpIpError::IncompleteHeader { .. } | TcpIpError::ClientTimeout => {
+                ResponseStatus::RequestTimeout
+            }
+            TcpIpError::Parse { .. } => ResponseStatus::BadRequest,
+            TcpIpError::IncompleteBody { source } if source.kind() == ErrorKind::InvalidData => {
+                ResponseStatus::BadRequest
+            }
+            TcpIpError::IncompleteBody { .. } => ResponseStatus::ClientClosedRequest,
+            TcpIpError::HeaderTooLarge { .. } => ResponseStatus::RequestHeaderFieldsTooLarge,
+            TcpIpError::BodyTooLarge { .. } => ResponseStatus::PayloadTooLarge,
+            TcpIpError::UpstreamConnect { .. }
+            | TcpIpError::UpstreamClosed
+            | TcpIpError::UpstreamResponse(_)
+            | TcpIpError::TooManyRedirects { .. } => ResponseStatus::BadGateway,
+            TcpIpError::UpstreamTimeout => ResponseStatus::GatewayTimeout,
+            TcpIpError::Overloaded { .. } => ResponseStatus::ServiceUnavailable,
+            TcpIpError::NotRecorded { .. } | TcpIpError::NoRoute { .. } => ResponseStatus::NotFound,
+            TcpIpError::MethodNotAllowed { .. } => ResponseStatus::MethodNotAllowed,
+            TcpIpError::DestinationDenied { .. } => ResponseStatus::Forbidden,
+        }
     }
 }
 
+fn is_timeout_kind(kind: ErrorKind) -> bool {
+    kind == ErrorKind::WouldBlock || kind == ErrorKind::TimedOut
+}
+
 impl From<std::io::Error> for TcpIpError {
     fn from(e: Error) -> Self {
-        let e_string = e.to_string();
-
-        if e.kind() == std::io::ErrorKind::WouldBlock
-            || e.kind() == std::io::ErrorKind::TimedOut
-            || e_string.contains("(os error 10053)")
-            || e_string.contains("(os error 10054)")
-        {
-            TcpIpError::TcpTimeout
-        } else {
-            TcpIpError::new(e_string)
+        match e.kind() {
+            ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => {
+                TcpIpError::ConnectionClosed
+            }
+            _ => TcpIpError::Io(e),
         }
     }
 }
 
-convert_error!(std::fmt::Error);
+impl From<std::fmt::Error> for TcpIpError {
+    fn from(e: std::fmt::Error) -> Self {
+        TcpIpError::Io(Error::other(e))
+    }
+}
+
 convert_error!(std::num::ParseIntError);
-convert_error!(std::num::ParseFloatError);
 convert_error!(std::net::AddrParseError);
 convert_error!(std::string::FromUtf8Error);
 convert_error!(std::array::TryFromSliceError);
@@ -72,8 +486,103 @@ macro_rules! convert_error {
     ($err:path) => {
         impl From<$err> for TcpIpError {
             fn from(e: $err) -> Self {
-                Self::new(e.to_string())
+                Self::parse(e.to_string())
             }
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::io::ErrorKind;
+
+    use crate::error::TcpIpError;
+    use crate::response::response_status::ResponseStatus;
+
+    #[test]
+    fn test_from_io_error() {
+        let e = TcpIpError::from(std::io::Error::from(ErrorKind::ConnectionReset));
+        assert!(matches!(e, TcpIpError::ConnectionClosed));
+
+        let e = TcpIpError::from(std::io::Error::from(ErrorKind::WouldBlock));
+        assert!(matches!(e, TcpIpError::Io(_)));
+        assert!(e.is_timeout());
+        assert!(e.source().is_some());
+    }
+
+    #[test]
+    fn test_eq() {
+        assert_eq!(
+            TcpIpError::from(std::io::Error::new(ErrorKind::TimedOut, "read timed out")),
+            TcpIpError::Io(std::io::Error::from(ErrorKind::TimedOut))
+        );
+        assert_ne!(
+            TcpIpError::Io(std::io::Error::from(ErrorKind::TimedOut)),
+            TcpIpError::Io(std::io::Error::from(ErrorKind::NotFound))
+        );
+        assert_eq!(
+            TcpIpError::parse_at("Invalid status code", 9).into_upstream(),
+            TcpIpError::UpstreamResponse(Box::new(TcpIpError::parse_at("Invalid status code", 9)))
+        );
+        assert_ne!(TcpIpError::ClientTimeout, TcpIpError::UpstreamTimeout);
+    }
+
+    #[test]
+    fn test_into_upstream() {
+        let e = TcpIpError::from(std::io::Error::from(ErrorKind::TimedOut)).into_upstream();
+        assert!(matches!(e, TcpIpError::UpstreamTimeout));
+        assert_eq!(e.to_status(), ResponseStatus::GatewayTimeout);
+
+        let e = TcpIpError::from_client_body(std::io::Error::from(ErrorKind::TimedOut));
+        assert!(matches!(e.into_upstream(), TcpIpError::ClientTimeout));
+
+        let e = TcpIpError::from_client_body(std::io::Error::from(ErrorKind::UnexpectedEof));
+        assert!(matches!(
+            e.into_upstream(),
+            TcpIpError::IncompleteBody { .. }
+        ));
+
+        let e = TcpIpError::UpstreamClosed.into_upstream();
+        assert_eq!(e, TcpIpError::UpstreamClosed);
+        assert_eq!(e.to_status(), ResponseStatus::BadGateway);
+
+        let e = TcpIpError::parse_at("Invalid status code", 9).into_upstream();
+        assert_eq!(e.to_status(), ResponseStatus::BadGateway);
+        assert_eq!(
+            e.source().map(|s| s.to_string()),
+            Some("Invalid status code at byte 9".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_to_status() {
+        assert_eq!(
+            TcpIpError::parse("Unknown request header method").to_status(),
+            ResponseStatus::BadRequest
+        );
+        assert_eq!(
+            TcpIpError::HeaderTooLarge { limit: 16 }.to_status(),
+            ResponseStatus::RequestHeaderFieldsTooLarge
+        );
+        assert_eq!(
+            TcpIpError::ClientTimeout.to_status(),
+            ResponseStatus::RequestTimeout
+        );
+        assert_eq!(
+            TcpIpError::Overloaded { max_connections: 1 }.to_status(),
+            ResponseStatus::ServiceUnavailable
+        );
+    }
+
+    #[test]
+    fn test_config_line() {
+        let e = TcpIpError::config("Missing remote address").at_line(3);
+
+        assert_eq!(e.to_string(), "Config line 3 - Missing remote address");
+
+        let e = TcpIpError::parse("Invalid port").at_column(4, 10);
+
+        assert_eq!(e.to_string(), "Config line 4, column 10 - Invalid port");
+    }
+}
@@ -0,0 +1,68 @@
+use crate::response::response_status::ResponseStatus;
+use crate::response::{Response, ResponseBuilder};
+use crate::Result;
+
+pub const DEFAULT_ERROR_TEMPLATE: &str = "{status_code} {reason_phrase}\n\n{message}\n";
+
+/// Template for the responses sent to clients when a request can't be forwarded.
+///
+/// `{status_code}`, `{reason_phrase}` and `{message}` in the template are
+/// replaced with the response status and a description of the error.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ErrorPage {
+    pub content_type: String,
+    pub template: String,
+}
+
+impl ErrorPage {
+    pub fn new<T: AsRef<str>, U: AsRef<str>>(content_type: T, template: U) -> Self {
+        Self {
+            content_type: content_type.as_ref().to_owned(),
+            template: template.as_ref().to_owned(),
+        }
+    }
+
+    pub fn render(&self, status: ResponseStatus, message: &str) -> Result<Response<'static>> {
+        let body = self
+            .template
+            .replace("{status_code}", &(status as u16).to_string())
+            .replace("{reason_phrase}", &status.to_string())
+            .replace("{message}", message);
+
+        ResponseBuilder::new()
+            .status_code(status as u16)
+            .header("Content-Type", &self.content_type)
+            .body(body.into_bytes())
+            .build()
+    }
+}
+
+impl Default for ErrorPage {
+    fn default() -> Self {
+        Self::new("text/plain; charset=utf-8", DEFAULT_ERROR_TEMPLATE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error_page::ErrorPage;
+    use crate::http_item::HttpItem;
+    use crate::response::response_status::ResponseStatus;
+
+    #[test]
+    fn test_render() {
+        let error_page = ErrorPage::new(
+            "text/html",
+            "<h1>{status_code} {reason_phrase}</h1><p>{message}</p>",
+        );
+
+        let response = error_page
+            .render(ResponseStatus::GatewayTimeout, "TCP Socket Timed out")
+            .expect("Failed to render error page");
+
+        assert_eq!(
+            response.as_string().expect("Failed to convert response to str"),
+            "HTTP/1.1 504 Gateway Timeout\r\nContent-Type: text/html\r\nContent-Length: 55\r\n\r\n<h1>504 Gateway Timeout</h1><p>TCP Socket Timed out</p>"
+        );
+    }
+}
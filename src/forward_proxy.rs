@@ -0,0 +1,451 @@
+//! Forwards each request to the server it names rather than to a fixed remote
+//! address, so clients can use the server as their HTTP proxy.
+//!
+//! The server is taken from an absolute-form target such as
+//! `GET http://example.com/ HTTP/1.1`, which is rewritten to origin-form before
+//! it is forwarded, or from the `Host` header. Which servers may be reached is
+//! limited with allow and deny rules, and deny rules for IP addresses are also
+//! checked against the addresses a name resolves to.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use crate::address::Address;
+use crate::error::TcpIpError;
+use crate::header_item::HeaderItem;
+use crate::request::request_header::RequestHeader;
+use crate::request::uri::TargetForm;
+use crate::Result;
+
+const HTTP_PORT: u16 = 80;
+
+/// Matches destinations by host and port.
+///
+/// Rules are written `host:port`, where the host may be `*` for any host or
+/// start with `*.` for any subdomain, and the port may be `*` or left out for
+/// any port.
+///
+/// Names are compared without case or a trailing dot. IP addresses are compared
+/// as addresses, so `127.1`, `0x7f000001` and `[::ffff:127.0.0.1]` all match a
+/// rule for `127.0.0.1`, but a name never matches an IP rule, see
+/// `Destinations::check_resolved` for that.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HostRule {
+    host: HostPattern,
+    port: Option<u16>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum HostPattern {
+    Any,
+    /// Subdomains of a name, stored with the leading '.'.
+    Suffix(String),
+    Name(String),
+    Ip(IpAddr),
+}
+
+impl HostRule {
+    pub fn matches(&self, address: &Address) -> bool {
+        let host_matches = match (&self.host, parse_host(&address.host)) {
+            (HostPattern::Any, _) => true,
+            (HostPattern::Ip(ip), Host::Ip(host)) => *ip == host,
+            (HostPattern::Name(name), Host::Name(host)) => *name == host,
+            (HostPattern::Suffix(suffix), Host::Name(host)) => {
+                host.len() > suffix.len() && host.ends_with(suffix.as_str())
+            }
+            _ => false,
+        };
+
+        host_matches && self.port_matches(address.port)
+    }
+
+    /// Returns true if the rule matches a socket address, which only rules for
+    /// any host or for an IP address do.
+    pub fn matches_socket(&self, address: &SocketAddr) -> bool {
+        let host_matches = match self.host {
+            HostPattern::Any => true,
+            HostPattern::Ip(ip) => ip == address.ip().to_canonical(),
+            HostPattern::Suffix(_) | HostPattern::Name(_) => false,
+        };
+
+        host_matches && self.port_matches(address.port())
+    }
+
+    fn port_matches(&self, port: u16) -> bool {
+        self.port.is_none_or(|p| p == port)
+    }
+}
+
+impl FromStr for HostRule {
+    type Err = TcpIpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid =
+            |message: &str| TcpIpError::parse(format!("Invalid host rule '{}' - {}", s, message));
+
+        let (host, port) = match s.strip_prefix('[') {
+            Some(ipv6) => {
+                let (host, rest) = ipv6.split_once(']').ok_or_else(|| invalid("missing ']'"))?;
+
+                match rest {
+                    "" => (host, None),
+                    _ => (
+                        host,
+                        Some(
+                            rest.strip_prefix(':')
+                                .ok_or_else(|| invalid("expected ':' after ']'"))?,
+                        ),
+                    ),
+                }
+            }
+            None => match s.rsplit_once(':') {
+                Some((host, _)) if host.contains(':') => {
+                    return Err(invalid("IPv6 addresses must be written in brackets"))
+                }
+                Some((host, port)) => (host, Some(port)),
+                None => (s, None),
+            },
+        };
+
+        let valid_host = !host.is_empty()
+            && host
+                .trim_start_matches('*')
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' || c == ':');
+
+        let host = match host.strip_prefix('*') {
+            _ if !valid_host => return Err(invalid("invalid host")),
+            Some("") => HostPattern::Any,
+            Some(suffix) if suffix.starts_with('.') => match parse_host(suffix) {
+                Host::Name(name) if name.len() > 1 => HostPattern::Suffix(name),
+                _ => return Err(invalid("invalid host")),
+            },
+            Some(_) => return Err(invalid("invalid host")),
+            None => match parse_host(host) {
+                Host::Ip(ip) => HostPattern::Ip(ip),
+                Host::Name(name) => HostPattern::Name(name),
+            },
+        };
+
+        let port = match port {
+            None | Some("*") => None,
+            Some(port) => Some(
+                port.parse::<u16>()
+                    .map_err(|e| invalid(&format!("invalid port - {}", e)))?,
+            ),
+        };
+
+        Ok(Self { host, port })
+    }
+}
+
+enum Host {
+    Name(String),
+    Ip(IpAddr),
+}
+
+// Normalizes a host for comparison, parsing IP addresses in any of the forms
+// resolvers accept, so a rule can't be bypassed by writing an address differently
+fn parse_host(host: &str) -> Host {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let host = host.strip_suffix('.').unwrap_or(host).to_ascii_lowercase();
+
+    if let Ok(ip) = host.parse::<Ipv6Addr>() {
+        return Host::Ip(IpAddr::V6(ip).to_canonical());
+    }
+
+    match parse_ipv4(&host) {
+        Some(ip) => Host::Ip(IpAddr::V4(ip)),
+        None => Host::Name(host),
+    }
+}
+
+// Parses the numbers-and-dots forms of `inet_aton`, such as `127.1`, `0x7f000001`
+// and `0177.0.0.1`, where the last number fills the remaining bytes
+fn parse_ipv4(host: &str) -> Option<Ipv4Addr> {
+    let parts = host
+        .split('.')
+        .map(|part| match part.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None if part.len() > 1 && part.starts_with('0') => {
+                u32::from_str_radix(&part[1..], 8).ok()
+            }
+            None if part.bytes().all(|b| b.is_ascii_digit()) => part.parse().ok(),
+            None => None,
+        })
+        .collect::<Option<Vec<u32>>>()?;
+
+    let (last, leading) = parts.split_last()?;
+
+    if leading.len() > 3 || leading.iter().any(|&part| part > 0xff) {
+        return None;
+    }
+
+    let last_bits = 32 - 8 * leading.len() as u32;
+
+    if last_bits < 32 && *last >= 1 << last_bits {
+        return None;
+    }
+
+    let ip = leading
+        .iter()
+        .enumerate()
+        .fold(*last, |ip, (i, &part)| ip | part << (24 - 8 * i));
+
+    Some(Ipv4Addr::from(ip))
+}
+
+/// The servers requests may be forwarded to, which are those that match no
+/// `deny` rule and, if there are any `allow` rules, match one of those.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Destinations {
+    pub allow: Vec<HostRule>,
+    pub deny: Vec<HostRule>,
+}
+
+impl Destinations {
+    pub fn is_allowed(&self, address: &Address) -> bool {
+        !self.deny.iter().any(|rule| rule.matches(address))
+            && (self.allow.is_empty() || self.allow.iter().any(|rule| rule.matches(address)))
+    }
+
+    /// Checks the socket addresses an allowed `address` resolved to against the
+    /// `deny` rules, so a name can't be used to reach a denied IP address.
+    ///
+    /// Requests should be sent to the checked addresses rather than resolving
+    /// the name again, which may give different addresses.
+    pub fn check_resolved(&self, address: &Address, resolved: &[SocketAddr]) -> Result<()> {
+        match resolved
+            .iter()
+            .find(|socket| self.deny.iter().any(|rule| rule.matches_socket(socket)))
+        {
+            Some(socket) => Err(TcpIpError::DestinationDenied {
+                address: format!("{} ({})", address, socket),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the server to forward the request with `header` to, rewriting
+    /// an absolute-form target to origin-form and setting `Host` to match.
+    ///
+    /// Requests that name no server, which are HTTP/1.0 requests without a
+    /// `Host` header, are forwarded to `fallback`.
+    pub fn resolve(&self, header: &mut RequestHeader, fallback: &Address) -> Result<Address> {
+        let address = match header.uri.form() {
+            TargetForm::Absolute => {
+                let scheme = header.uri.scheme().unwrap_or_default();
+
+                if !scheme.eq_ignore_ascii_case("http") {
+                    return Err(TcpIpError::parse(format!(
+                        "Can't forward '{}' URLs, only 'http'",
+                        scheme
+                    )));
+                }
+
+                let authority = header.uri.authority().unwrap_or_default().to_owned();
+                let address = Address::parse_with_default_port(&authority, HTTP_PORT)?;
+
+                header.uri = header.uri.origin_form().parse()?;
+                header.insert_header("Host", &authority);
+
+                address
+            }
+            TargetForm::Authority => {
+                return Err(TcpIpError::parse(
+                    "CONNECT tunnels are not supported by the forward proxy",
+                ))
+            }
+            TargetForm::Origin | TargetForm::Asterisk => {
+                match header.headers.as_ref().and_then(|h| h.get("Host")) {
+                    Some(host) => Address::parse_with_default_port(host, HTTP_PORT)?,
+                    None => return Ok(fallback.clone()),
+                }
+            }
+        };
+
+        if self.is_allowed(&address) {
+            Ok(address)
+        } else {
+            Err(TcpIpError::DestinationDenied {
+                address: address.to_string(),
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ForwardProxyOptions {
+    /// Forward requests to the server they name, `remote` is only used for
+    /// requests that don't name one.
+    pub enabled: bool,
+    pub destinations: Destinations,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::address::Address;
+    use crate::forward_proxy::{Destinations, HostRule};
+    use crate::header_item::HeaderItem;
+    use crate::request::request_header::RequestHeader;
+
+    fn rules(rules: &[&str]) -> Vec<HostRule> {
+        rules
+            .iter()
+            .map(|rule| rule.parse().expect("Invalid rule"))
+            .collect()
+    }
+
+    #[test]
+    fn test_host_rule() {
+        let allowed = |rule: &str, host: &str, port: u16| {
+            rule.parse::<HostRule>()
+                .expect("Invalid rule")
+                .matches(&Address::new(host, port))
+        };
+
+        assert!(allowed("example.com", "EXAMPLE.com", 8080));
+        assert!(allowed("example.com:80", "example.com", 80));
+        assert!(!allowed("example.com:80", "example.com", 81));
+        assert!(allowed("*.example.com:*", "a.b.example.com", 1));
+        assert!(!allowed("*.example.com", "example.com", 80));
+        assert!(allowed("*:443", "anything", 443));
+        assert!(allowed("[::1]:80", "::1", 80));
+        assert!(allowed("localhost", "LOCALHOST.", 80));
+        assert!(allowed("*.example.com", "www.Example.com.", 80));
+        assert!(!allowed("*.example.com", "www.example.com.evil", 80));
+
+        // IP addresses match however they are written
+        for host in [
+            "127.0.0.1.",
+            "127.1",
+            "127.0.1",
+            "0x7f000001",
+            "2130706433",
+            "0177.0.0.01",
+            "::ffff:127.0.0.1",
+            "[::ffff:7f00:1]",
+        ] {
+            assert!(allowed("127.0.0.1", host, 80), "{}", host);
+        }
+
+        assert!(allowed("[::ffff:127.0.0.1]", "127.0.0.1", 80));
+        assert!(allowed("[::1]", "0:0:0:0:0:0:0:1", 80));
+        assert!(!allowed("127.0.0.1", "127.0.0.2", 80));
+        assert!(!allowed("127.0.0.1", "localhost", 80));
+        assert!(!allowed("127.0.0.1", "127.0.0.1.1.1", 80));
+        assert!(!allowed("*.0.0.1", "127.0.0.1", 80));
+
+        let error = |rule: &str| {
+            rule.parse::<HostRule>()
+                .expect_err("Parsed an invalid rule")
+                .to_string()
+        };
+
+        assert_eq!(error("a*.com"), "Invalid host rule 'a*.com' - invalid host");
+        assert_eq!(
+            error("a.com:x"),
+            "Invalid host rule 'a.com:x' - invalid port - invalid digit found in string"
+        );
+        assert_eq!(
+            error("::1"),
+            "Invalid host rule '::1' - IPv6 addresses must be written in brackets"
+        );
+    }
+
+    #[test]
+    fn test_is_allowed() {
+        let destinations = Destinations {
+            allow: rules(&["*.example.com", "10.0.0.1:8080"]),
+            deny: rules(&["admin.example.com"]),
+        };
+
+        assert!(destinations.is_allowed(&Address::new("www.example.com", 80)));
+        assert!(destinations.is_allowed(&Address::new("10.0.0.1", 8080)));
+        assert!(!destinations.is_allowed(&Address::new("10.0.0.1", 80)));
+        assert!(!destinations.is_allowed(&Address::new("admin.example.com", 80)));
+        assert!(Destinations::default().is_allowed(&Address::new("a.test", 1)));
+    }
+
+    #[test]
+    fn test_check_resolved() {
+        let destinations = Destinations {
+            allow: Vec::new(),
+            deny: rules(&["127.0.0.1", "[::1]", "*.internal", "10.0.0.1:25"]),
+        };
+        let address = Address::new("example.com", 25);
+
+        let check = |resolved: &[&str]| {
+            let resolved = resolved
+                .iter()
+                .map(|socket| socket.parse().expect("Invalid socket address"))
+                .collect::<Vec<_>>();
+
+            destinations
+                .check_resolved(&address, &resolved)
+                .map_err(|e| e.to_string())
+        };
+
+        assert_eq!(check(&["93.184.216.34:25", "10.0.0.1:80"]), Ok(()));
+        assert_eq!(
+            check(&["93.184.216.34:25", "[::ffff:127.0.0.1]:25"]),
+            Err("Forwarding to 'example.com:25 ([::ffff:127.0.0.1]:25)' is not allowed".to_owned())
+        );
+        assert_eq!(
+            check(&["[::1]:25"]),
+            Err("Forwarding to 'example.com:25 ([::1]:25)' is not allowed".to_owned())
+        );
+        assert_eq!(
+            check(&["10.0.0.1:25"]),
+            Err("Forwarding to 'example.com:25 (10.0.0.1:25)' is not allowed".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let destinations = Destinations {
+            allow: Vec::new(),
+            deny: rules(&["*:25"]),
+        };
+        let fallback = Address::new("127.0.0.1", 9);
+
+        let resolve = |request: &str| {
+            let mut header =
+                RequestHeader::from_bytes(request.as_bytes()).expect("Invalid request");
+
+            destinations
+                .resolve(&mut header, &fallback)
+                .map(|address| {
+                    (
+                        address.to_string(),
+                        header.as_string().expect("Invalid header"),
+                    )
+                })
+                .map_err(|e| e.to_string())
+        };
+
+        assert_eq!(
+            resolve("GET http://a.test:8080?x HTTP/1.1\r\nHost: b.test\r\n\r\n"),
+            Ok((
+                "a.test:8080".to_owned(),
+                "GET /?x HTTP/1.1\r\nHost: a.test:8080\r\n\r\n".to_owned()
+            ))
+        );
+        assert_eq!(
+            resolve("GET / HTTP/1.1\r\nHost: [::1]\r\n\r\n").map(|(address, _)| address),
+            Ok("[::1]:80".to_owned())
+        );
+        assert_eq!(
+            resolve("GET / HTTP/1.0\r\n\r\n").map(|(address, _)| address),
+            Ok("127.0.0.1:9".to_owned())
+        );
+        assert_eq!(
+            resolve("GET http://mail.test:25/ HTTP/1.1\r\n\r\n"),
+            Err("Forwarding to 'mail.test:25' is not allowed".to_owned())
+        );
+        assert_eq!(
+            resolve("GET https://a.test/ HTTP/1.1\r\n\r\n"),
+            Err("Can't forward 'https' URLs, only 'http'".to_owned())
+        );
+    }
+}
@@ -0,0 +1,689 @@
+//! Captures forwarded exchanges as an HTTP Archive (HAR 1.2) that can be opened
+//! in browser developer tools.
+//!
+//! The bytes sent over the client connection are recorded as they are, then
+//! parsed again once the exchange is complete, so a capture shows what the
+//! client actually sent and received rather than what the proxy meant to send.
+//!
+//! HAR 1.2 expects `content.text` to be the decoded response, but bodies are
+//! captured as sent as there is nothing to decode them with. A response sent
+//! with a `Content-Encoding` such as gzip has `"_encoded":true` in its
+//! `content`, whose `text` and `size` are then of the encoded body.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+use crate::access_log::rfc3339_time;
+use crate::body::Body;
+use crate::body_type::BodyType;
+use crate::error::TcpIpError;
+use crate::header_item::HeaderItem;
+use crate::header_map::HeaderMap;
+use crate::request::request_header::RequestHeader;
+use crate::request::uri::TargetForm;
+use crate::response::response_header::ResponseHeader;
+use crate::util::{json_string, SharedFiles};
+use crate::Result;
+
+pub const DEFAULT_HAR_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+const HAR_HEADER: &str = concat!(
+    r#"{"log":{"version":"1.2","creator":{"name":""#,
+    env!("CARGO_PKG_NAME"),
+    r#"","version":""#,
+    env!("CARGO_PKG_VERSION"),
+    r#""},"entries":["#
+);
+const HAR_TRAILER: &str = "\n]}}\n";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CaptureOptions {
+    /// The HAR file exchanges are appended to, nothing is captured if not set.
+    pub file: Option<PathBuf>,
+    /// Bytes of each request and response body captured, longer bodies are truncated.
+    pub max_body_size: usize,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        Self {
+            file: None,
+            max_body_size: DEFAULT_HAR_MAX_BODY_SIZE,
+        }
+    }
+}
+
+/// The bytes sent one way over a connection, up to a limit.
+#[derive(Debug, Default)]
+pub struct Recording {
+    limit: usize,
+    data: Vec<u8>,
+    // Bytes a `BufRead` has buffered, which are recorded once they are consumed
+    pending: Vec<u8>,
+    truncated: bool,
+}
+
+impl Recording {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns true if more bytes were sent than were recorded.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    fn space(&self) -> usize {
+        self.limit.saturating_sub(self.data.len())
+    }
+
+    pub(crate) fn push(&mut self, bytes: &[u8]) {
+        // A read past the buffer replaces whatever was buffered
+        self.pending.clear();
+
+        let recorded = bytes.len().min(self.space());
+
+        self.data.extend_from_slice(&bytes[..recorded]);
+        self.truncated |= recorded < bytes.len();
+    }
+
+    pub(crate) fn buffered(&mut self, bytes: &[u8]) {
+        let buffered = bytes.len().min(self.space());
+
+        self.pending.clear();
+        self.pending.extend_from_slice(&bytes[..buffered]);
+    }
+
+    pub(crate) fn consumed(&mut self, amount: usize) {
+        let recorded = amount.min(self.pending.len());
+
+        self.data.extend(self.pending.drain(..recorded));
+        self.truncated |= recorded < amount;
+    }
+}
+
+/// A request and its response as they were sent over the client connection.
+pub struct Exchange<'a> {
+    pub started: SystemTime,
+    /// Time from reading the request header to sending the end of the response.
+    pub time: Duration,
+    /// Time waiting for the remote server to start its response.
+    pub wait: Option<Duration>,
+    pub request: &'a Recording,
+    pub response: &'a Recording,
+}
+
+impl Exchange<'_> {
+    /// Returns the exchange as a HAR entry, or None if the request can't be
+    /// parsed. Requests without a Host header are given URLs on `host`.
+    pub fn to_entry(&self, host: &str) -> Option<String> {
+        let mut reader = Cursor::new(self.request.data());
+        let header = RequestHeader::from_reader_with_limit(&mut reader, usize::MAX).ok()?;
+        let headers_size = reader.position();
+        let (body, body_size) = read_body(&mut reader, header.body_type().ok().flatten());
+
+        let headers = header.headers().as_ref();
+        let host = headers.and_then(|h| h.get("host")).unwrap_or(host);
+        let url = match header.uri.form() {
+            TargetForm::Origin => format!("http://{}{}", host, header.uri),
+            TargetForm::Absolute => header.uri.to_string(),
+            TargetForm::Authority => format!("http://{}", header.uri),
+            TargetForm::Asterisk => format!("http://{}", host),
+        };
+
+        let cookies = headers
+            .into_iter()
+            .flat_map(|h| h.get_all("cookie"))
+            .flat_map(|cookie| cookie.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .map(|(name, value)| name_value(name, value))
+            .collect::<Vec<_>>();
+
+        let query_string = header
+            .uri
+            .query_pairs()
+            .map(|(name, value)| name_value(&name, &value))
+            .collect::<Vec<_>>();
+
+        let post_data = if body_size > 0 {
+            let (text, encoding) = content_text(&body);
+
+            format!(
+                r#","postData":{{"mimeType":{},"params":[],"text":{}{}}}"#,
+                json_string(content_type(headers)),
+                json_string(&text),
+                encoding.map_or_else(String::new, |e| format!(r#","_encoding":"{}""#, e))
+            )
+        } else {
+            String::new()
+        };
+
+        let request = format!(
+            r#"{{"method":{},"url":{},"httpVersion":"{}","cookies":[{}],"headers":[{}],"queryString":[{}]{},"headersSize":{},"bodySize":{}}}"#,
+            json_string(&header.method.to_string()),
+            json_string(&url),
+            header.version,
+            cookies.join(","),
+            headers_json(headers),
+            query_string.join(","),
+            post_data,
+            headers_size,
+            body_size
+        );
+
+        let mut comments = Vec::new();
+
+        if self.request.is_truncated() {
+            comments.push("The request was too large to capture completely.");
+        }
+        if self.response.is_truncated() {
+            comments.push("The response was too large to capture completely.");
+        }
+
+        let time = millis(self.time);
+        let wait = self.wait.map_or(0.0, millis).min(time);
+
+        Some(format!(
+            r#"{{"startedDateTime":"{}","time":{:.3},"request":{},"response":{},"cache":{{}},"timings":{{"blocked":-1,"dns":-1,"connect":-1,"ssl":-1,"send":0,"wait":{:.3},"receive":{:.3}}}{}}}"#,
+            rfc3339_time(self.started),
+            time,
+            request,
+            self.response_json(&header),
+            wait,
+            time - wait,
+            if comments.is_empty() {
+                String::new()
+            } else {
+                format!(r#","comment":{}"#, json_string(&comments.join(" ")))
+            }
+        ))
+    }
+
+    fn response_json(&self, request: &RequestHeader) -> String {
+        let mut reader = Cursor::new(self.response.data());
+
+        // Only the final response is captured, after any 100 Continue
+        let (header, headers_size) = loop {
+            let start = reader.position();
+
+            match ResponseHeader::from_reader_with_limit(&mut reader, usize::MAX) {
+                Ok(header) if header.is_informational() && header.status_code != 101 => {}
+                Ok(header) => break (header, reader.position() - start),
+                // The connection failed before a response was sent
+                Err(_) => {
+                    return r#"{"status":0,"statusText":"","httpVersion":"","cookies":[],"headers":[],"content":{"size":0,"mimeType":"x-unknown"},"redirectURL":"","headersSize":-1,"bodySize":-1}"#.to_owned()
+                }
+            }
+        };
+
+        let body_type = header.body_type_for(&request.method).ok().flatten();
+        let (body, body_size) = read_body(&mut reader, body_type);
+        let headers = header.headers().as_ref();
+
+        let cookies = headers
+            .into_iter()
+            .flat_map(|h| h.get_all("set-cookie"))
+            .filter_map(set_cookie_json)
+            .collect::<Vec<_>>();
+
+        let encoded = headers
+            .and_then(|h| h.get("content-encoding"))
+            .is_some_and(|e| !e.trim().eq_ignore_ascii_case("identity"));
+
+        let text = if body.is_empty() {
+            String::new()
+        } else {
+            let (text, encoding) = content_text(&body);
+
+            format!(
+                r#","text":{}{}{}"#,
+                json_string(&text),
+                encoding.map_or_else(String::new, |e| format!(r#","encoding":"{}""#, e)),
+                if encoded { r#","_encoded":true"# } else { "" }
+            )
+        };
+
+        format!(
+            r#"{{"status":{},"statusText":{},"httpVersion":"{}","cookies":[{}],"headers":[{}],"content":{{"size":{},"mimeType":{}{}}},"redirectURL":{},"headersSize":{},"bodySize":{}}}"#,
+            header.status_code,
+            json_string(&header.reason_phrase),
+            header.version,
+            cookies.join(","),
+            headers_json(headers),
+            body.len(),
+            json_string(content_type(headers)),
+            text,
+            json_string(headers.and_then(|h| h.get("location")).unwrap_or("")),
+            headers_size,
+            body_size
+        )
+    }
+}
+
+// Decodes a recorded body, keeping what was recorded of a truncated one.
+// Returns the body and the number of bytes it was sent as.
+fn read_body(reader: &mut Cursor<&[u8]>, body_type: Option<BodyType>) -> (Vec<u8>, u64) {
+    let start = reader.position();
+    let mut body = Vec::new();
+
+    if let Some(body_type) = body_type {
+        let _ = Body::from_reader(reader, &body_type).read_to_end(&mut body);
+    }
+
+    (body, reader.position() - start)
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn name_value(name: &str, value: &str) -> String {
+    format!(
+        r#"{{"name":{},"value":{}}}"#,
+        json_string(name),
+        json_string(value)
+    )
+}
+
+fn headers_json(headers: Option<&HeaderMap>) -> String {
+    headers
+        .into_iter()
+        .flat_map(|h| h.iter())
+        .map(|(name, value)| name_value(name, value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn content_type(headers: Option<&HeaderMap>) -> &str {
+    headers
+        .and_then(|h| h.get("content-type"))
+        .unwrap_or("x-unknown")
+}
+
+// Returns a body as text, or as base64 if it isn't UTF-8
+fn content_text(body: &[u8]) -> (String, Option<&'static str>) {
+    match std::str::from_utf8(body) {
+        Ok(text) => (text.to_owned(), None),
+        Err(_) => (base64_encode(body), Some("base64")),
+    }
+}
+
+// `id=1; Path=/; HttpOnly` as a HAR cookie with its attributes
+fn set_cookie_json(set_cookie: &str) -> Option<String> {
+    let mut parts = set_cookie.split(';').map(str::trim);
+    let (name, value) = parts.next()?.split_once('=')?;
+
+    let mut cookie = name_value(name, value);
+    cookie.pop();
+
+    for part in parts {
+        let (attribute, value) = part.split_once('=').unwrap_or((part, ""));
+
+        match attribute.to_ascii_lowercase().as_str() {
+            "path" => cookie.push_str(&format!(r#","path":{}"#, json_string(value))),
+            "domain" => cookie.push_str(&format!(r#","domain":{}"#, json_string(value))),
+            "expires" => cookie.push_str(&format!(r#","expires":{}"#, json_string(value))),
+            "httponly" => cookie.push_str(r#","httpOnly":true"#),
+            "secure" => cookie.push_str(r#","secure":true"#),
+            _ => {}
+        }
+    }
+
+    cookie.push('}');
+    Some(cookie)
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Decodes base64 with or without padding, returning None if it is invalid.
+pub(crate) fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.trim_end_matches('=').as_bytes();
+
+    if encoded.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+
+    for chunk in encoded.chunks(4) {
+        let mut bits = 0;
+
+        for (i, c) in chunk.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|b| b == c)? as u32;
+            bits |= value << (18 - 6 * i);
+        }
+
+        decoded.extend_from_slice(&bits.to_be_bytes()[1..chunk.len()]);
+    }
+
+    Some(decoded)
+}
+
+/// A HAR file that is valid JSON after each entry is appended, so it can be
+/// opened while exchanges are still being captured.
+pub struct HarFile {
+    state: Mutex<HarState>,
+}
+
+struct HarState {
+    file: File,
+    empty: bool,
+}
+
+impl HarFile {
+    /// Opens the HAR file at `path`, appending to the entries of an earlier
+    /// capture if there is one.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let len = file.metadata()?.len();
+        let trailer_len = HAR_TRAILER.len() as u64;
+
+        let empty = if len == 0 {
+            file.write_all(HAR_HEADER.as_bytes())?;
+            file.write_all(HAR_TRAILER.as_bytes())?;
+            true
+        } else {
+            // A capture always leaves the file ending with the trailer
+            let mut end = vec![0; HAR_TRAILER.len() + 1];
+
+            if len > trailer_len {
+                file.seek(SeekFrom::End(-(end.len() as i64)))?;
+                file.read_exact(&mut end)?;
+            }
+
+            if end[1..] != *HAR_TRAILER.as_bytes() {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "it is not a HAR file written by a previous capture",
+                ));
+            }
+
+            end[0] == b'['
+        };
+
+        Ok(Self {
+            state: Mutex::new(HarState { file, empty }),
+        })
+    }
+
+    /// Appends a HAR entry, rewriting the end of the file after it.
+    pub fn append(&self, entry: &str) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let separator = if state.empty { "\n" } else { ",\n" };
+
+        state
+            .file
+            .seek(SeekFrom::End(-(HAR_TRAILER.len() as i64)))?;
+        state
+            .file
+            .write_all(format!("{}{}{}", separator, entry, HAR_TRAILER).as_bytes())?;
+        state.empty = false;
+
+        Ok(())
+    }
+}
+
+/// Writes the exchanges of a server to a HAR file.
+#[derive(Clone)]
+pub struct Capture {
+    file: Arc<HarFile>,
+    max_body_size: usize,
+}
+
+impl Capture {
+    /// Opens the HAR file at `path`. Servers capturing to the same file share it.
+    pub fn open(path: &Path, max_body_size: usize) -> Result<Self> {
+        static OPEN_FILES: SharedFiles<HarFile> = SharedFiles::new();
+
+        let file = OPEN_FILES.get_or_open(path, HarFile::open).map_err(|e| {
+            TcpIpError::config(format!(
+                "Failed to open HAR file '{}' - {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        Ok(Self {
+            file,
+            max_body_size,
+        })
+    }
+
+    pub fn max_body_size(&self) -> usize {
+        self.max_body_size
+    }
+
+    pub fn write(&self, exchange: &Exchange<'_>, host: &str) {
+        if let Some(entry) = exchange.to_entry(host) {
+            if let Err(e) = self.file.append(&entry) {
+                eprintln!("Failed to write HAR file - {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::har::{base64_decode, base64_encode, Exchange, HarFile, Recording};
+
+    fn recording(data: &[u8], limit: usize) -> Recording {
+        let mut recording = Recording::new(limit);
+        recording.push(data);
+        recording
+    }
+
+    #[test]
+    fn test_recording() {
+        let mut recording = Recording::new(8);
+
+        recording.buffered(b"GET / HTTP/1.1");
+        recording.consumed(4);
+        recording.buffered(b"/ HTTP/1.1");
+        recording.consumed(2);
+        recording.push(b"HTTP");
+
+        assert_eq!(recording.data(), b"GET / HT");
+        assert!(recording.is_truncated());
+    }
+
+    #[test]
+    fn test_base64() {
+        let cases: [(&[u8], &str); 5] = [
+            (b"", ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (&[0xff, 0xfe, 0x00, 0x01], "//4AAQ=="),
+        ];
+
+        for (data, encoded) in cases {
+            assert_eq!(base64_encode(data), encoded);
+            assert_eq!(base64_decode(encoded).as_deref(), Some(data));
+        }
+
+        assert_eq!(base64_decode("Zm8"), Some(b"fo".to_vec()));
+        assert_eq!(base64_decode("Zm9v!"), None);
+        assert_eq!(base64_decode("Z"), None);
+    }
+
+    #[test]
+    fn test_to_entry() {
+        let request = recording(
+            b"POST /search?q=a%20b+c&empty HTTP/1.1\r\nHost: example.com\r\nCookie: id=1; theme=dark\r\nContent-Length: 3\r\n\r\nabc",
+            1024,
+        );
+        let response = recording(
+            b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 302 Found\r\nLocation: /a\r\nSet-Cookie: id=2; Path=/; HttpOnly\r\nContent-Type: image/png\r\nTransfer-Encoding: chunked\r\n\r\n3\r\n\x89PN\r\n0\r\n\r\n",
+            1024,
+        );
+        let exchange = Exchange {
+            started: UNIX_EPOCH + Duration::from_millis(971_186_136_250),
+            time: Duration::from_millis(30),
+            wait: Some(Duration::from_millis(20)),
+            request: &request,
+            response: &response,
+        };
+
+        assert_eq!(
+            exchange
+                .to_entry("127.0.0.1:8080")
+                .expect("Failed to create entry"),
+            concat!(
+                r#"{"startedDateTime":"2000-10-10T13:55:36.250Z","time":30.000,"#,
+                r#""request":{"method":"POST","url":"http://example.com/search?q=a%20b+c&empty","httpVersion":"HTTP/1.1","#,
+                r#""cookies":[{"name":"id","value":"1"},{"name":"theme","value":"dark"}],"#,
+                r#""headers":[{"name":"Host","value":"example.com"},{"name":"Cookie","value":"id=1; theme=dark"},{"name":"Content-Length","value":"3"}],"#,
+                r#""queryString":[{"name":"q","value":"a b c"},{"name":"empty","value":""}],"#,
+                r#""postData":{"mimeType":"x-unknown","params":[],"text":"abc"},"headersSize":105,"bodySize":3},"#,
+                r#""response":{"status":302,"statusText":"Found","httpVersion":"HTTP/1.1","#,
+                r#""cookies":[{"name":"id","value":"2","path":"/","httpOnly":true}],"#,
+                r#""headers":[{"name":"Location","value":"/a"},{"name":"Set-Cookie","value":"id=2; Path=/; HttpOnly"},{"name":"Content-Type","value":"image/png"},{"name":"Transfer-Encoding","value":"chunked"}],"#,
+                r#""content":{"size":3,"mimeType":"image/png","text":"iVBO","encoding":"base64"},"#,
+                r#""redirectURL":"/a","headersSize":125,"bodySize":13},"#,
+                r#""cache":{},"timings":{"blocked":-1,"dns":-1,"connect":-1,"ssl":-1,"send":0,"wait":20.000,"receive":10.000}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_to_entry_truncated() {
+        let request = recording(b"GET /big HTTP/1.1\r\n\r\n", 1024);
+        let response = recording(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123456789",
+            44,
+        );
+        let exchange = Exchange {
+            started: UNIX_EPOCH,
+            time: Duration::from_millis(1),
+            wait: None,
+            request: &request,
+            response: &response,
+        };
+
+        let entry = exchange
+            .to_entry("127.0.0.1:8080")
+            .expect("Failed to create entry");
+
+        assert!(entry.contains(r#""url":"http://127.0.0.1:8080/big""#));
+        assert!(entry.contains(r#""content":{"size":5,"mimeType":"x-unknown","text":"01234"}"#));
+        assert!(
+            entry.ends_with(r#""comment":"The response was too large to capture completely."}"#)
+        );
+
+        let garbage = recording(b"\x00\x01", 1024);
+
+        assert!(Exchange {
+            request: &garbage,
+            ..exchange
+        }
+        .to_entry("127.0.0.1:8080")
+        .is_none());
+    }
+
+    #[test]
+    fn test_to_entry_encoded() {
+        let request = recording(b"GET /a.txt HTTP/1.1\r\n\r\n", 1024);
+        let response = recording(
+            b"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: 4\r\n\r\n\x1f\x8b\x08\xff",
+            1024,
+        );
+        let exchange = Exchange {
+            started: UNIX_EPOCH,
+            time: Duration::from_millis(1),
+            wait: None,
+            request: &request,
+            response: &response,
+        };
+
+        let entry = exchange
+            .to_entry("127.0.0.1:8080")
+            .expect("Failed to create entry");
+
+        // The body is kept as sent, which isn't what HAR readers expect
+        assert!(entry.contains(
+            r#""content":{"size":4,"mimeType":"x-unknown","text":"H4sI/w==","encoding":"base64","_encoded":true}"#
+        ));
+    }
+
+    #[test]
+    fn test_har_file() {
+        let dir = std::env::temp_dir().join(format!("har_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Failed to create directory");
+
+        let path = dir.join("capture.har");
+        let read = || fs::read_to_string(&path).expect("Failed to read HAR file");
+
+        HarFile::open(&path).expect("Failed to open HAR file");
+
+        assert!(read().ends_with(
+            r#""entries":[
+]}}
+"#
+        ));
+
+        let file = HarFile::open(&path).expect("Failed to open HAR file");
+        file.append("{}").expect("Failed to append");
+        drop(file);
+
+        // Reopening continues the earlier capture
+        let file = HarFile::open(&path).expect("Failed to open HAR file");
+        file.append(r#"{"a":1}"#).expect("Failed to append");
+
+        assert!(read().ends_with(
+            r#""entries":[
+{},
+{"a":1}
+]}}
+"#
+        ));
+
+        fs::write(&path, "not a capture").expect("Failed to write");
+        assert!(HarFile::open(&path).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
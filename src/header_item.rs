@@ -1,11 +1,11 @@
-use std::io::{BufRead, BufReader, Write};
-use std::net::TcpStream;
+use std::io::{BufRead, ErrorKind, Write};
 use std::ops::Deref;
 
 use crate::body_type::BodyType;
 use crate::error::TcpIpError;
 use crate::header_map::HeaderMap;
-use crate::util::slice_find_to_end;
+use crate::header_parser::{HeaderParser, ParseStatus, DEFAULT_MAX_HEADER_SIZE};
+use crate::http_version::HttpVersion;
 use crate::Result;
 
 // List of hop by hop headers that must be
@@ -25,6 +25,8 @@ const HOP_BY_HOP_HEADERS: [&str; 11] = [
 ];
 
 pub trait HeaderItem {
+    fn version(&self) -> HttpVersion;
+
     fn headers(&self) -> &Option<HeaderMap>;
 
     fn headers_mut(&mut self) -> &mut Option<HeaderMap>;
@@ -35,22 +37,56 @@ pub trait HeaderItem {
 
     fn to_bytes(&self) -> Result<Vec<u8>>;
 
-    fn from_reader(reader: &mut BufReader<&TcpStream>) -> Result<Self>
+    fn from_reader<R: BufRead>(reader: &mut R) -> Result<Self>
     where
         Self: Sized,
     {
-        let data = reader.fill_buf()?;
+        Self::from_reader_with_limit(reader, DEFAULT_MAX_HEADER_SIZE)
+    }
 
-        let headers_end = slice_find_to_end(data, &[13, 10, 13, 10]);
+    /// Reads a header that may arrive over several reads, failing with
+    /// `TcpIpError::HeaderTooLarge` once it exceeds `max_header_size` bytes.
+    fn from_reader_with_limit<R: BufRead>(reader: &mut R, max_header_size: usize) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let mut parser = HeaderParser::new(max_header_size);
+
+        loop {
+            let data = match reader.fill_buf() {
+                Ok(data) => data,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // The peer went silent part way through sending the header
+                Err(e) if !parser.is_empty() => {
+                    return Err(TcpIpError::IncompleteHeader {
+                        received: parser.len(),
+                        source: e,
+                    })
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            if data.is_empty() {
+                return if parser.is_empty() {
+                    Err(TcpIpError::ConnectionClosed)
+                } else {
+                    Err(TcpIpError::IncompleteHeader {
+                        received: parser.len(),
+                        source: ErrorKind::UnexpectedEof.into(),
+                    })
+                };
+            }
 
-        if let Some(headers_end) = headers_end {
-            let headers = Self::from_bytes(&data[..headers_end])?;
+            let len = data.len();
 
-            reader.consume(headers_end + 4);
+            match parser.feed(data)? {
+                ParseStatus::Partial => reader.consume(len),
+                ParseStatus::Complete(consumed) => {
+                    reader.consume(consumed);
 
-            Ok(headers)
-        } else {
-            Err(TcpIpError::DataTimeout)
+                    return Self::from_bytes(parser.header_bytes());
+                }
+            }
         }
     }
 
@@ -60,28 +96,125 @@ pub trait HeaderItem {
         Ok(String::from_utf8(bytes)?)
     }
 
-    fn body_type(&self) -> Option<BodyType> {
+    /// Returns how the length of the body is determined, or `None` if there is no
+    /// body, following the message body length rules in RFC 9112 section 6.3.
+    fn body_type(&self) -> Result<Option<BodyType>>;
+
+    /// Returns the body type given by the `Transfer-Encoding` or `Content-Length` headers.
+    fn framed_body_type(&self) -> Result<Option<BodyType>> {
         if let Some(headers) = self.headers() {
-            if let Some(content_length) = headers
-                .get("Content-Length")
-                .and_then(|cl| cl.parse::<usize>().ok())
-            {
-                return Some(BodyType::Fixed(content_length));
-            } else if let Some(true) = headers
-                .get("Transfer-Encoding")
-                .map(|te| te.to_lowercase() == "chunked")
+            // Transfer-Encoding overrides Content-Length, and the body is only
+            // chunked if chunked is the final encoding across every occurrence
+            if let Some(transfer_encoding) = headers.get_all("Transfer-Encoding").last() {
+                let chunked = transfer_encoding
+                    .rsplit(',')
+                    .next()
+                    .map(|te| te.trim().eq_ignore_ascii_case("chunked"))
+                    .unwrap_or(false);
+
+                return if chunked {
+                    Ok(Some(BodyType::Chunked))
+                } else {
+                    Ok(Some(BodyType::UntilClose))
+                };
+            }
+
+            // Repeated Content-Length values are only valid if they are all the same,
+            // see RFC 9112 section 6.3
+            let mut content_length = None;
+
+            for value in headers
+                .get_all("Content-Length")
+                .flat_map(|cl| cl.split(','))
             {
-                return Some(BodyType::Chunked);
+                let value = value.trim().parse::<usize>().map_err(|_| {
+                    TcpIpError::parse(format!("Invalid Content-Length '{}'", value))
+                })?;
+
+                if content_length.is_some_and(|cl| cl != value) {
+                    return Err(TcpIpError::parse("Conflicting Content-Length values"));
+                }
+
+                content_length = Some(value);
             }
+
+            if let Some(content_length) = content_length {
+                return Ok(Some(BodyType::Fixed(content_length)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Replaces the framing headers to match a body of `content_length` bytes, or
+    /// of unknown length if `None`. Returns true if the body must be sent chunked,
+    /// which is only possible if `peer_version` supports it, otherwise the body
+    /// is delimited by closing the connection.
+    fn frame_body(&mut self, content_length: Option<u64>, peer_version: HttpVersion) -> bool {
+        let chunked = content_length.is_none() && peer_version.supports_chunked();
+
+        self.remove_header("Content-Length");
+        self.remove_header("Transfer-Encoding");
+
+        if let Some(content_length) = content_length {
+            self.insert_header("Content-Length", &content_length.to_string());
+        } else if chunked {
+            self.insert_header("Transfer-Encoding", "chunked");
+        } else {
+            self.remove_header("Keep-Alive");
+            self.insert_header("Connection", "close");
+        }
+
+        chunked
+    }
+
+    fn insert_header(&mut self, key: &str, value: &str) {
+        if let Some(headers) = self.headers_mut() {
+            headers.insert(key, value);
+        } else {
+            let mut headers = HeaderMap::new();
+            headers.insert(key, value);
+            *self.headers_mut() = Some(headers);
         }
+    }
+
+    /// Removes every occurrence of `key`, returning true if there were any.
+    fn remove_header(&mut self, key: &str) -> bool {
+        if let Some(headers) = self.headers_mut() {
+            headers.remove_all(key) > 0
+        } else {
+            false
+        }
+    }
+
+    /// Returns true if any `Connection` header lists `option`.
+    fn has_connection_option(&self, option: &str) -> bool {
+        self.headers()
+            .as_ref()
+            .map(|h| {
+                h.get_all("Connection")
+                    .flat_map(|c| c.split(','))
+                    .any(|o| o.trim().eq_ignore_ascii_case(option))
+            })
+            .unwrap_or(false)
+    }
 
-        None
+    /// Returns true if the connection should stay open after this message, using
+    /// the `Connection` header or the default for the HTTP version.
+    fn is_keep_alive(&self) -> bool {
+        if self.has_connection_option("close") {
+            false
+        } else if self.has_connection_option("keep-alive") {
+            true
+        } else {
+            self.version().is_keep_alive_default()
+        }
     }
 
     fn strip_hop_by_hop(&mut self) {
         if let Some(headers) = self.headers_mut() {
             HOP_BY_HOP_HEADERS.iter().for_each(|h| {
-                headers.remove(h);
+                headers.remove_all(h);
             });
         }
     }
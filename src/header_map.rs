@@ -1,5 +1,8 @@
 use std::ops::Deref;
 
+use crate::error::TcpIpError;
+use crate::Result;
+
 #[derive(Debug, Default, Clone)]
 pub struct HeaderMap {
     pub headers: Vec<(String, String)>,
@@ -11,24 +14,35 @@ impl HeaderMap {
         Self::default()
     }
 
-    pub fn from_header_lines(header_str_lines: &mut dyn Iterator<Item = &str>) -> Option<Self> {
-        let headers = header_str_lines.fold(HeaderMap::new(), |mut curr, next| {
-            let mut key_val = next.splitn(2, ": ");
-
-            let key = key_val.next();
-            let val = key_val.next();
-
-            if let (Some(key), Some(val)) = (key, val) {
-                curr.insert(key, val);
+    /// Parses header lines, keeping every occurrence of a header in its original order.
+    ///
+    /// Lines must be `name:value` with optional whitespace around the value, see
+    /// RFC 9112 section 5.1. Any other line fails the whole header, as skipping it
+    /// could lose a header that frames the body.
+    pub fn from_header_lines(
+        header_str_lines: &mut dyn Iterator<Item = &str>,
+    ) -> Result<Option<Self>> {
+        let mut headers = HeaderMap::new();
+
+        for (i, line) in header_str_lines.filter(|l| !l.is_empty()).enumerate() {
+            let (key, val) = line.split_once(':').ok_or_else(|| {
+                TcpIpError::parse(format!("Header field {} is missing ':'", i + 1))
+            })?;
+
+            if key.is_empty() || !key.bytes().all(is_token_char) {
+                return Err(TcpIpError::parse(format!(
+                    "Header field {} has an invalid name",
+                    i + 1
+                )));
             }
 
-            curr
-        });
+            headers.append(key, val.trim_matches([' ', '\t']));
+        }
 
         if !headers.is_empty() {
-            Some(headers)
+            Ok(Some(headers))
         } else {
-            None
+            Ok(None)
         }
     }
 
@@ -36,29 +50,26 @@ impl HeaderMap {
         self.headers.is_empty()
     }
 
+    /// Sets the value of `key`, replacing every existing occurrence of it.
     pub fn insert(&mut self, key: &str, value: &str) {
-        let existing = self
-            .headers
-            .iter_mut()
-            .find(|(k, _)| *k == key)
-            .map(|(_, v)| v);
-
-        if let Some(existing) = existing {
-            *existing = value.to_owned();
-        } else {
-            self.headers.push((key.to_owned(), value.to_owned()));
+        match self.entry(key) {
+            Entry::Occupied(mut entry) => {
+                entry.insert(value);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
         }
     }
 
-    pub fn remove(&mut self, key: &str) -> bool {
-        let key = key.to_lowercase();
-
-        let existing = self
-            .headers
-            .iter()
-            .position(|(k, _)| k.to_lowercase() == key);
+    /// Adds another occurrence of `key` after any existing ones.
+    pub fn append(&mut self, key: &str, value: &str) {
+        self.headers.push((key.to_owned(), value.to_owned()));
+    }
 
-        if let Some(existing) = existing {
+    /// Removes the first occurrence of `key`.
+    pub fn remove(&mut self, key: &str) -> bool {
+        if let Some(existing) = self.position(key) {
             self.headers.remove(existing);
 
             true
@@ -67,25 +78,185 @@ impl HeaderMap {
         }
     }
 
+    /// Removes every occurrence of `key`, returning how many were removed.
+    pub fn remove_all(&mut self, key: &str) -> usize {
+        let len = self.headers.len();
+
+        self.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+
+        len - self.headers.len()
+    }
+
+    /// Returns the value of the first occurrence of `key`.
     pub fn get(&self, key: &str) -> Option<&str> {
-        let key = key.to_lowercase();
+        self.position(key).map(|i| self.headers[i].1.as_str())
+    }
 
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut String> {
+        self.headers
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+
+    /// Returns the values of every occurrence of `key` in order.
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
         self.headers
             .iter()
-            .find(|(k, _)| k.to_lowercase() == key)
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(key))
             .map(|(_, v)| v.as_str())
     }
 
-    pub fn get_mut(&mut self, key: &str) -> Option<&mut String> {
-        let key = key.to_lowercase();
+    pub fn entry(&mut self, key: &str) -> Entry<'_> {
+        match self.position(key) {
+            Some(index) => Entry::Occupied(OccupiedEntry { map: self, index }),
+            None => Entry::Vacant(VacantEntry {
+                map: self,
+                key: key.to_owned(),
+            }),
+        }
+    }
 
+    fn position(&self, key: &str) -> Option<usize> {
         self.headers
-            .iter_mut()
-            .find(|(k, _)| k.to_lowercase() == key)
-            .map(|(_, v)| v)
+            .iter()
+            .position(|(k, _)| k.eq_ignore_ascii_case(key))
+    }
+}
+
+/// A view into the occurrences of a header, returned by `HeaderMap::entry`.
+pub enum Entry<'a> {
+    Occupied(OccupiedEntry<'a>),
+    Vacant(VacantEntry<'a>),
+}
+
+impl<'a> Entry<'a> {
+    /// Returns the value of the first occurrence, inserting `value` if there is none.
+    pub fn or_insert(self, value: &str) -> &'a mut String {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(value),
+        }
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> String>(self, f: F) -> &'a mut String {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(&f()),
+        }
+    }
+
+    /// Modifies the value of the first occurrence if there is one.
+    pub fn and_modify<F: FnOnce(&mut String)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            entry => entry,
+        }
+    }
+}
+
+pub struct OccupiedEntry<'a> {
+    map: &'a mut HeaderMap,
+    // Index of the first occurrence of the header
+    index: usize,
+}
+
+impl<'a> OccupiedEntry<'a> {
+    pub fn key(&self) -> &str {
+        &self.map.headers[self.index].0
+    }
+
+    pub fn get(&self) -> &str {
+        &self.map.headers[self.index].1
+    }
+
+    pub fn get_mut(&mut self) -> &mut String {
+        &mut self.map.headers[self.index].1
+    }
+
+    pub fn into_mut(self) -> &'a mut String {
+        &mut self.map.headers[self.index].1
+    }
+
+    /// Returns the values of every occurrence of the header in order.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        let key = self.key();
+
+        self.map.headers[self.index..]
+            .iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Replaces the value of the first occurrence and removes the others,
+    /// returning the old value of the first occurrence.
+    pub fn insert(&mut self, value: &str) -> String {
+        let index = self.index;
+        let key = self.map.headers[index].0.clone();
+
+        let mut i = 0;
+        self.map.headers.retain(|(k, _)| {
+            let keep = i <= index || !k.eq_ignore_ascii_case(&key);
+            i += 1;
+            keep
+        });
+
+        std::mem::replace(&mut self.map.headers[index].1, value.to_owned())
+    }
+
+    /// Adds another occurrence of the header after the existing ones.
+    pub fn append(&mut self, value: &str) {
+        let key = self.key().to_owned();
+
+        self.map.append(&key, value);
+    }
+
+    /// Removes every occurrence of the header, returning their values in order.
+    pub fn remove(self) -> Vec<String> {
+        let key = self.key().to_owned();
+        let (removed, kept) = std::mem::take(&mut self.map.headers)
+            .into_iter()
+            .partition(|(k, _)| k.eq_ignore_ascii_case(&key));
+
+        self.map.headers = kept;
+
+        removed
+            .into_iter()
+            .map(|(_, v): (String, String)| v)
+            .collect()
+    }
+}
+
+pub struct VacantEntry<'a> {
+    map: &'a mut HeaderMap,
+    key: String,
+}
+
+impl<'a> VacantEntry<'a> {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn insert(self, value: &str) -> &'a mut String {
+        self.map.headers.push((self.key, value.to_owned()));
+
+        &mut self
+            .map
+            .headers
+            .last_mut()
+            .expect("Header was just inserted")
+            .1
     }
 }
 
+// The characters allowed in a header name, see RFC 9110 section 5.6.2
+fn is_token_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
 impl Deref for HeaderMap {
     type Target = Vec<(String, String)>;
 
@@ -96,7 +267,8 @@ impl Deref for HeaderMap {
 
 #[cfg(test)]
 mod tests {
-    use crate::header_map::HeaderMap;
+    use crate::error::TcpIpError;
+    use crate::header_map::{Entry, HeaderMap};
 
     #[test]
     fn test_insert() {
@@ -109,6 +281,101 @@ mod tests {
         header_map.insert("Transfer-Encoding", "gzip");
 
         assert_eq!(header_map.get("Transfer-Encoding"), Some("gzip"));
+
+        header_map.append("Set-Cookie", "a=1");
+        header_map.append("set-cookie", "b=2");
+        header_map.insert("SET-COOKIE", "c=3");
+
+        assert_eq!(
+            header_map.get_all("Set-Cookie").collect::<Vec<_>>(),
+            ["c=3"]
+        );
+        assert_eq!(header_map.len(), 2);
+    }
+
+    #[test]
+    fn test_from_header_lines() {
+        let lines = "Set-Cookie: a=1\r\nHost: localhost\r\nset-cookie: b=2";
+
+        let header_map = HeaderMap::from_header_lines(&mut lines.lines())
+            .expect("Failed to parse header lines")
+            .expect("Missing headers");
+
+        assert_eq!(header_map.len(), 3);
+        assert_eq!(header_map.get("Set-Cookie"), Some("a=1"));
+        assert_eq!(
+            header_map.get_all("Set-Cookie").collect::<Vec<_>>(),
+            ["a=1", "b=2"]
+        );
+    }
+
+    #[test]
+    fn test_from_header_lines_optional_whitespace() {
+        let lines =
+            "Transfer-Encoding:chunked\r\nContent-Length:5\r\nHost: \t localhost \r\nEmpty:\r\n";
+
+        let header_map = HeaderMap::from_header_lines(&mut lines.lines())
+            .expect("Failed to parse header lines")
+            .expect("Missing headers");
+
+        assert_eq!(header_map.get("Transfer-Encoding"), Some("chunked"));
+        assert_eq!(header_map.get("Content-Length"), Some("5"));
+        assert_eq!(header_map.get("Host"), Some("localhost"));
+        assert_eq!(header_map.get("Empty"), Some(""));
+
+        for lines in [
+            "Host localhost",
+            ": value",
+            "Content-Length : 5",
+            " Host: a",
+        ] {
+            assert!(matches!(
+                HeaderMap::from_header_lines(&mut lines.lines()),
+                Err(TcpIpError::Parse { .. })
+            ));
+        }
+
+        assert!(HeaderMap::from_header_lines(&mut "".lines())
+            .expect("Failed to parse header lines")
+            .is_none());
+    }
+
+    #[test]
+    fn test_remove_all() {
+        let mut header_map = HeaderMap::new();
+
+        header_map.append("Via", "1.1 a");
+        header_map.append("Host", "localhost");
+        header_map.append("via", "1.1 b");
+
+        assert_eq!(header_map.remove_all("Via"), 2);
+        assert_eq!(header_map.get("Via"), None);
+        assert_eq!(header_map.get("Host"), Some("localhost"));
+    }
+
+    #[test]
+    fn test_entry() {
+        let mut header_map = HeaderMap::new();
+
+        header_map.entry("Vary").or_insert("Accept");
+        header_map
+            .entry("vary")
+            .and_modify(|v| v.push_str(", Origin"))
+            .or_insert("Origin");
+
+        assert_eq!(header_map.get("Vary"), Some("Accept, Origin"));
+
+        if let Entry::Occupied(mut entry) = header_map.entry("Vary") {
+            entry.append("Cookie");
+
+            assert_eq!(
+                entry.iter().collect::<Vec<_>>(),
+                ["Accept, Origin", "Cookie"]
+            );
+            assert_eq!(entry.remove(), ["Accept, Origin", "Cookie"]);
+        }
+
+        assert!(header_map.is_empty());
     }
 
     #[test]
@@ -119,7 +386,7 @@ mod tests {
 
         assert_eq!(header_map.get("Transfer-Encoding"), Some("chunked"));
 
-        assert_eq!(header_map.remove("Transfer-Encoding"), true);
+        assert!(header_map.remove("Transfer-Encoding"));
 
         assert_eq!(header_map.get("Transfer-Encoding"), None);
     }
@@ -0,0 +1,179 @@
+use crate::error::TcpIpError;
+use crate::Result;
+
+pub const DEFAULT_MAX_HEADER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum State {
+    // Skipping empty lines sent before the start line
+    Start,
+    Line,
+    Cr,
+    Lf,
+    LfCr,
+    Done,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ParseStatus {
+    /// The header is incomplete, all of the given bytes were consumed.
+    Partial,
+    /// The header is complete after consuming the given number of bytes.
+    Complete(usize),
+}
+
+/// Finds the end of a header section across any number of reads.
+///
+/// Bytes are fed in as they arrive and accumulated until the empty line ending
+/// the header section is found, so a header may be split across TCP segments.
+#[derive(Debug)]
+pub struct HeaderParser {
+    data: Vec<u8>,
+    state: State,
+    max_size: usize,
+}
+
+impl HeaderParser {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            data: Vec::new(),
+            state: State::Start,
+            max_size,
+        }
+    }
+
+    /// Returns true if no header bytes have been received yet.
+    pub fn is_empty(&self) -> bool {
+        self.state == State::Start
+    }
+
+    /// Returns the number of header bytes received so far.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.state == State::Done
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<ParseStatus> {
+        if self.state == State::Done {
+            return Ok(ParseStatus::Complete(0));
+        }
+
+        for (i, b) in bytes.iter().enumerate() {
+            self.state = match *b {
+                b'\r' => match self.state {
+                    State::Start => continue,
+                    State::Lf => State::LfCr,
+                    _ => State::Cr,
+                },
+                b'\n' => match self.state {
+                    State::Start => continue,
+                    State::Lf | State::LfCr => State::Done,
+                    _ => State::Lf,
+                },
+                _ => State::Line,
+            };
+
+            if self.data.len() >= self.max_size {
+                return Err(TcpIpError::HeaderTooLarge {
+                    limit: self.max_size,
+                });
+            }
+
+            self.data.push(*b);
+
+            if self.state == State::Done {
+                return Ok(ParseStatus::Complete(i + 1));
+            }
+        }
+
+        Ok(ParseStatus::Partial)
+    }
+
+    /// Returns the header bytes without the empty line that ends them.
+    pub fn header_bytes(&self) -> &[u8] {
+        let end = self
+            .data
+            .iter()
+            .rposition(|b| *b != b'\r' && *b != b'\n')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+
+        &self.data[..end]
+    }
+}
+
+impl Default for HeaderParser {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_HEADER_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::TcpIpError;
+    use crate::header_parser::{HeaderParser, ParseStatus};
+
+    #[test]
+    fn test_split_across_reads() {
+        let mut parser = HeaderParser::default();
+
+        assert_eq!(
+            parser
+                .feed(b"GET / HTTP/1.1\r\nHo")
+                .expect("Failed to feed"),
+            ParseStatus::Partial
+        );
+        assert_eq!(
+            parser.feed(b"st: localhost\r").expect("Failed to feed"),
+            ParseStatus::Partial
+        );
+        assert_eq!(
+            parser.feed(b"\n\r").expect("Failed to feed"),
+            ParseStatus::Partial
+        );
+        assert_eq!(
+            parser.feed(b"\nbody").expect("Failed to feed"),
+            ParseStatus::Complete(1)
+        );
+
+        assert!(parser.is_complete());
+        assert_eq!(
+            parser.header_bytes(),
+            b"GET / HTTP/1.1\r\nHost: localhost".as_ref()
+        );
+    }
+
+    #[test]
+    fn test_skips_leading_empty_lines() {
+        let mut parser = HeaderParser::default();
+
+        assert_eq!(
+            parser.feed(b"\r\n\r\n").expect("Failed to feed"),
+            ParseStatus::Partial
+        );
+        assert!(parser.is_empty());
+
+        assert_eq!(
+            parser.feed(b"GET / HTTP/1.1\n\n").expect("Failed to feed"),
+            ParseStatus::Complete(16)
+        );
+        assert_eq!(parser.header_bytes(), b"GET / HTTP/1.1".as_ref());
+    }
+
+    #[test]
+    fn test_too_large() {
+        let mut parser = HeaderParser::new(16);
+
+        assert_eq!(
+            parser.feed(b"GET / HTTP/1.1\r\n").expect("Failed to feed"),
+            ParseStatus::Partial
+        );
+        assert!(matches!(
+            parser.feed(b"Host: localhost\r\n\r\n"),
+            Err(TcpIpError::HeaderTooLarge { limit: 16 })
+        ));
+    }
+}
@@ -1,28 +1,34 @@
-use std::fmt::{Display, Formatter};
-use std::io::BufReader;
-use std::net::TcpStream;
+use std::fmt::Formatter;
+use std::io::{BufRead, Read, Write};
 
+use crate::body::Body;
+use crate::error::TcpIpError;
 use crate::header_item::HeaderItem;
+use crate::header_parser::DEFAULT_MAX_HEADER_SIZE;
+use crate::http_version::HttpVersion;
 use crate::Result;
 
-pub trait HttpItem {
+pub trait HttpItem<'a>: Sized {
     type HeaderType: HeaderItem;
 
     fn item_name(&self) -> &str;
 
     fn header(&self) -> &Self::HeaderType;
 
-    fn body(&self) -> Option<Vec<u8>>;
+    fn header_mut(&mut self) -> &mut Self::HeaderType;
 
-    fn new(header: Self::HeaderType, body: Option<Vec<u8>>) -> Self
-    where
-        Self: Sized;
+    fn body(&self) -> &Body<'a>;
 
+    fn body_mut(&mut self) -> &mut Body<'a>;
+
+    fn new(header: Self::HeaderType, body: Body<'a>) -> Self;
+
+    /// Returns the header followed by the body if it is held in memory.
     fn to_bytes(&self) -> Result<Vec<u8>> {
         let mut bytes = self.header().to_bytes()?;
 
-        if let Some(mut body) = self.body() {
-            bytes.append(&mut body);
+        if let Some(body) = self.body().as_bytes() {
+            bytes.extend_from_slice(body);
         }
 
         Ok(bytes)
@@ -34,33 +40,99 @@ pub trait HttpItem {
         Ok(String::from_utf8(bytes)?)
     }
 
-    fn from_reader(reader: &mut BufReader<&TcpStream>) -> Result<Self>
-    where
-        Self: Sized,
-    {
-        let header = Self::HeaderType::from_reader(reader)?;
+    /// Reads the header and returns an item whose body streams from `reader`.
+    fn from_reader<R: BufRead>(reader: &'a mut R) -> Result<Self> {
+        Self::from_reader_with_limit(reader, DEFAULT_MAX_HEADER_SIZE)
+    }
+
+    fn from_reader_with_limit<R: BufRead>(
+        reader: &'a mut R,
+        max_header_size: usize,
+    ) -> Result<Self> {
+        let header = Self::HeaderType::from_reader_with_limit(reader, max_header_size)?;
 
-        let body = if let Some(b) = header.body_type() {
-            Some(b.read_body(reader)?)
+        let body = if let Some(b) = header.body_type()? {
+            Body::from_reader(reader, &b)
         } else {
-            None
+            Body::Empty
         };
 
         Ok(Self::new(header, body))
     }
 
+    /// Reads the rest of a streamed body into memory.
+    fn buffer_body(&mut self) -> Result<()> {
+        self.body_mut().buffer()?;
+
+        Ok(())
+    }
+
+    /// Reads the rest of a streamed body into memory like `buffer_body`, failing
+    /// with `TcpIpError::BodyTooLarge` if it is longer than `max_body_size` bytes.
+    ///
+    /// A body whose length is known is rejected without reading it, otherwise
+    /// reading stops at the limit.
+    fn buffer_body_with_limit(&mut self, max_body_size: u64) -> Result<()> {
+        let too_large = || TcpIpError::BodyTooLarge {
+            limit: max_body_size,
+        };
+
+        if let Some(content_length) = self.body().content_length() {
+            if content_length > max_body_size {
+                return Err(too_large());
+            }
+        }
+
+        if let Body::Fixed(_) | Body::Chunked(_) | Body::UntilClose(_) = self.body() {
+            let mut data = Vec::new();
+
+            self.body_mut()
+                .take(max_body_size.saturating_add(1))
+                .read_to_end(&mut data)?;
+
+            if data.len() as u64 > max_body_size {
+                return Err(too_large());
+            }
+
+            *self.body_mut() = Body::from(data);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the header and streams the body to `writer`.
+    ///
+    /// The framing headers are updated to match the body, so a body of unknown
+    /// length is sent with `Transfer-Encoding: chunked` if `peer_version` supports
+    /// it, otherwise it is delimited by closing the connection.
+    fn write_to<W: Write>(&mut self, writer: &mut W, peer_version: HttpVersion) -> Result<u64> {
+        let chunked = if self.body().is_empty() {
+            false
+        } else {
+            let content_length = self.body().content_length();
+
+            self.header_mut().frame_body(content_length, peer_version)
+        };
+
+        writer.write_all(&self.header().to_bytes()?)?;
+
+        Ok(self.body_mut().write_to(writer, chunked)?)
+    }
+
     fn display(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self.header().as_string() {
             Ok(header) => {
                 write!(f, "{}", header)?;
 
-                if let Some(body) = self.body() {
-                    match String::from_utf8(body) {
+                if let Some(body) = self.body().as_bytes() {
+                    match std::str::from_utf8(body) {
                         Ok(body) => write!(f, "{}", body)?,
                         Err(_) => write!(f, "Binary data")?,
                     };
 
                     write!(f, "\n\n")?;
+                } else if !self.body().is_empty() {
+                    write!(f, "Streamed body\n\n")?;
                 }
 
                 Ok(())
@@ -70,16 +142,4 @@ pub trait HttpItem {
             }
         }
     }
-
-    fn pretty_print(&self, proxy_server_name: &str)
-    where
-        Self: Display,
-    {
-        let line_length = 60;
-
-        println!("{}", "-".repeat(line_length));
-        println!("{} [{}]", self.item_name(), proxy_server_name);
-        println!("{}\n", "-".repeat(line_length));
-        print!("{}", self);
-    }
 }
@@ -0,0 +1,89 @@
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use crate::error::TcpIpError;
+use crate::Result;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, Default)]
+pub enum HttpVersion {
+    Http09,
+    Http10,
+    #[default]
+    Http11,
+    Http2,
+    Http3,
+}
+
+impl HttpVersion {
+    /// Returns true if connections stay open unless `Connection: close` is sent.
+    pub fn is_keep_alive_default(self) -> bool {
+        self >= HttpVersion::Http11
+    }
+
+    /// Returns true if a message of unknown length can be sent with `Transfer-Encoding: chunked`.
+    pub fn supports_chunked(self) -> bool {
+        self == HttpVersion::Http11
+    }
+}
+
+impl FromStr for HttpVersion {
+    type Err = TcpIpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "HTTP/0.9" => Ok(HttpVersion::Http09),
+            "HTTP/1.0" => Ok(HttpVersion::Http10),
+            "HTTP/1.1" => Ok(HttpVersion::Http11),
+            "HTTP/2" | "HTTP/2.0" => Ok(HttpVersion::Http2),
+            "HTTP/3" | "HTTP/3.0" => Ok(HttpVersion::Http3),
+            _ => Err(TcpIpError::parse(format!("Unknown HTTP version '{}'", s))),
+        }
+    }
+}
+
+impl std::fmt::Display for HttpVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let res = match self {
+            HttpVersion::Http09 => "HTTP/0.9",
+            HttpVersion::Http10 => "HTTP/1.0",
+            HttpVersion::Http11 => "HTTP/1.1",
+            HttpVersion::Http2 => "HTTP/2",
+            HttpVersion::Http3 => "HTTP/3",
+        };
+
+        write!(f, "{}", res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http_version::HttpVersion;
+
+    #[test]
+    fn test_from_str_to_string() {
+        for version in &["HTTP/0.9", "HTTP/1.0", "HTTP/1.1", "HTTP/2", "HTTP/3"] {
+            let parsed = version
+                .parse::<HttpVersion>()
+                .expect("Failed to parse version");
+
+            assert_eq!(parsed.to_string(), *version);
+        }
+
+        assert_eq!(
+            "HTTP/2.0"
+                .parse::<HttpVersion>()
+                .expect("Failed to parse version"),
+            HttpVersion::Http2
+        );
+        assert!("HTTP/1".parse::<HttpVersion>().is_err());
+        assert!("1.1".parse::<HttpVersion>().is_err());
+    }
+
+    #[test]
+    fn test_version_behaviour() {
+        assert!(!HttpVersion::Http10.is_keep_alive_default());
+        assert!(HttpVersion::Http11.is_keep_alive_default());
+        assert!(!HttpVersion::Http10.supports_chunked());
+        assert!(HttpVersion::Http11.supports_chunked());
+    }
+}
@@ -0,0 +1,345 @@
+//! Reads JSON documents such as HAR captures, which are written with
+//! `util::json_string` rather than a serializer.
+
+use crate::error::TcpIpError;
+use crate::Result;
+
+/// A JSON value, with the members of an object kept in their original order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Returns the first member named `key` if this is an object.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Returns the items of an array, or nothing if this is not an array.
+    pub fn items(&self) -> &[Value] {
+        match self {
+            Value::Array(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+/// Parses a JSON document. Errors give the byte offset of the invalid part.
+pub fn parse(contents: &str) -> Result<Value> {
+    let mut parser = Parser {
+        bytes: contents.as_bytes(),
+        contents,
+        position: 0,
+    };
+
+    let value = parser.value()?;
+
+    parser.skip_whitespace();
+
+    if parser.position < parser.bytes.len() {
+        return Err(parser.error("unexpected data after the value"));
+    }
+
+    Ok(value)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    contents: &'a str,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> TcpIpError {
+        TcpIpError::parse_at(format!("Invalid JSON - {}", message), self.position)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<()> {
+        self.skip_whitespace();
+
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", expected as char)))
+        }
+    }
+
+    fn literal(&mut self, literal: &str, value: Value) -> Result<Value> {
+        if self.bytes[self.position..].starts_with(literal.as_bytes()) {
+            self.position += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("expected a value"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b't') => self.literal("true", Value::Boolean(true)),
+            Some(b'f') => self.literal("false", Value::Boolean(false)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of the document")),
+        }
+    }
+
+    // Calls `item` for each comma separated item up to `end`
+    fn items<F: FnMut(&mut Self) -> Result<()>>(&mut self, end: u8, mut item: F) -> Result<()> {
+        self.position += 1;
+        self.skip_whitespace();
+
+        if self.peek() == Some(end) {
+            self.position += 1;
+            return Ok(());
+        }
+
+        loop {
+            item(self)?;
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b) if b == end => {
+                    self.position += 1;
+                    return Ok(());
+                }
+                _ => return Err(self.error(&format!("expected ',' or '{}'", end as char))),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value> {
+        let mut members = Vec::new();
+
+        self.items(b'}', |parser| {
+            parser.skip_whitespace();
+
+            if parser.peek() != Some(b'"') {
+                return Err(parser.error("expected a member name"));
+            }
+
+            let key = parser.string()?;
+            parser.expect(b':')?;
+            members.push((key, parser.value()?));
+
+            Ok(())
+        })?;
+
+        Ok(Value::Object(members))
+    }
+
+    fn array(&mut self) -> Result<Value> {
+        let mut items = Vec::new();
+
+        self.items(b']', |parser| {
+            items.push(parser.value()?);
+            Ok(())
+        })?;
+
+        Ok(Value::Array(items))
+    }
+
+    fn number(&mut self) -> Result<Value> {
+        let start = self.position;
+
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.peek() {
+            self.position += 1;
+        }
+
+        self.contents[start..self.position]
+            .parse()
+            .map(Value::Number)
+            .map_err(|_| TcpIpError::parse_at("Invalid JSON - invalid number", start))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.position += 1;
+
+        let mut value = String::new();
+
+        loop {
+            let start = self.position;
+
+            while let Some(b) = self.peek() {
+                if b == b'"' || b == b'\\' || b < 0x20 {
+                    break;
+                }
+                self.position += 1;
+            }
+
+            value.push_str(&self.contents[start..self.position]);
+
+            match self.peek() {
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(value);
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    let escaped = self
+                        .peek()
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.position += 1;
+
+                    match escaped {
+                        b'"' => value.push('"'),
+                        b'\\' => value.push('\\'),
+                        b'/' => value.push('/'),
+                        b'b' => value.push('\u{8}'),
+                        b'f' => value.push('\u{c}'),
+                        b'n' => value.push('\n'),
+                        b'r' => value.push('\r'),
+                        b't' => value.push('\t'),
+                        b'u' => value.push(self.unicode_escape()?),
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                Some(_) => return Err(self.error("control character in string")),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let digits = self
+            .contents
+            .get(self.position..self.position + 4)
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+
+        self.position += 4;
+        Ok(digits)
+    }
+
+    // `\uXXXX`, which is one half of a surrogate pair for characters outside the BMP
+    fn unicode_escape(&mut self) -> Result<char> {
+        let high = self.hex4()?;
+
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if !self.bytes[self.position..].starts_with(b"\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+
+            self.position += 2;
+            let low = self.hex4()?;
+
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(self.error("unpaired surrogate"));
+            }
+
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::json::{parse, Value};
+    use crate::util::json_string;
+
+    #[test]
+    fn test_parse() {
+        let value = parse(r#" {"a": [1, -2.5e1, true, null], "b": {"c": "d\"é😀"}, "e": []} "#)
+            .expect("Failed to parse");
+
+        assert_eq!(
+            value.get("a").map(Value::items),
+            Some(
+                &[
+                    Value::Number(1.0),
+                    Value::Number(-25.0),
+                    Value::Boolean(true),
+                    Value::Null
+                ][..]
+            )
+        );
+        assert_eq!(
+            value
+                .get("b")
+                .and_then(|b| b.get("c"))
+                .and_then(Value::as_str),
+            Some("d\"\u{e9}\u{1f600}")
+        );
+        assert!(value
+            .get("e")
+            .map(Value::items)
+            .is_some_and(<[_]>::is_empty));
+
+        let text = "tab\t \"quote\" \u{1} \u{1f600}";
+
+        assert_eq!(
+            parse(&json_string(text)).expect("Failed to parse"),
+            Value::String(text.to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |contents: &str| {
+            parse(contents)
+                .expect_err("Parsed invalid JSON")
+                .to_string()
+        };
+
+        assert_eq!(
+            error(r#"{"a": 1,}"#),
+            "Invalid JSON - expected a member name at byte 8"
+        );
+        assert_eq!(
+            error("[1 2]"),
+            "Invalid JSON - expected ',' or ']' at byte 3"
+        );
+        assert_eq!(
+            error(r#""abc"#),
+            "Invalid JSON - unterminated string at byte 4"
+        );
+        assert_eq!(
+            error("{} x"),
+            "Invalid JSON - unexpected data after the value at byte 3"
+        );
+    }
+}
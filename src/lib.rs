@@ -1,13 +1,29 @@
 use crate::error::TcpIpError;
 
+pub mod access_log;
+pub mod address;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod body;
 pub mod body_type;
+pub mod client;
 pub mod config;
 pub mod error;
+pub mod error_page;
+pub mod forward_proxy;
+pub mod har;
 pub mod header_item;
 pub mod header_map;
+pub mod header_parser;
 pub mod http_item;
+pub mod http_version;
+pub mod json;
+pub mod monitor;
+pub mod replay;
 pub mod request;
 pub mod response;
+pub mod router;
+pub mod server;
 pub mod stream_helper;
 pub mod util;
 
@@ -0,0 +1,350 @@
+use std::fmt::Formatter;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::config::{Config, LoadOptions};
+use crate::error::TcpIpError;
+use crate::server::ServerHandle;
+use crate::Result;
+
+/// How often `Monitor::watch` checks the config file for changes.
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Runs every server in a config, and applies a changed config without
+/// dropping connections that are forwarding a request.
+pub struct Monitor {
+    servers: Vec<ServerHandle>,
+}
+
+/// Stops `Monitor::watch` from another thread, such as one waiting for SIGTERM,
+/// after which its servers are drained with `Monitor::shutdown_timeout`.
+///
+/// Clones stop the same watch loop.
+#[derive(Debug, Clone, Default)]
+pub struct StopHandle {
+    // The drain timeout, set once `stop` is called
+    timeout: Arc<(Mutex<Option<Duration>>, Condvar)>,
+}
+
+impl StopHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the watch loop, closing connections still forwarding a request
+    /// after `timeout`. Only the first call has an effect.
+    pub fn stop(&self, timeout: Duration) {
+        let (stop_timeout, stopped) = &*self.timeout;
+
+        stop_timeout
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_or_insert(timeout);
+        stopped.notify_all();
+    }
+
+    // Waits up to `interval` for `stop`, returning its timeout if it was called
+    fn wait(&self, interval: Duration) -> Option<Duration> {
+        let (stop_timeout, stopped) = &*self.timeout;
+        let stop_timeout = stop_timeout.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let (stop_timeout, _) = stopped
+            .wait_timeout_while(stop_timeout, interval, |timeout| timeout.is_none())
+            .unwrap_or_else(PoisonError::into_inner);
+
+        *stop_timeout
+    }
+}
+
+/// The names of the servers affected by `Monitor::reload`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Reload {
+    pub started: Vec<String>,
+    pub stopped: Vec<String>,
+    /// Servers whose new options apply to connections accepted from now on.
+    pub updated: Vec<String>,
+    /// Servers that couldn't be started or updated, such as when their port
+    /// is in use or their log file can't be opened. Servers that fail to
+    /// update keep running with their old options.
+    pub failed: Vec<String>,
+}
+
+impl Monitor {
+    /// Starts every server in `config`, stopping them all if one fails to start.
+    pub fn start(config: Config) -> Result<Self> {
+        let mut monitor = Self {
+            servers: Vec::new(),
+        };
+
+        for server in config.servers {
+            let name = server.name.clone();
+
+            match ServerHandle::spawn(server) {
+                Ok(running) => monitor.servers.push(running),
+                Err(e) => {
+                    monitor.reload(Config {
+                        servers: Vec::new(),
+                    });
+
+                    return Err(TcpIpError::config(format!(
+                        "Failed to start server '{}' - {}",
+                        name, e
+                    )));
+                }
+            }
+        }
+
+        Ok(monitor)
+    }
+
+    /// Applies a new config, matching servers by name.
+    ///
+    /// Servers that were removed or whose listen address changed stop accepting
+    /// connections and close them as their current request finishes. New
+    /// servers are started, and other changed options apply to connections
+    /// accepted from now on.
+    pub fn reload(&mut self, config: Config) -> Reload {
+        let mut reload = Reload::default();
+
+        let (mut kept, removed): (Vec<_>, Vec<_>) = self.servers.drain(..).partition(|running| {
+            config.servers.iter().any(|s| {
+                s.name == running.server.name && s.listen_address == running.server.listen_address
+            })
+        });
+
+        // Stop removed servers first so a new server can reuse their address
+        for mut running in removed {
+            running.stop_accepting();
+            reload.stopped.push(running.server.name.clone());
+
+            thread::spawn(move || running.wait(None));
+        }
+
+        for server in config.servers {
+            if let Some(running) = kept.iter_mut().find(|r| r.server.name == server.name) {
+                if running.server != server {
+                    let name = server.name.clone();
+
+                    match running.update(server) {
+                        Ok(()) => reload.updated.push(name),
+                        Err(e) => {
+                            eprintln!("Failed to update server '{}' - {}", name, e);
+                            reload.failed.push(name);
+                        }
+                    }
+                }
+
+                continue;
+            }
+
+            let name = server.name.clone();
+
+            match ServerHandle::spawn(server) {
+                Ok(running) => {
+                    reload.started.push(name);
+                    kept.push(running);
+                }
+                Err(e) => {
+                    eprintln!("Failed to start server '{}' - {}", name, e);
+                    reload.failed.push(name);
+                }
+            }
+        }
+
+        self.servers = kept;
+
+        reload
+    }
+
+    /// Stops every server, closing any connection still forwarding a request
+    /// after `timeout`. Returns the number of connections that were closed
+    /// before their request finished.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+
+        for server in &mut self.servers {
+            server.stop_accepting();
+        }
+
+        self.servers
+            .iter()
+            .map(|server| server.wait(Some(deadline.saturating_duration_since(Instant::now()))))
+            .sum()
+    }
+
+    /// Loads the config and runs its servers, reloading the config whenever
+    /// the file is modified. Errors in a modified config are printed and the
+    /// running servers are left unchanged.
+    ///
+    /// Runs until `stop` is stopped, then shuts the servers down like
+    /// `shutdown_timeout` and returns the number of connections closed before
+    /// their request finished. Returns early if the config can't be loaded or
+    /// its servers started.
+    pub fn watch(options: &LoadOptions, interval: Duration, stop: &StopHandle) -> Result<usize> {
+        let config = Config::load_with(options)?;
+
+        let path = Config::path(options)
+            .ok_or_else(|| TcpIpError::config("Missing config file to watch"))?;
+
+        let options = LoadOptions {
+            path: Some(path.clone()),
+            check: false,
+        };
+
+        let mut modified = modified_time(&path);
+        let mut monitor = Self::start(config)?;
+
+        loop {
+            if let Some(timeout) = stop.wait(interval) {
+                return Ok(monitor.shutdown_timeout(timeout));
+            }
+
+            let current = modified_time(&path);
+
+            if current == modified {
+                continue;
+            }
+
+            modified = current;
+
+            match Config::load_with(&options) {
+                Ok(config) => println!("{}", monitor.reload(config)),
+                Err(e) => eprintln!("Failed to reload '{}' - {}", path.display(), e),
+            }
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl std::fmt::Display for Reload {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Config reloaded.")?;
+
+        for (action, names) in &[
+            ("Started", &self.started),
+            ("Stopped", &self.stopped),
+            ("Updated", &self.updated),
+            ("Failed to start or update", &self.failed),
+        ] {
+            if !names.is_empty() {
+                write!(f, " {} '{}'.", action, names.join("', '"))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::address::Address;
+    use crate::config::{Config, LoadOptions, Server};
+    use crate::monitor::{Monitor, Reload, StopHandle};
+
+    fn server(name: &str, port: u16) -> Server {
+        let mut server = Server::new(
+            name,
+            Address::new("127.0.0.1", port),
+            Address::new("127.0.0.1", 9),
+        );
+        server.log = false;
+        server
+    }
+
+    #[test]
+    fn test_reload() {
+        let mut monitor = Monitor::start(Config {
+            servers: vec![server("api", 0), server("admin", 0)],
+        })
+        .expect("Failed to start monitor");
+
+        let mut api = server("api", 0);
+        api.timeout = 10;
+
+        let reload = monitor.reload(Config {
+            servers: vec![api.clone(), server("web", 0)],
+        });
+
+        assert_eq!(
+            reload,
+            Reload {
+                started: vec!["web".to_owned()],
+                stopped: vec!["admin".to_owned()],
+                updated: vec!["api".to_owned()],
+                failed: vec![],
+            }
+        );
+        assert_eq!(
+            reload.to_string(),
+            "Config reloaded. Started 'web'. Stopped 'admin'. Updated 'api'."
+        );
+
+        // A server already listening on an address can't be started again
+        let taken = monitor.servers[0].local_address();
+
+        let reload = monitor.reload(Config {
+            servers: vec![api, server("web", 0), server("copy", taken.port())],
+        });
+
+        assert_eq!(reload.failed, ["copy"]);
+        assert!(reload.started.is_empty() && reload.updated.is_empty());
+
+        let reload = monitor.reload(Config {
+            servers: Vec::new(),
+        });
+
+        assert_eq!(reload.stopped, ["api", "web"]);
+    }
+
+    #[test]
+    fn test_stop_watch() {
+        let dir = std::env::temp_dir().join(format!("http_lib_monitor_{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("Failed to create directory");
+
+        let path = dir.join("monitor.toml");
+        fs::write(
+            &path,
+            "[servers.api]\nlisten = 0\nremote = \"127.0.0.1:9\"\nlog = false\n",
+        )
+        .expect("Failed to write config");
+
+        let stop = StopHandle::new();
+        let watching = {
+            let stop = stop.clone();
+
+            thread::spawn(move || {
+                let options = LoadOptions {
+                    path: Some(path),
+                    check: false,
+                };
+
+                Monitor::watch(&options, Duration::from_secs(60), &stop)
+            })
+        };
+
+        // Stopping wakes the loop rather than waiting for the next check
+        thread::sleep(Duration::from_millis(100));
+        let stopped = Instant::now();
+        stop.stop(Duration::from_secs(1));
+
+        let closed = watching
+            .join()
+            .expect("Watch panicked")
+            .expect("Failed to watch");
+
+        assert_eq!(closed, 0);
+        assert!(stopped.elapsed() < Duration::from_secs(10));
+
+        fs::remove_dir_all(&dir).expect("Failed to remove directory");
+    }
+}
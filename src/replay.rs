@@ -0,0 +1,500 @@
+//! Answers requests with responses recorded in a HAR file instead of forwarding
+//! them, so clients can be tested offline against how a remote server behaved.
+//!
+//! Exchanges are recorded by capturing them with `har_file`, see `har`. A
+//! request is answered with the first recorded exchange it matches that has not
+//! been replayed yet, or with the last one it matches once they all have been.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, PoisonError};
+
+use crate::body::Body;
+use crate::error::TcpIpError;
+use crate::error_page::ErrorPage;
+use crate::har::base64_decode;
+use crate::header_item::HeaderItem;
+use crate::header_map::HeaderMap;
+use crate::http_item::HttpItem;
+use crate::http_version::HttpVersion;
+use crate::json::{self, Value};
+use crate::request::request_method::RequestMethod;
+use crate::request::uri::Uri;
+use crate::request::Request;
+use crate::response::response_header::ResponseHeader;
+use crate::response::Response;
+use crate::stream_helper::{set_connection_headers, KeepAlive};
+use crate::Result;
+
+/// What a request must have in common with a recorded request to be answered
+/// with its response, in addition to the method and target.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Matching {
+    /// Headers that must have the same values, compared case-insensitively by name.
+    pub headers: Vec<String>,
+    pub body: bool,
+}
+
+impl Default for Matching {
+    fn default() -> Self {
+        Self {
+            headers: Vec::new(),
+            body: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ReplayOptions {
+    /// The HAR file responses are replayed from, requests are forwarded if not set.
+    pub file: Option<PathBuf>,
+    pub matching: Matching,
+}
+
+struct Recorded {
+    method: RequestMethod,
+    target: String,
+    request_headers: HeaderMap,
+    request_body: Vec<u8>,
+    status_code: u16,
+    reason_phrase: String,
+    response_headers: HeaderMap,
+    response_body: Vec<u8>,
+}
+
+impl Recorded {
+    fn from_entry(entry: &Value) -> Result<Option<Self>> {
+        let request = field(entry, "request")?;
+        let response = field(entry, "response")?;
+
+        // Capturing records a status of 0 when the connection failed before a response
+        let status_code = match field(response, "status")?.as_f64() {
+            Some(status) if (100.0..1000.0).contains(&status) => status as u16,
+            Some(_) => return Ok(None),
+            None => return Err(TcpIpError::parse("'status' must be a number")),
+        };
+
+        let content = field(response, "content")?;
+        let response_headers = header_map(field(response, "headers")?);
+
+        // Captures keep content-encoded bodies as they were sent, see `har`
+        let response_headers = if matches!(content.get("_encoded"), Some(Value::Boolean(true))) {
+            response_headers
+        } else {
+            decoded_headers(response_headers)
+        };
+
+        Ok(Some(Self {
+            method: RequestMethod::from_str(text(request, "method")?)?,
+            target: text(request, "url")?.parse::<Uri>()?.origin_form(),
+            request_headers: header_map(field(request, "headers")?),
+            request_body: match request.get("postData") {
+                Some(post_data) => body(post_data, "_encoding")?,
+                None => Vec::new(),
+            },
+            status_code,
+            reason_phrase: text(response, "statusText")?.to_owned(),
+            response_headers,
+            response_body: body(content, "encoding")?,
+        }))
+    }
+
+    fn matches(&self, request: &Request, matching: &Matching) -> bool {
+        let values = |headers: Option<&HeaderMap>, name: &str| {
+            headers
+                .into_iter()
+                .flat_map(|h| h.get_all(name))
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+        };
+
+        self.method == request.header.method
+            && self.target == request.header.uri.origin_form()
+            && matching.headers.iter().all(|name| {
+                values(Some(&self.request_headers), name)
+                    == values(request.header.headers().as_ref(), name)
+            })
+            && (!matching.body || request.body.as_bytes().unwrap_or_default() == self.request_body)
+    }
+}
+
+/// The recorded exchanges a server answers requests with.
+pub struct Replay {
+    recorded: Vec<Recorded>,
+    matching: Matching,
+    replayed: Mutex<Vec<bool>>,
+}
+
+impl Replay {
+    /// Reads the exchanges in a HAR file. Entries without a response, such as
+    /// those captured when the remote server couldn't be reached, are skipped.
+    pub fn from_har(contents: &str, matching: Matching) -> Result<Self> {
+        let har = json::parse(contents)?;
+        let entries = har
+            .get("log")
+            .and_then(|log| log.get("entries"))
+            .ok_or_else(|| TcpIpError::parse("Missing 'log.entries'"))?
+            .items();
+
+        let mut recorded = Vec::new();
+
+        for (i, entry) in entries.iter().enumerate() {
+            let entry = Recorded::from_entry(entry).map_err(|e| {
+                TcpIpError::parse(format!("HAR entry {} is invalid - {}", i + 1, e))
+            })?;
+
+            recorded.extend(entry);
+        }
+
+        Ok(Self {
+            replayed: Mutex::new(vec![false; recorded.len()]),
+            recorded,
+            matching,
+        })
+    }
+
+    pub fn open(path: &Path, matching: Matching) -> Result<Self> {
+        Self::from_har(&fs::read_to_string(path)?, matching)
+    }
+
+    pub fn len(&self) -> usize {
+        self.recorded.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.recorded.is_empty()
+    }
+
+    /// Returns the recorded response to `request`, whose body must have been
+    /// buffered, or None if no recorded request matches it.
+    pub fn find(&self, request: &Request) -> Option<Response<'static>> {
+        let mut replayed = self.replayed.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let matches = self
+            .recorded
+            .iter()
+            .enumerate()
+            .filter(|(_, recorded)| recorded.matches(request, &self.matching))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+
+        let index = matches
+            .iter()
+            .find(|i| !replayed[**i])
+            .or_else(|| matches.last())
+            .copied()?;
+
+        replayed[index] = true;
+
+        let recorded = &self.recorded[index];
+        let header = ResponseHeader::new(
+            HttpVersion::Http11,
+            recorded.status_code,
+            &recorded.reason_phrase,
+            Some(recorded.response_headers.clone()).filter(|h| !h.is_empty()),
+        );
+        let body = if recorded.response_body.is_empty() {
+            Body::Empty
+        } else {
+            Body::from(recorded.response_body.clone())
+        };
+        let mut response = Response::new(header, body);
+
+        // The recorded framing was for the body as sent, which may have been chunked
+        // or content-encoded
+        if !response.body.is_empty() {
+            let content_length = response.body.content_length();
+
+            response
+                .header
+                .frame_body(content_length, HttpVersion::Http11);
+        }
+
+        Some(response)
+    }
+
+    /// Returns the response to send for `request` over the local connection,
+    /// which is an error page if nothing was recorded for it.
+    pub(crate) fn respond(
+        &self,
+        request: &Request,
+        keep_alive: Option<KeepAlive>,
+        error_page: &ErrorPage,
+    ) -> Result<Response<'static>> {
+        let mut response = match self.find(request) {
+            Some(mut response) => {
+                response.header.strip_hop_by_hop();
+
+                // Bodies are framed again as they were decoded when they were captured
+                let has_body = response.header.body_type_for(&request.header.method);
+
+                if !response.body.is_empty() || matches!(has_body, Ok(Some(_))) {
+                    let content_length = response.body.content_length();
+
+                    response
+                        .header
+                        .frame_body(content_length, request.header.version);
+                }
+
+                response
+            }
+            None => {
+                let e = TcpIpError::NotRecorded {
+                    method: request.header.method.to_string(),
+                    uri: request.header.uri.to_string(),
+                };
+
+                error_page.render(e.to_status(), &e.to_string())?
+            }
+        };
+
+        set_connection_headers(&mut response.header, keep_alive);
+
+        Ok(response)
+    }
+}
+
+fn field<'a>(value: &'a Value, key: &str) -> Result<&'a Value> {
+    value
+        .get(key)
+        .ok_or_else(|| TcpIpError::parse(format!("missing '{}'", key)))
+}
+
+fn text<'a>(value: &'a Value, key: &str) -> Result<&'a str> {
+    field(value, key)?
+        .as_str()
+        .ok_or_else(|| TcpIpError::parse(format!("'{}' must be a string", key)))
+}
+
+fn header_map(headers: &Value) -> HeaderMap {
+    let mut map = HeaderMap::new();
+
+    for header in headers.items() {
+        if let (Some(name), Some(value)) = (
+            header.get("name").and_then(Value::as_str),
+            header.get("value").and_then(Value::as_str),
+        ) {
+            map.append(name, value);
+        }
+    }
+
+    map
+}
+
+// HAR `content.text` is the decoded body, so the response is replayed without
+// its `Content-Encoding` and the `Content-Length` of the encoded body
+fn decoded_headers(mut headers: HeaderMap) -> HeaderMap {
+    if headers.remove_all("Content-Encoding") > 0 {
+        headers.remove_all("Content-Length");
+    }
+
+    headers
+}
+
+// The body in HAR `postData` or `content`, which is base64 if `encoding_key` says so
+fn body(content: &Value, encoding_key: &str) -> Result<Vec<u8>> {
+    let text = content.get("text").and_then(Value::as_str).unwrap_or("");
+
+    match content.get(encoding_key).and_then(Value::as_str) {
+        Some("base64") => {
+            base64_decode(text).ok_or_else(|| TcpIpError::parse("'text' is not valid base64"))
+        }
+        _ => Ok(text.as_bytes().to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::error_page::ErrorPage;
+    use crate::har::{Exchange, Recording};
+    use crate::http_item::HttpItem;
+    use crate::replay::{Matching, Replay};
+    use crate::request::request_method::RequestMethod;
+    use crate::request::{Request, RequestBuilder};
+
+    const HAR: &str = r#"{"log":{"version":"1.2","entries":[
+{"request":{"method":"GET","url":"http://api.test/items?page=1","headers":[{"name":"Accept","value":"application/json"}]},
+ "response":{"status":200,"statusText":"OK","headers":[{"name":"Content-Type","value":"application/json"},{"name":"Transfer-Encoding","value":"chunked"}],"content":{"size":2,"mimeType":"application/json","text":"[]"}}},
+{"request":{"method":"GET","url":"http://api.test/items?page=1","headers":[{"name":"Accept","value":"text/html"}]},
+ "response":{"status":406,"statusText":"Not Acceptable","headers":[],"content":{"size":0,"mimeType":"x-unknown"}}},
+{"request":{"method":"POST","url":"http://api.test/items","headers":[],"postData":{"mimeType":"application/octet-stream","params":[],"text":"/wA=","_encoding":"base64"}},
+ "response":{"status":201,"statusText":"Created","headers":[{"name":"Set-Cookie","value":"a=1"},{"name":"Set-Cookie","value":"b=2"}],"content":{"size":2,"mimeType":"x-unknown","text":"/wE=","encoding":"base64"}}},
+{"request":{"method":"GET","url":"http://api.test/down","headers":[]},
+ "response":{"status":0,"statusText":"","httpVersion":"","headers":[],"content":{"size":0,"mimeType":"x-unknown"}}}
+]}}"#;
+
+    fn request(method: RequestMethod, uri: &str, accept: &str, body: &[u8]) -> Request<'static> {
+        RequestBuilder::new()
+            .method(method)
+            .uri(uri)
+            .header("Accept", accept)
+            .body(body.to_vec())
+            .build()
+            .expect("Failed to build request")
+    }
+
+    fn replayed(replay: &Replay, request: &Request) -> String {
+        replay
+            .respond(request, None, &ErrorPage::new("text/plain", "{message}"))
+            .expect("Failed to respond")
+            .as_string()
+            .expect("Binary response")
+    }
+
+    #[test]
+    fn test_replay() {
+        let replay = Replay::from_har(HAR, Matching::default()).expect("Failed to read HAR");
+
+        assert_eq!(replay.len(), 3);
+
+        let get = request(RequestMethod::Get, "/items?page=1", "*/*", b"");
+
+        assert_eq!(
+            replayed(&replay, &get),
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]"
+        );
+        // Every recorded match has been replayed so the last is repeated
+        assert_eq!(
+            replayed(&replay, &get),
+            "HTTP/1.1 406 Not Acceptable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+        assert_eq!(
+            replayed(&replay, &get),
+            "HTTP/1.1 406 Not Acceptable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+
+        let post = request(
+            RequestMethod::Post,
+            "http://api.test/items",
+            "*/*",
+            &[0xff, 0],
+        );
+        let response = replay.find(&post).expect("Missing recorded response");
+
+        assert_eq!(response.header.status_code, 201);
+        assert_eq!(response.body.as_bytes(), Some(&[0xff, 1][..]));
+        assert_eq!(
+            response
+                .header
+                .headers
+                .as_ref()
+                .map(|h| h.get_all("set-cookie").collect::<Vec<_>>()),
+            Some(vec!["a=1", "b=2"])
+        );
+
+        assert!(replay
+            .find(&request(RequestMethod::Post, "/items", "*/*", b"other"))
+            .is_none());
+        assert_eq!(
+            replayed(&replay, &request(RequestMethod::Get, "/down", "*/*", b"")),
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 40\r\nConnection: close\r\n\r\nNo recorded response matches 'GET /down'"
+        );
+    }
+
+    #[test]
+    fn test_replay_matching_headers() {
+        let matching = Matching {
+            headers: vec!["accept".to_owned()],
+            body: false,
+        };
+        let replay = Replay::from_har(HAR, matching).expect("Failed to read HAR");
+
+        let html = request(RequestMethod::Get, "/items?page=1", "text/html", b"");
+        let json = request(RequestMethod::Get, "/items?page=1", "application/json", b"");
+
+        assert_eq!(replay.find(&html).map(|r| r.header.status_code), Some(406));
+        assert_eq!(replay.find(&json).map(|r| r.header.status_code), Some(200));
+        assert!(replay
+            .find(&request(RequestMethod::Get, "/items?page=1", "*/*", b""))
+            .is_none());
+        assert_eq!(
+            replay
+                .find(&request(
+                    RequestMethod::Get,
+                    "/items?page=1",
+                    "text/html",
+                    b"body"
+                ))
+                .map(|r| r.header.status_code),
+            Some(406)
+        );
+    }
+
+    #[test]
+    fn test_replay_decoded_content() {
+        // As exported by browser devtools, with the text of a gzipped response decoded
+        let har = r#"{"log":{"version":"1.2","entries":[
+{"request":{"method":"GET","url":"https://api.test/greeting","headers":[]},
+ "response":{"status":200,"statusText":"OK","headers":[{"name":"content-encoding","value":"gzip"},{"name":"content-length","value":"33"},{"name":"content-type","value":"text/plain"}],"content":{"size":13,"mimeType":"text/plain","compression":20,"text":"Hello, world!"}}}
+]}}"#;
+        let replay = Replay::from_har(har, Matching::default()).expect("Failed to read HAR");
+        let get = request(RequestMethod::Get, "/greeting", "*/*", b"");
+
+        let response = replay.find(&get).expect("Missing recorded response");
+        let headers = response.header.headers.as_ref().expect("Missing headers");
+
+        assert_eq!(headers.get("Content-Encoding"), None);
+        assert_eq!(headers.get("Content-Length"), Some("13"));
+
+        assert_eq!(
+            replayed(&replay, &get),
+            "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\nContent-Length: 13\r\nConnection: close\r\n\r\nHello, world!"
+        );
+    }
+
+    #[test]
+    fn test_replay_captured_encoded_content() {
+        let mut captured_request = Recording::new(1024);
+        captured_request.push(b"GET /greeting HTTP/1.1\r\nHost: api.test\r\n\r\n");
+
+        let mut captured_response = Recording::new(1024);
+        captured_response.push(
+            b"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: 4\r\n\r\n\x1f\x8b\x08\xff",
+        );
+
+        let entry = Exchange {
+            started: UNIX_EPOCH,
+            time: Duration::from_millis(1),
+            wait: None,
+            request: &captured_request,
+            response: &captured_response,
+        }
+        .to_entry("api.test")
+        .expect("Failed to create entry");
+
+        let har = format!(r#"{{"log":{{"version":"1.2","entries":[{}]}}}}"#, entry);
+        let replay = Replay::from_har(&har, Matching::default()).expect("Failed to read HAR");
+
+        // The body was captured still encoded, so it is replayed with its encoding
+        let response = replay
+            .find(&request(RequestMethod::Get, "/greeting", "*/*", b""))
+            .expect("Missing recorded response");
+        let headers = response.header.headers.as_ref().expect("Missing headers");
+
+        assert_eq!(headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(headers.get("Content-Length"), Some("4"));
+        assert_eq!(
+            response.body.as_bytes(),
+            Some(&[0x1f, 0x8b, 0x08, 0xff][..])
+        );
+    }
+
+    #[test]
+    fn test_from_har_errors() {
+        let error = |contents: &str| {
+            Replay::from_har(contents, Matching::default())
+                .err()
+                .expect("Read invalid HAR")
+                .to_string()
+        };
+
+        assert_eq!(error("{}"), "Missing 'log.entries'");
+        assert_eq!(
+            error(r#"{"log":{"entries":[{"request":{}}]}}"#),
+            "HAR entry 1 is invalid - missing 'response'"
+        );
+    }
+}
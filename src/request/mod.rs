@@ -1,20 +1,24 @@
 use std::fmt::Formatter;
 
+use crate::body::Body;
 use crate::error::ErrorExt;
 use crate::header_map::HeaderMap;
 use crate::http_item::HttpItem;
+use crate::http_version::HttpVersion;
 use crate::request::request_header::RequestHeader;
 use crate::request::request_method::RequestMethod;
+use crate::router::Params;
 use crate::Result;
 
 pub mod request_header;
 pub mod request_method;
+pub mod uri;
 
 #[derive(Debug, Default)]
 pub struct RequestBuilder {
     method: Option<RequestMethod>,
     uri: Option<String>,
-    version: f32,
+    version: HttpVersion,
     headers: Option<HeaderMap>,
     body: Option<Vec<u8>>,
 }
@@ -22,7 +26,7 @@ pub struct RequestBuilder {
 impl RequestBuilder {
     pub fn new() -> Self {
         RequestBuilder {
-            version: 1.1,
+            version: HttpVersion::Http11,
             ..Self::default()
         }
     }
@@ -37,7 +41,7 @@ impl RequestBuilder {
         self
     }
 
-    pub fn version(mut self, version: f32) -> Self {
+    pub fn version(mut self, version: HttpVersion) -> Self {
         self.version = version;
         self
     }
@@ -59,26 +63,40 @@ impl RequestBuilder {
         self
     }
 
-    pub fn build(self) -> Result<Request> {
+    pub fn build(self) -> Result<Request<'static>> {
         let method = self.method.context("Missing request_method")?;
-        let uri = self.uri.context("Missing URI")?;
+        let uri = self.uri.context("Missing URI")?.parse()?;
         let version = self.version;
         let headers = self.headers;
-        let body = self.body;
+        let body = self.body.map(Body::from).unwrap_or_default();
 
         let header = RequestHeader::new(method, uri, version, headers);
 
-        Ok(Request { header, body })
+        Ok(Request {
+            header,
+            body,
+            params: Params::default(),
+        })
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct Request {
+#[derive(Debug)]
+pub struct Request<'a> {
     pub header: RequestHeader,
-    pub body: Option<Vec<u8>>,
+    pub body: Body<'a>,
+    /// The path parameters of the route the request matched, see `router`.
+    pub params: Params,
 }
 
-impl HttpItem for Request {
+impl Request<'_> {
+    /// Returns the value of the path parameter or wildcard `name` of the route
+    /// the request matched.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name)
+    }
+}
+
+impl<'a> HttpItem<'a> for Request<'a> {
     type HeaderType = RequestHeader;
 
     fn item_name(&self) -> &str {
@@ -89,16 +107,28 @@ impl HttpItem for Request {
         &self.header
     }
 
-    fn body(&self) -> Option<Vec<u8>> {
-        self.body.clone()
+    fn header_mut(&mut self) -> &mut Self::HeaderType {
+        &mut self.header
+    }
+
+    fn body(&self) -> &Body<'a> {
+        &self.body
+    }
+
+    fn body_mut(&mut self) -> &mut Body<'a> {
+        &mut self.body
     }
 
-    fn new(header: Self::HeaderType, body: Option<Vec<u8>>) -> Self {
-        Self { header, body }
+    fn new(header: Self::HeaderType, body: Body<'a>) -> Self {
+        Self {
+            header,
+            body,
+            params: Params::default(),
+        }
     }
 }
 
-impl std::fmt::Display for Request {
+impl std::fmt::Display for Request<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         self.display(f)
     }
@@ -106,19 +136,22 @@ impl std::fmt::Display for Request {
 
 #[cfg(test)]
 mod tests {
+    use crate::body::Body;
     use crate::header_map::HeaderMap;
     use crate::http_item::HttpItem;
+    use crate::http_version::HttpVersion;
     use crate::request::request_header::RequestHeader;
     use crate::request::request_method::RequestMethod;
     use crate::request::Request;
+    use crate::router::Params;
 
     #[test]
     fn test_to_bytes() {
         let request = Request {
             header: RequestHeader {
                 method: RequestMethod::Get,
-                uri: "/abc/123".to_string(),
-                version: 1.1,
+                uri: "/abc/123".parse().expect("Invalid URI"),
+                version: HttpVersion::Http11,
                 headers: Some(HeaderMap {
                     headers: vec![
                         ("Content-Type".to_owned(), "application/json".to_owned()),
@@ -127,10 +160,11 @@ mod tests {
                     ],
                 }),
             },
-            body: Some(vec![
+            body: Body::from(vec![
                 123, 10, 9, 34, 100, 97, 116, 97, 34, 58, 32, 34, 104, 101, 108, 108, 111, 32, 119,
                 111, 114, 108, 100, 34, 10, 125,
             ]),
+            params: Params::default(),
         };
 
         let request_str_raw = "GET /abc/123 HTTP/1.1\r\nContent-Type: application/json\r\nAccept: */*\r\nContent-Length: 26\r\n\r\n{\n\t\"data\": \"hello world\"\n}";
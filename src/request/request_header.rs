@@ -1,29 +1,33 @@
 use std::io::{Read, Write};
 
+use crate::body_type::BodyType;
 use crate::error::TcpIpError;
 use crate::header_item::HeaderItem;
 use crate::header_map::HeaderMap;
+use crate::http_version::HttpVersion;
 use crate::request::request_method::RequestMethod;
+use crate::request::uri::Uri;
+use crate::util::str_offset;
 use crate::Result;
 
 #[derive(Debug, Clone)]
 pub struct RequestHeader {
     pub method: RequestMethod,
-    pub uri: String,
-    pub version: f32,
+    pub uri: Uri,
+    pub version: HttpVersion,
     pub headers: Option<HeaderMap>,
 }
 
 impl RequestHeader {
-    pub fn new<T: AsRef<str>>(
+    pub fn new(
         method: RequestMethod,
-        uri: T,
-        version: f32,
+        uri: Uri,
+        version: HttpVersion,
         headers: Option<HeaderMap>,
     ) -> Self {
         RequestHeader {
             method,
-            uri: uri.as_ref().to_owned(),
+            uri,
             version,
             headers,
         }
@@ -31,6 +35,33 @@ impl RequestHeader {
 }
 
 impl HeaderItem for RequestHeader {
+    fn body_type(&self) -> Result<Option<BodyType>> {
+        // HTTP/1.0 has no Transfer-Encoding, so it can't be trusted to frame the
+        // body, see RFC 9112 section 6.1
+        let transfer_encoding = self
+            .headers
+            .as_ref()
+            .and_then(|headers| headers.get("Transfer-Encoding"));
+
+        if self.version < HttpVersion::Http11 && transfer_encoding.is_some() {
+            return Err(TcpIpError::parse(
+                "Transfer-Encoding is not supported in HTTP/1.0 requests",
+            ));
+        }
+
+        // A request without framing headers has no body as it can't be delimited by closing
+        match self.framed_body_type()? {
+            Some(BodyType::UntilClose) => Err(TcpIpError::parse(
+                "Request Transfer-Encoding must end with chunked",
+            )),
+            body_type => Ok(body_type),
+        }
+    }
+
+    fn version(&self) -> HttpVersion {
+        self.version
+    }
+
     fn headers(&self) -> &Option<HeaderMap> {
         &self.headers
     }
@@ -48,31 +79,41 @@ impl HeaderItem for RequestHeader {
 
         let mut header_str_lines = header_str.lines();
 
-        let mut request_line = header_str_lines
+        let request_line = header_str_lines
             .next()
-            .ok_or_else(|| TcpIpError::new("Failed to read HTTP Request line"))?
-            .split_whitespace();
+            .ok_or_else(|| TcpIpError::parse("Failed to read HTTP Request line"))?;
 
-        let method = request_line
-            .next()
-            .ok_or_else(|| TcpIpError::new("Failed to read HTTP Request Method"))?
-            .parse::<RequestMethod>()?;
+        let mut parts = request_line.split_whitespace();
 
-        let uri = request_line
+        let method = parts
             .next()
-            .ok_or_else(|| TcpIpError::new("Failed to read HTTP Request URI"))?;
+            .ok_or_else(|| TcpIpError::parse_at("Failed to read HTTP Request Method", 0))?;
 
-        let version = request_line
-            .next()
-            .map(|v| v.replace("HTTP/", ""))
-            .ok_or_else(|| TcpIpError::new("Failed to read HTTP Request Version"))?
-            .parse::<f32>()?;
+        let uri = parts.next().ok_or_else(|| {
+            TcpIpError::parse_at("Failed to read HTTP Request URI", request_line.len())
+        })?;
+
+        let version = parts.next().ok_or_else(|| {
+            TcpIpError::parse_at("Failed to read HTTP Request Version", request_line.len())
+        })?;
+
+        let method = method
+            .parse::<RequestMethod>()
+            .map_err(|e| e.at_position(str_offset(&header_str, method)))?;
 
-        let headers = HeaderMap::from_header_lines(&mut header_str_lines);
+        let version = version
+            .parse::<HttpVersion>()
+            .map_err(|e| e.at_position(str_offset(&header_str, version)))?;
+
+        let uri = uri
+            .parse::<Uri>()
+            .map_err(|e| e.at_position(str_offset(&header_str, uri)))?;
+
+        let headers = HeaderMap::from_header_lines(&mut header_str_lines)?;
 
         Ok(RequestHeader {
             method,
-            uri: uri.to_owned(),
+            uri,
             version,
             headers,
         })
@@ -81,11 +122,7 @@ impl HeaderItem for RequestHeader {
     fn to_bytes(&self) -> Result<Vec<u8>> {
         let mut bytes = Vec::new();
 
-        write!(
-            bytes,
-            "{} {} HTTP/{}\r\n",
-            self.method, self.uri, self.version
-        )?;
+        write!(bytes, "{} {} {}\r\n", self.method, self.uri, self.version)?;
 
         self.write_headers(&mut bytes)?;
 
@@ -98,7 +135,9 @@ impl HeaderItem for RequestHeader {
 #[cfg(test)]
 mod tests {
     use crate::body_type::BodyType;
+    use crate::error::TcpIpError;
     use crate::header_item::HeaderItem;
+    use crate::http_version::HttpVersion;
     use crate::request::request_header::RequestHeader;
     use crate::request::request_method::RequestMethod;
 
@@ -111,7 +150,7 @@ mod tests {
 
         assert_eq!(header.method, RequestMethod::Get);
         assert_eq!(header.uri, "/v1/api/episode/watch/random");
-        assert_eq!(header.version, 2.0);
+        assert_eq!(header.version, HttpVersion::Http2);
 
         let headers = header.headers.as_ref().expect("Headers was None");
 
@@ -137,7 +176,10 @@ mod tests {
         let header =
             RequestHeader::from_bytes(raw_request.as_bytes()).expect("Failed to read request");
 
-        assert_eq!(header.body_type(), Some(BodyType::Fixed(20)))
+        assert_eq!(
+            header.body_type().expect("Failed to read body type"),
+            Some(BodyType::Fixed(20))
+        )
     }
 
     #[test]
@@ -146,7 +188,113 @@ mod tests {
         let header =
             RequestHeader::from_bytes(raw_request.as_bytes()).expect("Failed to read request");
 
-        assert_eq!(header.body_type(), Some(BodyType::Chunked))
+        assert_eq!(
+            header.body_type().expect("Failed to read body type"),
+            Some(BodyType::Chunked)
+        );
+
+        // The whitespace after the colon is optional
+        let header = RequestHeader::from_bytes(
+            b"POST / HTTP/1.1\r\nContent-Length:5\r\nTransfer-Encoding:chunked\r\n\r\n",
+        )
+        .expect("Failed to read request");
+
+        assert_eq!(
+            header.body_type().expect("Failed to read body type"),
+            Some(BodyType::Chunked)
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_invalid_uri() {
+        let raw_request = String::from("CONNECT a.test HTTP/1.1\r\n\r\n");
+        let e = RequestHeader::from_bytes(raw_request.as_bytes())
+            .expect_err("Read a request with an invalid target");
+
+        assert_eq!(
+            e.to_string(),
+            "Invalid request target 'a.test' - expected a path, a URL or a host and port at byte 8"
+        );
+
+        let raw_request = String::from("CONNECT a.test:443 HTTP/1.1\r\n\r\n");
+        let header =
+            RequestHeader::from_bytes(raw_request.as_bytes()).expect("Failed to read request");
+
+        assert_eq!(header.uri.port(), Some(443));
+    }
+
+    #[test]
+    fn test_body_type_none() {
+        let raw_request = String::from("GET / HTTP/1.1\r\nHost: localhost:5678\r\n\r\n");
+        let header =
+            RequestHeader::from_bytes(raw_request.as_bytes()).expect("Failed to read request");
+
+        assert_eq!(header.body_type().expect("Failed to read body type"), None)
+    }
+
+    #[test]
+    fn test_body_type_invalid() {
+        let raw_request =
+            String::from("POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n");
+        let header =
+            RequestHeader::from_bytes(raw_request.as_bytes()).expect("Failed to read request");
+
+        assert!(header.body_type().is_err());
+
+        let raw_request = String::from("POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n");
+        let header =
+            RequestHeader::from_bytes(raw_request.as_bytes()).expect("Failed to read request");
+
+        assert!(header.body_type().is_err());
+
+        let raw_request =
+            String::from("POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\n");
+        let header =
+            RequestHeader::from_bytes(raw_request.as_bytes()).expect("Failed to read request");
+
+        assert!(header.body_type().is_err());
+    }
+
+    #[test]
+    fn test_body_type_http10_transfer_encoding() {
+        let raw_request = String::from(
+            "POST /x HTTP/1.0\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n",
+        );
+        let header =
+            RequestHeader::from_bytes(raw_request.as_bytes()).expect("Failed to read request");
+
+        assert!(matches!(header.body_type(), Err(TcpIpError::Parse { .. })));
+    }
+
+    #[test]
+    fn test_body_type_repeated_content_length() {
+        let raw_request =
+            String::from("POST / HTTP/1.1\r\nContent-Length: 5, 5\r\ncontent-length: 5\r\n\r\n");
+        let header =
+            RequestHeader::from_bytes(raw_request.as_bytes()).expect("Failed to read request");
+
+        assert_eq!(
+            header.body_type().expect("Failed to read body type"),
+            Some(BodyType::Fixed(5))
+        )
+    }
+
+    #[test]
+    fn test_is_keep_alive() {
+        let keep_alive = |raw_request: &str| {
+            RequestHeader::from_bytes(raw_request.as_bytes())
+                .expect("Failed to read request")
+                .is_keep_alive()
+        };
+
+        assert!(keep_alive("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+        assert!(!keep_alive(
+            "GET / HTTP/1.1\r\nConnection: Upgrade, close\r\n\r\n"
+        ));
+        assert!(!keep_alive("GET / HTTP/1.0\r\nHost: localhost\r\n\r\n"));
+        assert!(keep_alive(
+            "GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n"
+        ));
     }
 
     #[test]
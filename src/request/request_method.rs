@@ -17,6 +17,16 @@ pub enum RequestMethod {
     Patch,
 }
 
+impl RequestMethod {
+    /// Returns true if sending the request more than once has the same effect as sending it once.
+    pub fn is_idempotent(&self) -> bool {
+        !matches!(
+            self,
+            RequestMethod::Post | RequestMethod::Connect | RequestMethod::Patch
+        )
+    }
+}
+
 impl FromStr for RequestMethod {
     type Err = TcpIpError;
 
@@ -31,7 +41,7 @@ impl FromStr for RequestMethod {
             "OPTIONS" => Ok(RequestMethod::Options),
             "CONNECT" => Ok(RequestMethod::Connect),
             "PATCH" => Ok(RequestMethod::Patch),
-            _ => Err(TcpIpError::new("Unknown request header method")),
+            _ => Err(TcpIpError::parse("Unknown request header method")),
         }
     }
 }
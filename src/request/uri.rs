@@ -0,0 +1,332 @@
+use std::fmt::Formatter;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::error::TcpIpError;
+use crate::util::percent_decode;
+use crate::Result;
+
+/// The four forms of request target, see RFC 9112 section 3.2.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum TargetForm {
+    /// A path and optional query such as `/index.html?q=1`, used for requests
+    /// sent to an origin server.
+    Origin,
+    /// A URL such as `http://example.com/index.html`, used for requests sent
+    /// to a proxy.
+    Absolute,
+    /// A host and port such as `example.com:443`, only used by `CONNECT`.
+    Authority,
+    /// `*`, only used by a server wide `OPTIONS`.
+    Asterisk,
+}
+
+/// The target of a request.
+///
+/// The components are slices of the target as it was received, so it is sent
+/// on exactly as it arrived. `util::percent_encode` encodes values to build one.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Uri {
+    raw: String,
+    form: TargetForm,
+    scheme: Option<Range<usize>>,
+    authority: Option<Range<usize>>,
+    path: Range<usize>,
+    query: Option<Range<usize>>,
+}
+
+impl Uri {
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    pub fn form(&self) -> TargetForm {
+        self.form
+    }
+
+    fn part(&self, range: &Option<Range<usize>>) -> Option<&str> {
+        range.as_ref().map(|range| &self.raw[range.clone()])
+    }
+
+    /// Returns the scheme of an absolute-form target, such as `http`.
+    pub fn scheme(&self) -> Option<&str> {
+        self.part(&self.scheme)
+    }
+
+    /// Returns the authority of an absolute-form or authority-form target,
+    /// which is the host and port and may have user information.
+    pub fn authority(&self) -> Option<&str> {
+        self.part(&self.authority)
+    }
+
+    /// Returns the host of the authority, with any brackets around an IPv6
+    /// address removed.
+    pub fn host(&self) -> Option<&str> {
+        let authority = self.authority()?;
+        let host = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+
+        match host.strip_prefix('[') {
+            Some(ipv6) => ipv6.split(']').next(),
+            None => host.split(':').next(),
+        }
+    }
+
+    /// Returns the port of the authority if it has one.
+    pub fn port(&self) -> Option<u16> {
+        let authority = self.authority()?;
+        let (_, port) = authority.rsplit_once(':')?;
+
+        if port.contains(']') {
+            None
+        } else {
+            port.parse().ok()
+        }
+    }
+
+    /// Returns the path as it was sent, which is empty for authority-form and
+    /// asterisk-form targets and may be empty for absolute-form targets.
+    pub fn path(&self) -> &str {
+        &self.raw[self.path.clone()]
+    }
+
+    /// Returns the query as it was sent, without the `?`.
+    pub fn query(&self) -> Option<&str> {
+        self.part(&self.query)
+    }
+
+    /// Returns the percent-decoded path.
+    pub fn decoded_path(&self) -> String {
+        percent_decode(self.path())
+    }
+
+    /// Returns the percent-decoded names and values of the query, where `+`
+    /// is a space as in HTML forms. Names without a value have an empty one.
+    pub fn query_pairs(&self) -> impl Iterator<Item = (String, String)> + '_ {
+        let decode = |s: &str| percent_decode(&s.replace('+', " "));
+
+        self.query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(move |pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+                (decode(name), decode(value))
+            })
+    }
+
+    /// Returns the path and query, with the path `/` if it is empty, which is
+    /// how an absolute-form target is sent to an origin server. Authority-form
+    /// and asterisk-form targets are returned as they are.
+    pub fn origin_form(&self) -> String {
+        match self.form {
+            TargetForm::Absolute => {
+                let path = if self.path().is_empty() {
+                    "/"
+                } else {
+                    self.path()
+                };
+
+                match self.query() {
+                    Some(query) => format!("{}?{}", path, query),
+                    None => path.to_owned(),
+                }
+            }
+            _ => self.raw.clone(),
+        }
+    }
+}
+
+impl FromStr for Uri {
+    type Err = TcpIpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |message: &str| {
+            TcpIpError::parse(format!("Invalid request target '{}' - {}", s, message))
+        };
+
+        if s.is_empty() {
+            return Err(invalid("it is empty"));
+        }
+
+        if let Some(b) = s
+            .bytes()
+            .find(|b| b.is_ascii_control() || *b == b' ' || *b == b'#')
+        {
+            return Err(invalid(&format!("'{}' is not allowed", b.escape_ascii())));
+        }
+
+        let mut uri = Uri {
+            raw: s.to_owned(),
+            form: TargetForm::Origin,
+            scheme: None,
+            authority: None,
+            path: 0..0,
+            query: None,
+        };
+
+        // The path and query start at `start` and run to the end
+        let split_query = |uri: &mut Uri, start: usize| match s[start..].find('?') {
+            Some(i) => {
+                uri.path = start..start + i;
+                uri.query = Some(start + i + 1..s.len());
+            }
+            None => uri.path = start..s.len(),
+        };
+
+        let is_scheme = |scheme: &str| {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        };
+
+        if s == "*" {
+            uri.form = TargetForm::Asterisk;
+        } else if s.starts_with('/') {
+            split_query(&mut uri, 0);
+        } else if let Some((scheme, rest)) =
+            s.split_once("://").filter(|(scheme, _)| is_scheme(scheme))
+        {
+            let authority_start = scheme.len() + 3;
+            let authority_end = rest
+                .find(['/', '?'])
+                .map_or(s.len(), |i| authority_start + i);
+
+            if authority_start == authority_end {
+                return Err(invalid("the host is missing"));
+            }
+
+            uri.form = TargetForm::Absolute;
+            uri.scheme = Some(0..scheme.len());
+            uri.authority = Some(authority_start..authority_end);
+            split_query(&mut uri, authority_end);
+        } else {
+            uri.form = TargetForm::Authority;
+            uri.authority = Some(0..s.len());
+            uri.path = s.len()..s.len();
+
+            if s.contains(['/', '?', '@']) || uri.port().is_none() {
+                return Err(invalid("expected a path, a URL or a host and port"));
+            }
+        }
+
+        Ok(uri)
+    }
+}
+
+impl std::fmt::Display for Uri {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.raw)
+    }
+}
+
+impl PartialEq<str> for Uri {
+    fn eq(&self, other: &str) -> bool {
+        self.raw == other
+    }
+}
+
+impl PartialEq<&str> for Uri {
+    fn eq(&self, other: &&str) -> bool {
+        self.raw == *other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::request::uri::{TargetForm, Uri};
+
+    fn parse(s: &str) -> Uri {
+        let uri = s.parse::<Uri>().expect("Failed to parse URI");
+
+        assert_eq!(uri.to_string(), s);
+        uri
+    }
+
+    #[test]
+    fn test_origin_form() {
+        let uri = parse("/a%20b/c?x=1&y=a+b%26c&z");
+
+        assert_eq!(uri.form(), TargetForm::Origin);
+        assert_eq!(uri.path(), "/a%20b/c");
+        assert_eq!(uri.decoded_path(), "/a b/c");
+        assert_eq!(uri.query(), Some("x=1&y=a+b%26c&z"));
+        assert_eq!(
+            uri.query_pairs().collect::<Vec<_>>(),
+            [
+                ("x".to_owned(), "1".to_owned()),
+                ("y".to_owned(), "a b&c".to_owned()),
+                ("z".to_owned(), String::new())
+            ]
+        );
+        assert_eq!(uri.origin_form(), "/a%20b/c?x=1&y=a+b%26c&z");
+        assert_eq!(uri.host(), None);
+
+        assert_eq!(parse("/?").query(), Some(""));
+    }
+
+    #[test]
+    fn test_absolute_form() {
+        let uri = parse("http://user@[::1]:8080/a?b");
+
+        assert_eq!(uri.form(), TargetForm::Absolute);
+        assert_eq!(uri.scheme(), Some("http"));
+        assert_eq!(uri.authority(), Some("user@[::1]:8080"));
+        assert_eq!(uri.host(), Some("::1"));
+        assert_eq!(uri.port(), Some(8080));
+        assert_eq!(uri.path(), "/a");
+        assert_eq!(uri.origin_form(), "/a?b");
+
+        let uri = parse("HTTP://a.test?q");
+
+        assert_eq!(uri.host(), Some("a.test"));
+        assert_eq!(uri.port(), None);
+        assert_eq!(uri.path(), "");
+        assert_eq!(uri.origin_form(), "/?q");
+    }
+
+    #[test]
+    fn test_authority_and_asterisk_form() {
+        let uri = parse("a.test:443");
+
+        assert_eq!(uri.form(), TargetForm::Authority);
+        assert_eq!(uri.host(), Some("a.test"));
+        assert_eq!(uri.port(), Some(443));
+        assert_eq!(uri.path(), "");
+        assert_eq!(uri.origin_form(), "a.test:443");
+
+        assert_eq!(parse("[::1]:443").host(), Some("::1"));
+
+        let uri = parse("*");
+
+        assert_eq!(uri.form(), TargetForm::Asterisk);
+        assert_eq!(uri.path(), "");
+        assert_eq!(uri.authority(), None);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let error = |s: &str| {
+            s.parse::<Uri>()
+                .expect_err("Parsed an invalid URI")
+                .to_string()
+        };
+
+        assert_eq!(
+            error("a.test"),
+            "Invalid request target 'a.test' - expected a path, a URL or a host and port"
+        );
+        assert_eq!(
+            error("/a#b"),
+            "Invalid request target '/a#b' - '#' is not allowed"
+        );
+        assert_eq!(
+            error("http:///a"),
+            "Invalid request target 'http:///a' - the host is missing"
+        );
+        assert_eq!(error(""), "Invalid request target '' - it is empty");
+    }
+}
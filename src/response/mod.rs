@@ -1,9 +1,11 @@
 use std::convert::TryFrom;
 use std::fmt::Formatter;
 
+use crate::body::Body;
 use crate::error::ErrorExt;
 use crate::header_map::HeaderMap;
 use crate::http_item::HttpItem;
+use crate::http_version::HttpVersion;
 use crate::response::response_header::ResponseHeader;
 use crate::response::response_status::ResponseStatus;
 use crate::Result;
@@ -13,7 +15,7 @@ pub mod response_status;
 
 #[derive(Debug, Default)]
 pub struct ResponseBuilder {
-    version: f32,
+    version: HttpVersion,
     status_code: Option<u16>,
     reason_phrase: Option<String>,
     headers: Option<HeaderMap>,
@@ -23,12 +25,12 @@ pub struct ResponseBuilder {
 impl ResponseBuilder {
     pub fn new() -> Self {
         ResponseBuilder {
-            version: 1.1,
+            version: HttpVersion::Http11,
             ..Self::default()
         }
     }
 
-    pub fn version(mut self, version: f32) -> Self {
+    pub fn version(mut self, version: HttpVersion) -> Self {
         self.version = version;
         self
     }
@@ -61,7 +63,7 @@ impl ResponseBuilder {
         new
     }
 
-    pub fn build(self) -> Result<Response> {
+    pub fn build(self) -> Result<Response<'static>> {
         let version = self.version;
         let status_code = self.status_code.context("Missing status_code")?;
 
@@ -72,7 +74,7 @@ impl ResponseBuilder {
         };
 
         let headers = self.headers;
-        let body = self.body;
+        let body = self.body.map(Body::from).unwrap_or_default();
 
         let header = ResponseHeader::new(version, status_code, reason_phrase, headers);
 
@@ -80,13 +82,13 @@ impl ResponseBuilder {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct Response {
+#[derive(Debug)]
+pub struct Response<'a> {
     pub header: ResponseHeader,
-    pub body: Option<Vec<u8>>,
+    pub body: Body<'a>,
 }
 
-impl HttpItem for Response {
+impl<'a> HttpItem<'a> for Response<'a> {
     type HeaderType = ResponseHeader;
 
     fn item_name(&self) -> &str {
@@ -97,16 +99,24 @@ impl HttpItem for Response {
         &self.header
     }
 
-    fn body(&self) -> Option<Vec<u8>> {
-        self.body.clone()
+    fn header_mut(&mut self) -> &mut Self::HeaderType {
+        &mut self.header
     }
 
-    fn new(header: Self::HeaderType, body: Option<Vec<u8>>) -> Self {
+    fn body(&self) -> &Body<'a> {
+        &self.body
+    }
+
+    fn body_mut(&mut self) -> &mut Body<'a> {
+        &mut self.body
+    }
+
+    fn new(header: Self::HeaderType, body: Body<'a>) -> Self {
         Self { header, body }
     }
 }
 
-impl std::fmt::Display for Response {
+impl std::fmt::Display for Response<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         self.display(f)
     }
@@ -114,8 +124,10 @@ impl std::fmt::Display for Response {
 
 #[cfg(test)]
 mod tests {
+    use crate::body::Body;
     use crate::header_map::HeaderMap;
     use crate::http_item::HttpItem;
+    use crate::http_version::HttpVersion;
     use crate::response::response_header::ResponseHeader;
     use crate::response::Response;
 
@@ -123,14 +135,14 @@ mod tests {
     fn test_to_bytes() {
         let request = Response {
             header: ResponseHeader {
-                version: 1.1,
+                version: HttpVersion::Http11,
                 status_code: 200,
                 reason_phrase: "OK".to_string(),
                 headers: Some(HeaderMap {
                     headers: vec![("Content-Length".to_owned(), "24".to_owned())],
                 }),
             },
-            body: Some(vec![
+            body: Body::from(vec![
                 123, 10, 9, 34, 100, 97, 116, 97, 34, 58, 32, 34, 104, 101, 108, 108, 111, 32, 122,
                 97, 107, 34, 10, 125,
             ]),
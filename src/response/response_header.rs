@@ -1,13 +1,17 @@
 use std::io::{Read, Write};
 
+use crate::body_type::BodyType;
 use crate::error::TcpIpError;
 use crate::header_item::HeaderItem;
 use crate::header_map::HeaderMap;
+use crate::http_version::HttpVersion;
+use crate::request::request_method::RequestMethod;
+use crate::util::str_offset;
 use crate::Result;
 
 #[derive(Debug, Clone)]
 pub struct ResponseHeader {
-    pub version: f32,
+    pub version: HttpVersion,
     pub status_code: u16,
     pub reason_phrase: String,
     pub headers: Option<HeaderMap>,
@@ -15,7 +19,7 @@ pub struct ResponseHeader {
 
 impl ResponseHeader {
     pub fn new<T: AsRef<str>>(
-        version: f32,
+        version: HttpVersion,
         status_code: u16,
         reason_phrase: T,
         headers: Option<HeaderMap>,
@@ -27,9 +31,43 @@ impl ResponseHeader {
             headers,
         }
     }
+
+    /// Returns true for 1xx responses which are followed by the final response.
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.status_code)
+    }
+
+    /// Returns the body type of a response to a request made with `request_method`.
+    pub fn body_type_for(&self, request_method: &RequestMethod) -> Result<Option<BodyType>> {
+        let successful = (200..300).contains(&self.status_code);
+
+        // Responses to HEAD never have a body and a successful CONNECT switches to a tunnel
+        if *request_method == RequestMethod::Head
+            || (*request_method == RequestMethod::Connect && successful)
+        {
+            Ok(None)
+        } else {
+            self.body_type()
+        }
+    }
 }
 
 impl HeaderItem for ResponseHeader {
+    fn body_type(&self) -> Result<Option<BodyType>> {
+        // 1xx, 204 No Content and 304 Not Modified responses never have a body
+        if self.is_informational() || self.status_code == 204 || self.status_code == 304 {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            self.framed_body_type()?.unwrap_or(BodyType::UntilClose),
+        ))
+    }
+
+    fn version(&self) -> HttpVersion {
+        self.version
+    }
+
     fn headers(&self) -> &Option<HeaderMap> {
         &self.headers
     }
@@ -47,28 +85,35 @@ impl HeaderItem for ResponseHeader {
 
         let mut header_str_lines = header_str.lines();
 
-        let mut status_line = header_str_lines
+        // The reason phrase may contain spaces or be empty, so only split off the first two fields
+        let status_line = header_str_lines
             .next()
-            .ok_or_else(|| TcpIpError::new("Failed to read HTTP Response Status line"))?
-            .split_whitespace();
+            .ok_or_else(|| TcpIpError::parse("Failed to read HTTP Response Status line"))?;
 
-        let version = status_line
-            .next()
-            .map(|v| v.replace("HTTP/", ""))
-            .ok_or_else(|| TcpIpError::new("Failed to read HTTP Response Version"))?
-            .parse::<f32>()?;
+        let mut parts = status_line.splitn(3, ' ');
 
-        let status_code = status_line
+        let version = parts
             .next()
-            .ok_or_else(|| TcpIpError::new("Failed to read HTTP Response Status code"))?
-            .parse::<u16>()?;
+            .ok_or_else(|| TcpIpError::parse_at("Failed to read HTTP Response Version", 0))?;
 
-        let reason_phrase = status_line
-            .next()
-            .ok_or_else(|| TcpIpError::new("Failed to read HTTP Response Reason phrase"))?
-            .to_owned();
+        let version = version
+            .parse::<HttpVersion>()
+            .map_err(|e| e.at_position(str_offset(&header_str, version)))?;
+
+        let status_code = parts.next().filter(|c| c.len() == 3).ok_or_else(|| {
+            TcpIpError::parse_at(
+                "Failed to read HTTP Response Status code",
+                status_line.len(),
+            )
+        })?;
+
+        let status_code = status_code
+            .parse::<u16>()
+            .map_err(|e| TcpIpError::from(e).at_position(str_offset(&header_str, status_code)))?;
 
-        let headers = HeaderMap::from_header_lines(&mut header_str_lines);
+        let reason_phrase = parts.next().unwrap_or_default().to_owned();
+
+        let headers = HeaderMap::from_header_lines(&mut header_str_lines)?;
 
         Ok(ResponseHeader {
             version,
@@ -83,7 +128,7 @@ impl HeaderItem for ResponseHeader {
 
         write!(
             bytes,
-            "HTTP/{} {} {}\r\n",
+            "{} {} {}\r\n",
             self.version, self.status_code, self.reason_phrase
         )?;
 
@@ -97,9 +142,15 @@ impl HeaderItem for ResponseHeader {
 
 #[cfg(test)]
 mod tests {
+    use std::convert::TryFrom;
+
     use crate::body_type::BodyType;
+    use crate::error::TcpIpError;
     use crate::header_item::HeaderItem;
+    use crate::http_version::HttpVersion;
+    use crate::request::request_method::RequestMethod;
     use crate::response::response_header::ResponseHeader;
+    use crate::response::response_status::ResponseStatus;
 
     #[test]
     fn test_from_bytes_to_bytes() {
@@ -108,7 +159,7 @@ mod tests {
         let header =
             ResponseHeader::from_bytes(raw_request.as_bytes()).expect("Failed to read request");
 
-        assert_eq!(header.version, 1.1);
+        assert_eq!(header.version, HttpVersion::Http11);
         assert_eq!(header.status_code, 200);
         assert_eq!(header.reason_phrase, "OK");
 
@@ -124,6 +175,89 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_repeated_headers_round_trip() {
+        let raw_response = String::from(
+            "HTTP/1.1 200 OK\r\nSet-Cookie: a=1\r\nContent-Length: 0\r\nSet-Cookie: b=2\r\n\r\n",
+        );
+
+        let mut header =
+            ResponseHeader::from_bytes(raw_response.as_bytes()).expect("Failed to read response");
+
+        header.strip_hop_by_hop();
+
+        assert_eq!(
+            header
+                .to_bytes()
+                .expect("Failed to convert header to bytes"),
+            raw_response.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_reason_phrase_round_trip() {
+        let statuses = (100..600).filter_map(|code| ResponseStatus::try_from(code).ok());
+
+        for status in statuses {
+            let raw_response = format!(
+                "HTTP/1.1 {} {}\r\nContent-Length: 0\r\n\r\n",
+                status as u16, status
+            );
+
+            let header = ResponseHeader::from_bytes(raw_response.as_bytes())
+                .expect("Failed to read response");
+
+            assert_eq!(header.status_code, status as u16);
+            assert_eq!(header.reason_phrase, status.to_string());
+            assert_eq!(
+                header
+                    .to_bytes()
+                    .expect("Failed to convert header to bytes"),
+                raw_response.as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty_reason_phrase() {
+        let raw_response = String::from("HTTP/1.1 204 \r\nServer: test\r\n\r\n");
+
+        let header =
+            ResponseHeader::from_bytes(raw_response.as_bytes()).expect("Failed to read response");
+
+        assert_eq!(header.status_code, 204);
+        assert_eq!(header.reason_phrase, "");
+        assert_eq!(
+            header
+                .to_bytes()
+                .expect("Failed to convert header to bytes"),
+            raw_response.as_bytes()
+        );
+
+        let header = ResponseHeader::from_bytes(b"HTTP/1.1 204\r\n\r\n".as_ref())
+            .expect("Failed to read response");
+
+        assert_eq!(header.status_code, 204);
+        assert_eq!(header.reason_phrase, "");
+    }
+
+    #[test]
+    fn test_invalid_status_code() {
+        assert!(ResponseHeader::from_bytes(b"HTTP/1.1 2000 OK\r\n\r\n".as_ref()).is_err());
+        assert!(ResponseHeader::from_bytes(b"HTTP/1.1 OK\r\n\r\n".as_ref()).is_err());
+
+        let e = ResponseHeader::from_bytes(b"HTTP/1.1 2x0 OK\r\n\r\n".as_ref())
+            .expect_err("Parsed invalid status code");
+
+        assert!(matches!(
+            e,
+            TcpIpError::Parse {
+                position: Some(9),
+                ..
+            }
+        ));
+    }
+
     #[test]
     fn test_body_type_fixed() {
         let raw_request = String::from("HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\n");
@@ -131,7 +265,10 @@ mod tests {
         let header =
             ResponseHeader::from_bytes(raw_request.as_bytes()).expect("Failed to read request");
 
-        assert_eq!(header.body_type(), Some(BodyType::Fixed(13)))
+        assert_eq!(
+            header.body_type().expect("Failed to read body type"),
+            Some(BodyType::Fixed(13))
+        )
     }
 
     #[test]
@@ -141,6 +278,65 @@ mod tests {
         let header =
             ResponseHeader::from_bytes(raw_request.as_bytes()).expect("Failed to read request");
 
-        assert_eq!(header.body_type(), Some(BodyType::Chunked))
+        assert_eq!(
+            header.body_type().expect("Failed to read body type"),
+            Some(BodyType::Chunked)
+        )
+    }
+
+    #[test]
+    fn test_body_type_until_close() {
+        let body_type = |raw_response: &str| {
+            ResponseHeader::from_bytes(raw_response.as_bytes())
+                .expect("Failed to read response")
+                .body_type()
+                .expect("Failed to read body type")
+        };
+
+        assert_eq!(
+            body_type("HTTP/1.0 200 OK\r\nServer: legacy\r\n\r\n"),
+            Some(BodyType::UntilClose)
+        );
+        assert_eq!(
+            body_type("HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\n\r\n"),
+            Some(BodyType::UntilClose)
+        );
+        assert_eq!(
+            body_type(
+                "HTTP/1.1 200 OK\r\nContent-Length: 13\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"
+            ),
+            Some(BodyType::Chunked)
+        );
+    }
+
+    #[test]
+    fn test_body_type_none() {
+        for raw_response in &[
+            "HTTP/1.1 100 Continue\r\n\r\n",
+            "HTTP/1.1 204 No Content\r\n\r\n",
+            "HTTP/1.1 304 Not Modified\r\nContent-Length: 13\r\n\r\n",
+        ] {
+            let header = ResponseHeader::from_bytes(raw_response.as_bytes())
+                .expect("Failed to read response");
+
+            assert_eq!(header.body_type().expect("Failed to read body type"), None);
+        }
+
+        let header =
+            ResponseHeader::from_bytes(b"HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\n".as_ref())
+                .expect("Failed to read response");
+
+        assert_eq!(
+            header
+                .body_type_for(&RequestMethod::Head)
+                .expect("Failed to read body type"),
+            None
+        );
+        assert_eq!(
+            header
+                .body_type_for(&RequestMethod::Get)
+                .expect("Failed to read body type"),
+            Some(BodyType::Fixed(13))
+        );
     }
 }
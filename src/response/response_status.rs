@@ -4,7 +4,7 @@ use std::fmt::Formatter;
 use crate::error::TcpIpError;
 use crate::Result;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum ResponseStatus {
     Continue = 100,
     SwitchingProtocols = 101,
@@ -145,7 +145,7 @@ impl TryFrom<u16> for ResponseStatus {
             510 => Ok(ResponseStatus::NotExtended),
             511 => Ok(ResponseStatus::NetworkAuthenticationRequired),
             599 => Ok(ResponseStatus::NetworkConnectTimeoutError),
-            _ => Err(TcpIpError::new("Unknown response status")),
+            _ => Err(TcpIpError::parse("Unknown response status")),
         }
     }
 }
@@ -0,0 +1,556 @@
+//! Answers requests with handlers registered against a method and a path
+//! pattern, so a server can be an application rather than a proxy.
+//!
+//! Patterns are paths whose segments may be a parameter such as `:id`, which
+//! matches any one segment, or end with a wildcard such as `*path`, which
+//! matches the rest of the path. Routes are tried in the order they were added.
+//! A server answers requests with a router when started with `Server::serve`,
+//! or `Server::serve_async` with the `tokio` feature.
+//!
+//! ```no_run
+//! use http_lib::address::Address;
+//! use http_lib::config::Server;
+//! use http_lib::response::ResponseBuilder;
+//! use http_lib::router::Router;
+//!
+//! let router = Router::new().get("/users/:id", |request| {
+//!     let id = request.param("id").unwrap_or_default();
+//!
+//!     ResponseBuilder::new()
+//!         .status_code(200)
+//!         .body(format!("User {}", id).into_bytes())
+//!         .build()
+//!         .expect("Failed to build response")
+//! });
+//!
+//! let address = Address::new("127.0.0.1", 8080);
+//! let server = Server::new("app", address.clone(), address);
+//!
+//! server.serve(router).expect("Failed to start").join().ok();
+//! ```
+
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::body::Body;
+use crate::error::TcpIpError;
+use crate::error_page::ErrorPage;
+use crate::header_item::HeaderItem;
+use crate::request::request_method::RequestMethod;
+use crate::request::uri::Uri;
+use crate::request::Request;
+use crate::response::Response;
+use crate::stream_helper::{set_connection_headers, KeepAlive};
+use crate::util::percent_decode;
+use crate::Result;
+
+/// Answers a request that matched a route.
+pub type Handler = Box<dyn Fn(&Request) -> Response<'static> + Send + Sync>;
+
+/// The values of the parameters and wildcard in the pattern of the route a
+/// request matched, in the order they appear in the pattern.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Params {
+    pub values: Vec<(String, String)>,
+}
+
+impl Params {
+    /// Returns the percent-decoded value of the parameter `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    /// Matches the rest of the path, including nothing.
+    Wildcard(String),
+}
+
+struct Route {
+    method: RequestMethod,
+    segments: Vec<Segment>,
+    handler: Handler,
+}
+
+impl Route {
+    // Returns the parameters if `path` matches the pattern
+    fn matches(&self, path: &[String]) -> Option<Params> {
+        let mut params = Params::default();
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            match (segment, path.get(i)) {
+                (Segment::Wildcard(name), _) => {
+                    let rest = path.get(i..).unwrap_or_default().join("/");
+
+                    params.values.push((name.clone(), rest));
+                    return Some(params);
+                }
+                (Segment::Literal(literal), Some(value)) if literal == value => {}
+                (Segment::Param(name), Some(value)) => {
+                    params.values.push((name.clone(), value.clone()))
+                }
+                _ => return None,
+            }
+        }
+
+        if path.len() == self.segments.len() {
+            Some(params)
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Result<Vec<Segment>> {
+    let invalid = |message: &str| {
+        TcpIpError::InvalidInput(format!("Invalid route '{}' - {}", pattern, message))
+    };
+
+    let path = pattern
+        .strip_prefix('/')
+        .ok_or_else(|| invalid("it must start with '/'"))?;
+
+    let parts: Vec<&str> = path.split('/').collect();
+    let mut segments = Vec::with_capacity(parts.len());
+
+    for (i, part) in parts.iter().enumerate() {
+        let segment = if let Some(name) = part.strip_prefix(':') {
+            if name.is_empty() {
+                return Err(invalid("a parameter is missing its name"));
+            }
+
+            Segment::Param(name.to_owned())
+        } else if let Some(name) = part.strip_prefix('*') {
+            if i + 1 < parts.len() {
+                return Err(invalid("a wildcard must be the last segment"));
+            }
+
+            Segment::Wildcard(name.to_owned())
+        } else {
+            Segment::Literal(percent_decode(part))
+        };
+
+        segments.push(segment);
+    }
+
+    Ok(segments)
+}
+
+// Splits the path of a request target into percent-decoded segments
+fn path_segments(uri: &Uri) -> Vec<String> {
+    let path = uri.path();
+
+    path.strip_prefix('/')
+        .unwrap_or(path)
+        .split('/')
+        .map(percent_decode)
+        .collect()
+}
+
+/// Routes requests to handlers.
+///
+/// A request whose path matches no route is answered with 404 Not Found, and
+/// one whose path only matches routes for other methods with 405 Method Not
+/// Allowed and an `Allow` header listing them. `HEAD` requests are answered by
+/// the `GET` route for the path when there is no `HEAD` route.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Option<Handler>,
+    /// Why the first invalid pattern is invalid, reported when the server is started.
+    invalid: Option<String>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers `method` requests whose path matches `pattern` with `handler`.
+    pub fn route<F>(mut self, method: RequestMethod, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request) -> Response<'static> + Send + Sync + 'static,
+    {
+        match parse_pattern(pattern) {
+            Ok(segments) => self.routes.push(Route {
+                method,
+                segments,
+                handler: Box::new(handler),
+            }),
+            Err(e) => {
+                self.invalid.get_or_insert(e.to_string());
+            }
+        }
+
+        self
+    }
+
+    pub fn get<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request) -> Response<'static> + Send + Sync + 'static,
+    {
+        self.route(RequestMethod::Get, pattern, handler)
+    }
+
+    pub fn post<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request) -> Response<'static> + Send + Sync + 'static,
+    {
+        self.route(RequestMethod::Post, pattern, handler)
+    }
+
+    pub fn put<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request) -> Response<'static> + Send + Sync + 'static,
+    {
+        self.route(RequestMethod::Put, pattern, handler)
+    }
+
+    pub fn patch<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request) -> Response<'static> + Send + Sync + 'static,
+    {
+        self.route(RequestMethod::Patch, pattern, handler)
+    }
+
+    pub fn delete<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request) -> Response<'static> + Send + Sync + 'static,
+    {
+        self.route(RequestMethod::Delete, pattern, handler)
+    }
+
+    /// Answers requests that match no route with `handler` instead of the
+    /// server's error page.
+    pub fn not_found<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&Request) -> Response<'static> + Send + Sync + 'static,
+    {
+        self.not_found = Some(Box::new(handler));
+        self
+    }
+
+    /// Returns an error if a route was added with an invalid pattern.
+    pub fn check(&self) -> Result<()> {
+        match &self.invalid {
+            Some(message) => Err(TcpIpError::InvalidInput(message.clone())),
+            None => Ok(()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Calls the handler for `request`, setting its params, or builds the 404
+    /// or 405 response if there is none.
+    pub fn handle(
+        &self,
+        request: &mut Request,
+        error_page: &ErrorPage,
+    ) -> Result<Response<'static>> {
+        let path = path_segments(&request.header.uri);
+        let method = request.header.method.clone();
+
+        let mut allowed = Vec::new();
+        let mut fallback = None;
+
+        for route in &self.routes {
+            let params = match route.matches(&path) {
+                Some(params) => params,
+                None => continue,
+            };
+
+            if route.method == method {
+                return self.call(route, params, request);
+            }
+
+            if method == RequestMethod::Head && route.method == RequestMethod::Get {
+                fallback = fallback.or(Some((route, params)));
+            }
+
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method.clone());
+            }
+        }
+
+        if let Some((route, params)) = fallback {
+            return self.call(route, params, request);
+        }
+
+        let e = if allowed.is_empty() {
+            if let Some(not_found) = &self.not_found {
+                request.params = Params::default();
+
+                return call_handler(not_found, request);
+            }
+
+            TcpIpError::NoRoute {
+                method: method.to_string(),
+                uri: request.header.uri.to_string(),
+            }
+        } else {
+            if allowed.contains(&RequestMethod::Get) && !allowed.contains(&RequestMethod::Head) {
+                allowed.push(RequestMethod::Head);
+            }
+
+            TcpIpError::MethodNotAllowed {
+                method: method.to_string(),
+                uri: request.header.uri.to_string(),
+                allowed: allowed.iter().map(RequestMethod::to_string).collect(),
+            }
+        };
+
+        let mut response = error_page.render(e.to_status(), &e.to_string())?;
+
+        if let TcpIpError::MethodNotAllowed { allowed, .. } = &e {
+            response.header.insert_header("Allow", &allowed.join(", "));
+        }
+
+        Ok(response)
+    }
+
+    fn call(
+        &self,
+        route: &Route,
+        params: Params,
+        request: &mut Request,
+    ) -> Result<Response<'static>> {
+        request.params = params;
+
+        call_handler(&route.handler, request)
+    }
+
+    /// Answers `request` like `handle`, framing the body of the response and
+    /// setting the connection headers to keep the connection open with the
+    /// `keep_alive` limits if they are given.
+    pub(crate) fn respond(
+        &self,
+        request: &mut Request,
+        keep_alive: Option<KeepAlive>,
+        error_page: &ErrorPage,
+    ) -> Result<Response<'static>> {
+        let mut response = self.handle(request, error_page)?;
+
+        response.header.strip_hop_by_hop();
+
+        let has_body = response.header.body_type_for(&request.header.method);
+
+        if !response.body.is_empty() || matches!(has_body, Ok(Some(_))) {
+            let content_length = response.body.content_length();
+
+            response
+                .header
+                .frame_body(content_length, request.header.version);
+        }
+
+        // A response to HEAD describes the body a GET would get without sending it
+        if request.header.method == RequestMethod::Head {
+            response.body = Body::Empty;
+        }
+
+        set_connection_headers(&mut response.header, keep_alive);
+
+        Ok(response)
+    }
+}
+
+// A handler that panics fails the request rather than the worker serving it
+fn call_handler(handler: &Handler, request: &Request) -> Result<Response<'static>> {
+    panic::catch_unwind(AssertUnwindSafe(|| handler(request))).map_err(|_| {
+        TcpIpError::HandlerPanicked {
+            method: request.header.method.to_string(),
+            uri: request.header.uri.to_string(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error_page::ErrorPage;
+    use crate::request::request_method::RequestMethod;
+    use crate::request::{Request, RequestBuilder};
+    use crate::response::{Response, ResponseBuilder};
+    use crate::router::{path_segments, Router};
+
+    fn text(body: String) -> Response<'static> {
+        ResponseBuilder::new()
+            .status_code(200)
+            .body(body.into_bytes())
+            .build()
+            .expect("Failed to build response")
+    }
+
+    fn new_request(method: RequestMethod, uri: &str) -> Request<'static> {
+        RequestBuilder::new()
+            .method(method)
+            .uri(uri)
+            .build()
+            .expect("Failed to build request")
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .get("/users/:id", |r| {
+                text(format!("user {}", r.param("id").unwrap_or_default()))
+            })
+            .delete("/users/:id", |_| text("deleted".to_owned()))
+            .get("/users/:id/posts/:post", |r| {
+                text(format!(
+                    "{} {}",
+                    r.param("id").unwrap_or_default(),
+                    r.param("post").unwrap_or_default()
+                ))
+            })
+            .get("/files/*path", |r| {
+                text(format!("file {}", r.param("path").unwrap_or_default()))
+            })
+            .post("/", |_| text("root".to_owned()))
+    }
+
+    fn header<'a>(response: &'a Response, key: &str) -> Option<&'a str> {
+        response.header.headers.as_ref().and_then(|h| h.get(key))
+    }
+
+    fn body(router: &Router, method: RequestMethod, uri: &str) -> (u16, String) {
+        let mut request = new_request(method, uri);
+        let response = router
+            .handle(&mut request, &ErrorPage::default())
+            .expect("Failed to handle request");
+
+        let body = response.body.as_bytes().unwrap_or_default().to_vec();
+
+        (
+            response.header.status_code,
+            String::from_utf8(body).expect("Invalid body"),
+        )
+    }
+
+    #[test]
+    fn test_path_segments() {
+        let segments = |uri: &str| path_segments(&uri.parse().expect("Invalid URI"));
+
+        assert_eq!(segments("/a/b%20c?d=/e"), ["a", "b c"]);
+        assert_eq!(segments("http://a.test/x/"), ["x", ""]);
+        assert_eq!(segments("http://a.test?x"), [""]);
+        assert_eq!(segments("/"), [""]);
+    }
+
+    #[test]
+    fn test_handle() {
+        let router = router();
+
+        assert_eq!(
+            body(&router, RequestMethod::Get, "/users/a%20b?x=1"),
+            (200, "user a b".to_owned())
+        );
+        assert_eq!(
+            body(&router, RequestMethod::Get, "/users/1/posts/2"),
+            (200, "1 2".to_owned())
+        );
+        assert_eq!(
+            body(&router, RequestMethod::Get, "/files/a/b.txt"),
+            (200, "file a/b.txt".to_owned())
+        );
+        assert_eq!(
+            body(&router, RequestMethod::Get, "/files"),
+            (200, "file ".to_owned())
+        );
+        assert_eq!(
+            body(&router, RequestMethod::Post, "/"),
+            (200, "root".to_owned())
+        );
+        assert_eq!(
+            body(&router, RequestMethod::Get, "/users"),
+            (
+                404,
+                "404 Not Found\n\nNo route matches 'GET /users'\n".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn test_method_not_allowed() {
+        let router = router();
+        let mut request = new_request(RequestMethod::Put, "/users/1");
+
+        let response = router
+            .handle(&mut request, &ErrorPage::default())
+            .expect("Failed to handle request");
+
+        assert_eq!(response.header.status_code, 405);
+        assert_eq!(header(&response, "Allow"), Some("GET, DELETE, HEAD"));
+
+        let mut request = new_request(RequestMethod::Post, "/");
+
+        let response = router
+            .handle(&mut request, &ErrorPage::default())
+            .expect("Failed to handle request");
+
+        assert_eq!(response.header.status_code, 200);
+    }
+
+    #[test]
+    fn test_head_and_not_found() {
+        let router = router().not_found(|r| text(format!("missing {}", r.header.uri)));
+        let mut request = new_request(RequestMethod::Head, "/users/7");
+
+        let response = router
+            .respond(&mut request, None, &ErrorPage::default())
+            .expect("Failed to respond");
+
+        assert!(response.body.is_empty());
+        assert_eq!(header(&response, "Content-Length"), Some("6"));
+        assert_eq!(header(&response, "Connection"), Some("close"));
+
+        assert_eq!(
+            body(&router, RequestMethod::Get, "/nope"),
+            (200, "missing /nope".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_handler_panics() {
+        let router = Router::new().get("/", |_| panic!("Handler failed"));
+        let mut request = new_request(RequestMethod::Get, "/");
+
+        let e = router
+            .handle(&mut request, &ErrorPage::default())
+            .expect_err("Handler didn't fail");
+
+        assert_eq!(e.to_string(), "The handler for 'GET /' panicked");
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        let error = |pattern: &str| {
+            Router::new()
+                .get(pattern, |_| text(String::new()))
+                .check()
+                .expect_err("Accepted an invalid pattern")
+                .to_string()
+        };
+
+        assert_eq!(
+            error("users"),
+            "Invalid route 'users' - it must start with '/'"
+        );
+        assert_eq!(
+            error("/a/*rest/b"),
+            "Invalid route '/a/*rest/b' - a wildcard must be the last segment"
+        );
+        assert_eq!(
+            error("/a/:"),
+            "Invalid route '/a/:' - a parameter is missing its name"
+        );
+    }
+}
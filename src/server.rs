@@ -0,0 +1,1525 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::access_log::{AccessLog, AccessRecord, CountingReader, CountingWriter};
+use crate::config::Server;
+use crate::error::TcpIpError;
+use crate::forward_proxy::Destinations;
+use crate::har::{Capture, Exchange};
+use crate::http_item::HttpItem;
+use crate::http_version::HttpVersion;
+use crate::replay::Replay;
+use crate::request::Request;
+use crate::router::Router;
+use crate::stream_helper::{setup_stream, ConnectionPool, Forwarder, KeepAlive};
+use crate::Result;
+
+/// How often the accept loop checks whether it has been stopped, or whether a
+/// worker is free when the overload policy is to queue.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub const DEFAULT_MAX_CONNECTIONS: usize = 256;
+pub const DEFAULT_ACCEPT_BACKLOG: usize = 64;
+
+/// What a server does with a new connection when every worker is busy and the
+/// accept backlog is full.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum OverloadPolicy {
+    /// Stop accepting until a worker is free, leaving new connections waiting
+    /// in the OS listen queue.
+    #[default]
+    Queue,
+    /// Send a 503 Service Unavailable response and close the connection.
+    Reject,
+    /// Close the connection without a response.
+    Close,
+}
+
+impl FromStr for OverloadPolicy {
+    type Err = TcpIpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "queue" => Ok(OverloadPolicy::Queue),
+            "reject" => Ok(OverloadPolicy::Reject),
+            "close" => Ok(OverloadPolicy::Close),
+            _ => Err(TcpIpError::parse(format!(
+                "Unknown overload policy '{}', expected 'queue', 'reject' or 'close'",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for OverloadPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            OverloadPolicy::Queue => "queue",
+            OverloadPolicy::Reject => "reject",
+            OverloadPolicy::Close => "close",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+// Creates a forwarder with the options of `server`, for blocking or async connections
+pub(crate) fn new_forwarder<S>(server: &Server) -> Forwarder<S> {
+    Forwarder {
+        name: server.name.clone(),
+        remote_address: server.remote_address.clone(),
+        timeout_seconds: server.timeout,
+        pool: ConnectionPool::new(
+            server.pool_max_idle,
+            Duration::from_secs(server.pool_idle_timeout),
+        ),
+        error_page: server.error_page.clone(),
+        max_body_size: server.max_body_size,
+    }
+}
+
+// State shared by every connection accepted with the same server options
+pub(crate) struct Proxy {
+    pub(crate) forwarder: Forwarder,
+    pub(crate) max_header_size: usize,
+    pub(crate) max_requests: usize,
+    pub(crate) max_connections: usize,
+    pub(crate) accept_backlog: usize,
+    pub(crate) overload: OverloadPolicy,
+    pub(crate) journal: Journal,
+    pub(crate) replay: Option<Replay>,
+    /// Forwards each request to the server it names if set.
+    pub(crate) destinations: Option<Destinations>,
+    /// Answers requests instead of forwarding or replaying them if set.
+    pub(crate) router: Option<Arc<Router>>,
+}
+
+impl Proxy {
+    pub(crate) fn new(server: &Server, router: Option<Arc<Router>>) -> Result<Self> {
+        Ok(Self {
+            forwarder: new_forwarder(server),
+            max_header_size: server.max_header_size,
+            max_requests: server.max_requests,
+            max_connections: server.max_connections,
+            accept_backlog: server.accept_backlog,
+            overload: server.overload,
+            journal: Journal::open(server)?,
+            replay: open_replay(server)?,
+            destinations: forward_proxy_destinations(server),
+            router,
+        })
+    }
+
+    // Tells the client the server is too busy, without reading its request
+    fn reject(&self, stream: &TcpStream) {
+        if setup_stream(stream, self.forwarder.timeout_seconds).is_ok() {
+            let e = TcpIpError::Overloaded {
+                max_connections: self.max_connections,
+            };
+
+            let _ = self
+                .forwarder
+                .send_error_response(&e, &mut &*stream, HttpVersion::Http11);
+            let _ = stream.shutdown(Shutdown::Write);
+        }
+    }
+
+    fn handle_connection(&self, stream: TcpStream, slot: ConnectionSlot) {
+        if let Err(e) = setup_stream(&stream, self.forwarder.timeout_seconds) {
+            eprintln!("{}", e);
+            return;
+        }
+
+        let mut local_reader = BufReader::new(&stream);
+        let mut local_writer = BufWriter::new(&stream);
+
+        self.serve(
+            &mut local_reader,
+            &mut local_writer,
+            &slot,
+            stream.peer_addr().ok(),
+        );
+
+        // Let the client see the end of the last response before the socket is closed
+        let _ = local_writer.flush();
+        let _ = stream.shutdown(Shutdown::Write);
+    }
+
+    // Forwards requests read from a local connection until it should be closed
+    pub(crate) fn serve<R: BufRead, W: Write>(
+        &self,
+        local_reader: &mut R,
+        local_writer: &mut W,
+        slot: &ConnectionSlot,
+        client_address: Option<SocketAddr>,
+    ) {
+        let mut local_reader = CountingReader::new(local_reader);
+        let mut local_writer = CountingWriter::new(local_writer);
+
+        for served in 0..self.max_requests.max(1) {
+            if !slot.idle() {
+                break;
+            }
+
+            let counted = self.journal.start(&mut local_reader, &mut local_writer);
+
+            let mut request =
+                match Request::from_reader_with_limit(&mut local_reader, self.max_header_size) {
+                    Ok(request) => request,
+                    Err(TcpIpError::ConnectionClosed) => break,
+                    // The client kept the connection open without sending another request
+                    Err(e @ TcpIpError::Io(_)) if e.is_timeout() => break,
+                    Err(e) => {
+                        let e = if e.is_timeout() {
+                            TcpIpError::ClientTimeout
+                        } else {
+                            e
+                        };
+
+                        let mut record = AccessRecord::new(&self.forwarder.name, client_address);
+                        record.status_code = e.to_status() as u16;
+                        record.error = Some(e.to_string());
+
+                        let _ = self.forwarder.send_error_response(
+                            &e,
+                            &mut local_writer,
+                            HttpVersion::Http11,
+                        );
+
+                        self.journal.finish(
+                            record,
+                            counted,
+                            Instant::now(),
+                            &mut local_reader,
+                            &mut local_writer,
+                        );
+                        break;
+                    }
+                };
+
+            slot.busy();
+
+            let started = Instant::now();
+            let mut record = AccessRecord::new(&self.forwarder.name, client_address);
+            record.set_request(&request.header);
+
+            let keep_alive = KeepAlive {
+                timeout_seconds: self.forwarder.timeout_seconds,
+                remaining_requests: self.max_requests.saturating_sub(served),
+            };
+
+            let forwarded = match (&self.router, &self.replay, &self.destinations) {
+                (Some(router), _, _) => self.forwarder.route_request(
+                    router,
+                    &mut request,
+                    &mut local_writer,
+                    keep_alive,
+                ),
+                (None, Some(replay), _) => self.forwarder.replay_request(
+                    replay,
+                    &mut request,
+                    &mut local_writer,
+                    keep_alive,
+                ),
+                (None, None, Some(destinations)) => self.forwarder.proxy_request(
+                    destinations,
+                    &mut request,
+                    &mut local_writer,
+                    keep_alive,
+                ),
+                (None, None, None) => {
+                    self.forwarder
+                        .forward_request(&mut request, &mut local_writer, keep_alive)
+                }
+            };
+
+            drop(request);
+
+            let keep_alive = match forwarded {
+                Ok(forwarded) => {
+                    record.status_code = forwarded.status_code;
+                    record.upstream_latency = forwarded.upstream_latency;
+                    record.error = forwarded.error;
+                    forwarded.keep_alive
+                }
+                // Part of the response may have been sent so the connection can't be reused
+                Err(e) => {
+                    record.status_code = e.to_status() as u16;
+                    record.error = Some(e.to_string());
+                    false
+                }
+            };
+
+            self.journal.finish(
+                record,
+                counted,
+                started,
+                &mut local_reader,
+                &mut local_writer,
+            );
+
+            if !keep_alive {
+                break;
+            }
+        }
+    }
+}
+
+/// Tells the user where a server that has just started sends its requests.
+pub(crate) fn print_started(server: &Server, router: Option<&Router>, local_address: SocketAddr) {
+    let upstream = match (router, &server.replay.file) {
+        (Some(router), _) => format!("Routing requests to {} handlers", router.len()),
+        (None, Some(path)) => format!("Replaying responses recorded in '{}'", path.display()),
+        (None, None) if server.forward_proxy.enabled => {
+            "Forwarding requests to the servers they name".to_owned()
+        }
+        (None, None) => format!("Forwarding requests to 'http://{}'", server.remote_address),
+    };
+
+    println!(
+        "Proxy service started at 'http://{}'. {}. Timeout is {} seconds.\n",
+        local_address, upstream, server.timeout
+    );
+}
+
+/// Returns the servers `server` may forward requests to, if it is a forward proxy.
+pub(crate) fn forward_proxy_destinations(server: &Server) -> Option<Destinations> {
+    if server.forward_proxy.enabled {
+        Some(server.forward_proxy.destinations.clone())
+    } else {
+        None
+    }
+}
+
+/// Reads the exchanges `server` replays, if it replays rather than forwards requests.
+pub(crate) fn open_replay(server: &Server) -> Result<Option<Replay>> {
+    match &server.replay.file {
+        Some(path) => Replay::open(path, server.replay.matching.clone())
+            .map(Some)
+            .map_err(|e| {
+                TcpIpError::config(format!(
+                    "Server '{}' can't replay '{}' - {}",
+                    server.name,
+                    path.display(),
+                    e
+                ))
+            }),
+        None => Ok(None),
+    }
+}
+
+// Writes each exchange on a connection to the access log and HAR capture of a server
+#[derive(Default)]
+pub(crate) struct Journal {
+    pub(crate) access_log: Option<AccessLog>,
+    pub(crate) capture: Option<Capture>,
+    // Recorded in addition to the body size limit of the capture
+    max_header_size: usize,
+    // Host of the URLs of captured requests without a Host header
+    host: String,
+}
+
+impl Journal {
+    pub(crate) fn open(server: &Server) -> Result<Self> {
+        let access_log = if server.log {
+            Some(AccessLog::open(&server.access_log)?)
+        } else {
+            None
+        };
+
+        let capture = match &server.capture.file {
+            Some(path) => Some(Capture::open(path, server.capture.max_body_size)?),
+            None => None,
+        };
+
+        Ok(Self {
+            access_log,
+            capture,
+            max_header_size: server.max_header_size,
+            host: server.listen_address.to_string(),
+        })
+    }
+
+    // Starts recording the next exchange on a connection, returning the bytes
+    // transferred before it
+    pub(crate) fn start<R, W>(
+        &self,
+        local_reader: &mut CountingReader<R>,
+        local_writer: &mut CountingWriter<W>,
+    ) -> (u64, u64) {
+        if let Some(capture) = &self.capture {
+            let limit = self.max_header_size.saturating_add(capture.max_body_size());
+
+            local_reader.record(limit);
+            local_writer.record(limit);
+        }
+
+        (local_reader.count(), local_writer.count())
+    }
+
+    // Logs `record` with the bytes transferred since the reader and writer were
+    // at `counted`, and captures the exchange if it was recorded
+    pub(crate) fn finish<R, W>(
+        &self,
+        mut record: AccessRecord,
+        counted: (u64, u64),
+        started: Instant,
+        local_reader: &mut CountingReader<R>,
+        local_writer: &mut CountingWriter<W>,
+    ) {
+        if let Some(access_log) = &self.access_log {
+            record.bytes_in = local_reader.count() - counted.0;
+            record.bytes_out = local_writer.count() - counted.1;
+
+            access_log.log(&record);
+        }
+
+        if let (Some(capture), Some(request), Some(response)) = (
+            &self.capture,
+            local_reader.take_recording(),
+            local_writer.take_recording(),
+        ) {
+            let exchange = Exchange {
+                started: record.time,
+                time: started.elapsed(),
+                wait: record.upstream_latency,
+                request: &request,
+                response: &response,
+            };
+
+            capture.write(&exchange, &self.host);
+        }
+    }
+}
+
+struct TrackedConnection {
+    stream: TcpStream,
+    /// True while a request is being forwarded.
+    busy: bool,
+}
+
+#[derive(Default)]
+struct Registry {
+    stopping: bool,
+    next_id: u64,
+    connections: HashMap<u64, TrackedConnection>,
+}
+
+// The connections accepted by a server, so they can be drained when it stops
+#[derive(Default)]
+struct Connections {
+    registry: Mutex<Registry>,
+    closed: Condvar,
+}
+
+impl Connections {
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn is_stopping(&self) -> bool {
+        self.lock().stopping
+    }
+
+    // Returns None if the server has been stopped
+    fn register(self: &Arc<Self>, stream: &TcpStream) -> Result<Option<ConnectionSlot>> {
+        let mut registry = self.lock();
+
+        if registry.stopping {
+            return Ok(None);
+        }
+
+        let id = registry.next_id;
+        registry.next_id += 1;
+        registry.connections.insert(
+            id,
+            TrackedConnection {
+                stream: stream.try_clone()?,
+                busy: false,
+            },
+        );
+
+        Ok(Some(ConnectionSlot {
+            id,
+            connections: Some(self.clone()),
+        }))
+    }
+
+    // Stops new requests being read and closes connections waiting for one
+    fn stop(&self) {
+        let mut registry = self.lock();
+
+        registry.stopping = true;
+
+        for connection in registry.connections.values().filter(|c| !c.busy) {
+            let _ = connection.stream.shutdown(Shutdown::Read);
+        }
+    }
+
+    // Waits for every connection to close, closing any left after `timeout`.
+    // Returns the number of connections that were closed.
+    fn wait(&self, timeout: Option<Duration>) -> usize {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut registry = self.lock();
+
+        while !registry.connections.is_empty() {
+            registry = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+
+                    if now >= deadline {
+                        break;
+                    }
+
+                    self.closed
+                        .wait_timeout(registry, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .closed
+                    .wait(registry)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+
+        for connection in registry.connections.values() {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+        }
+
+        registry.connections.len()
+    }
+}
+
+/// Tracks whether a connection is waiting for a request or forwarding one,
+/// and unregisters it when dropped.
+pub(crate) struct ConnectionSlot {
+    id: u64,
+    connections: Option<Arc<Connections>>,
+}
+
+impl ConnectionSlot {
+    /// A slot for a connection that doesn't belong to a server.
+    #[cfg(test)]
+    pub(crate) fn detached() -> Self {
+        Self {
+            id: 0,
+            connections: None,
+        }
+    }
+
+    /// Marks the connection as waiting for a request, returning false if no
+    /// more requests should be read because the server is stopping.
+    pub(crate) fn idle(&self) -> bool {
+        self.set_busy(false)
+    }
+
+    /// Marks the connection as forwarding a request, which is allowed to
+    /// finish when the server stops.
+    pub(crate) fn busy(&self) {
+        self.set_busy(true);
+    }
+
+    fn set_busy(&self, busy: bool) -> bool {
+        let connections = match &self.connections {
+            Some(connections) => connections,
+            None => return true,
+        };
+
+        let mut registry = connections.lock();
+
+        if let Some(connection) = registry.connections.get_mut(&self.id) {
+            connection.busy = busy;
+        }
+
+        !registry.stopping
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        if let Some(connections) = &self.connections {
+            connections.lock().connections.remove(&self.id);
+            connections.closed.notify_all();
+        }
+    }
+}
+
+// An accepted connection waiting for a worker
+struct Job {
+    stream: TcpStream,
+    slot: ConnectionSlot,
+    proxy: Arc<Proxy>,
+}
+
+#[derive(Default)]
+struct WorkerState {
+    running: usize,
+    queue: VecDeque<Job>,
+}
+
+// Serves connections on at most `max_connections` threads, queueing up to
+// `accept_backlog` more. Threads exit once the queue is empty.
+#[derive(Default)]
+struct Workers {
+    state: Mutex<WorkerState>,
+}
+
+impl Workers {
+    // Returns the job if every worker is busy and the queue is full
+    fn dispatch(self: &Arc<Self>, job: Job) -> std::result::Result<(), Job> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if state.running < job.proxy.max_connections {
+            state.running += 1;
+
+            let workers = self.clone();
+
+            thread::spawn(move || workers.run(job));
+        } else if state.queue.len() < job.proxy.accept_backlog {
+            state.queue.push_back(job);
+        } else {
+            return Err(job);
+        }
+
+        Ok(())
+    }
+
+    fn run(&self, mut job: Job) {
+        loop {
+            let Job {
+                stream,
+                slot,
+                proxy,
+            } = job;
+
+            // Like a panicking route handler, a panic only loses its own connection
+            // so the worker still serves the queue or gives up its place
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| proxy.handle_connection(stream, slot)));
+
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+            match state.queue.pop_front() {
+                Some(next) => job = next,
+                None => {
+                    state.running -= 1;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// A running server, returned by `Server::start`.
+///
+/// Dropping the handle leaves the server running until the process exits.
+pub struct ServerHandle {
+    pub(crate) server: Server,
+    local_address: SocketAddr,
+    proxy: Arc<RwLock<Arc<Proxy>>>,
+    router: Option<Arc<Router>>,
+    connections: Arc<Connections>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    pub(crate) fn spawn(server: Server) -> Result<Self> {
+        Self::spawn_with(server, None)
+    }
+
+    /// Starts a server that answers requests with `router` if it is given.
+    pub(crate) fn spawn_with(server: Server, router: Option<Arc<Router>>) -> Result<Self> {
+        let listener = TcpListener::bind(server.listen_address.resolve()?.as_slice())?;
+        let local_address = listener.local_addr()?;
+
+        // Accept without blocking so the loop can notice it has been stopped
+        listener.set_nonblocking(true)?;
+
+        print_started(&server, router.as_deref(), local_address);
+
+        let proxy = Arc::new(RwLock::new(Arc::new(Proxy::new(&server, router.clone())?)));
+        let connections = Arc::new(Connections::default());
+
+        let accept_thread = {
+            let proxy = proxy.clone();
+            let connections = connections.clone();
+
+            thread::spawn(move || accept_loop(listener, &proxy, &connections))
+        };
+
+        Ok(Self {
+            server,
+            local_address,
+            proxy,
+            router,
+            connections,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    /// The address the server is listening on, which has the port chosen by
+    /// the OS if the server was configured with port 0.
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    /// Stops accepting connections and waits for every open connection to
+    /// finish its current request.
+    ///
+    /// Connections waiting for a request are closed straight away. Requests
+    /// can't last forever as every read and write is limited by the server's
+    /// timeout.
+    pub fn shutdown(mut self) {
+        self.stop_accepting();
+        self.wait(None);
+    }
+
+    /// Like `shutdown`, but closes any connection still forwarding a request
+    /// after `timeout`. Returns the number of connections that were closed
+    /// before their request finished.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> usize {
+        self.stop_accepting();
+        self.wait(Some(timeout))
+    }
+
+    /// Blocks the current thread while the server runs, which is until the
+    /// process exits as the handle is needed to shut it down.
+    pub fn join(mut self) -> thread::Result<()> {
+        match self.accept_thread.take() {
+            Some(accept_thread) => accept_thread.join(),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies new options to connections accepted from now on. Open
+    /// connections keep the options they were accepted with.
+    ///
+    /// The listen address can't be changed without starting a new server.
+    pub(crate) fn update(&mut self, server: Server) -> Result<()> {
+        let proxy = Arc::new(Proxy::new(&server, self.router.clone())?);
+
+        *self.proxy.write().unwrap_or_else(PoisonError::into_inner) = proxy;
+        self.server = server;
+
+        Ok(())
+    }
+
+    /// Stops accepting connections and closes those waiting for a request.
+    /// Requests being forwarded are allowed to finish.
+    pub(crate) fn stop_accepting(&mut self) {
+        self.connections.stop();
+
+        if let Some(accept_thread) = self.accept_thread.take() {
+            let _ = accept_thread.join();
+
+            println!(
+                "Proxy service at 'http://{}' stopped accepting connections.\n",
+                self.local_address
+            );
+        }
+    }
+
+    /// Waits for open connections to close, closing any left after `timeout`.
+    /// Returns the number of connections that had to be closed.
+    pub(crate) fn wait(&self, timeout: Option<Duration>) -> usize {
+        self.connections.wait(timeout)
+    }
+}
+
+fn accept_loop(listener: TcpListener, proxy: &RwLock<Arc<Proxy>>, connections: &Arc<Connections>) {
+    let workers = Arc::new(Workers::default());
+
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                // Accepted sockets may inherit non-blocking mode from the listener
+                if let Err(e) = stream.set_nonblocking(false) {
+                    eprintln!("{}", e);
+                    continue;
+                }
+
+                let slot = match connections.register(&stream) {
+                    Ok(Some(slot)) => slot,
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        continue;
+                    }
+                };
+
+                let proxy = proxy.read().unwrap_or_else(PoisonError::into_inner).clone();
+
+                dispatch(
+                    &workers,
+                    connections,
+                    Job {
+                        stream,
+                        slot,
+                        proxy,
+                    },
+                );
+            }
+            Err(e) => {
+                if connections.is_stopping() {
+                    break;
+                }
+
+                if e.kind() != ErrorKind::WouldBlock {
+                    eprintln!("{}", e);
+                }
+
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+// Hands a connection to a worker, applying the overload policy if there is no room
+fn dispatch(workers: &Arc<Workers>, connections: &Connections, mut job: Job) {
+    loop {
+        job = match workers.dispatch(job) {
+            Ok(()) => return,
+            Err(job) => job,
+        };
+
+        match job.proxy.overload {
+            OverloadPolicy::Queue if !connections.is_stopping() => {
+                thread::sleep(ACCEPT_POLL_INTERVAL)
+            }
+            OverloadPolicy::Reject => return job.proxy.reject(&job.stream),
+            // Dropping the job closes the connection
+            _ => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+    use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::access_log::{AccessLog, LogFormat, LogSink};
+    use crate::address::Address;
+    use crate::config::Server;
+    use crate::error_page::ErrorPage;
+    use crate::http_item::HttpItem;
+    use crate::request::Request;
+    use crate::response::ResponseBuilder;
+    use crate::router::Router;
+    use crate::server::{
+        ConnectionSlot, Job, Journal, OverloadPolicy, Proxy, ServerHandle, Workers,
+    };
+    use crate::stream_helper::{ConnectionPool, Forwarder, DEFAULT_MAX_BODY_SIZE};
+
+    // Collects access log lines
+    #[derive(Default)]
+    struct Lines(Mutex<Vec<String>>);
+
+    impl LogSink for Lines {
+        fn write_line(&self, line: &str) -> io::Result<()> {
+            self.0
+                .lock()
+                .expect("Failed to lock lines")
+                .push(line.to_owned());
+            Ok(())
+        }
+    }
+
+    // An upstream answering every request on a connection with "ok"
+    fn upstream() -> Address {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+        let address = Address::from(listener.local_addr().expect("Missing local address"));
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(&stream);
+                    let mut writer = &stream;
+
+                    while let Ok(mut request) = Request::from_reader(&mut reader) {
+                        if request.buffer_body().is_err() {
+                            break;
+                        }
+
+                        let _ = writer.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+                    }
+                });
+            }
+        });
+
+        address
+    }
+
+    fn test_server() -> Server {
+        let mut server = Server::new("test", Address::new("127.0.0.1", 0), upstream());
+        server.log = false;
+        server
+    }
+
+    fn running_server() -> ServerHandle {
+        ServerHandle::spawn(test_server()).expect("Failed to start server")
+    }
+
+    fn read_response(reader: &mut BufReader<&TcpStream>) -> String {
+        let mut response = String::new();
+
+        while !response.ends_with("\r\n\r\n") {
+            reader.read_line(&mut response).expect("Failed to read");
+        }
+
+        let mut body = [0; 2];
+        reader.read_exact(&mut body).expect("Failed to read body");
+
+        response + &String::from_utf8_lossy(&body)
+    }
+
+    fn connect(address: SocketAddr) -> TcpStream {
+        let stream = TcpStream::connect(address).expect("Failed to connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(4)))
+            .expect("Failed to set timeout");
+        stream
+    }
+
+    // A proxy that can't reach a remote server, logging to `sink`
+    fn logging_proxy(sink: Arc<dyn LogSink>) -> Proxy {
+        Proxy {
+            forwarder: Forwarder {
+                name: "test".to_owned(),
+                remote_address: Address::new("127.0.0.1", 9),
+                timeout_seconds: 4,
+                pool: ConnectionPool::default(),
+                error_page: ErrorPage::new("text/plain", "{status_code} {reason_phrase}"),
+                max_body_size: DEFAULT_MAX_BODY_SIZE,
+            },
+            max_header_size: 1024,
+            max_requests: 100,
+            max_connections: 1,
+            accept_backlog: 1,
+            overload: OverloadPolicy::Queue,
+            journal: Journal {
+                access_log: Some(AccessLog::new(LogFormat::Common, sink)),
+                ..Journal::default()
+            },
+            replay: None,
+            destinations: None,
+            router: None,
+        }
+    }
+
+    #[test]
+    fn test_serve_bad_request() {
+        let lines = Arc::new(Lines::default());
+        let proxy = logging_proxy(lines.clone());
+
+        let mut local_reader = Cursor::new(b"BREW /pot HTTP/1.1\r\n\r\n".to_vec());
+        let mut local_writer = Vec::new();
+
+        proxy.serve(
+            &mut local_reader,
+            &mut local_writer,
+            &ConnectionSlot::detached(),
+            Some(([127, 0, 0, 1], 50000).into()),
+        );
+
+        assert_eq!(
+            String::from_utf8_lossy(&local_writer),
+            "HTTP/1.1 400 Bad Request\r\nContent-Type: text/plain\r\nConnection: close\r\nContent-Length: 15\r\n\r\n400 Bad Request"
+        );
+
+        let lines = lines.0.lock().expect("Failed to lock lines");
+
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("127.0.0.1 - - ["));
+        assert!(lines[0].ends_with(&format!("] \"-\" 400 {}", local_writer.len())));
+    }
+
+    #[test]
+    fn test_serve_http10_chunked_request() {
+        let proxy = logging_proxy(Arc::new(Lines::default()));
+
+        // HTTP/1.0 has no Transfer-Encoding, so the body can't be delimited
+        let mut local_reader = Cursor::new(
+            b"POST /x HTTP/1.0\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n"
+                .to_vec(),
+        );
+        let mut local_writer = Vec::new();
+
+        proxy.serve(
+            &mut local_reader,
+            &mut local_writer,
+            &ConnectionSlot::detached(),
+            None,
+        );
+
+        assert!(String::from_utf8_lossy(&local_writer).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn test_serve_logs_error() {
+        let lines = Arc::new(Lines::default());
+        let mut proxy = logging_proxy(lines.clone());
+        proxy.journal.access_log = Some(AccessLog::new(LogFormat::Json, lines.clone()));
+
+        let mut local_reader =
+            Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n".to_vec());
+        let mut local_writer = Vec::new();
+
+        proxy.serve(
+            &mut local_reader,
+            &mut local_writer,
+            &ConnectionSlot::detached(),
+            Some(([127, 0, 0, 1], 50000).into()),
+        );
+
+        assert!(String::from_utf8_lossy(&local_writer).starts_with("HTTP/1.1 502 "));
+
+        // The error is only recorded with the request, not printed as well
+        let lines = lines.0.lock().expect("Failed to lock lines");
+
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains(r#""status":502"#));
+        assert!(lines[0].contains(r#""error":"Failed to connect to "#));
+    }
+
+    #[test]
+    fn test_worker_survives_panic() {
+        struct Panics;
+
+        impl LogSink for Panics {
+            fn write_line(&self, _: &str) -> io::Result<()> {
+                panic!("Failed to log");
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+        let address = listener.local_addr().expect("Missing local address");
+        let proxy = Arc::new(logging_proxy(Arc::new(Panics)));
+        let workers = Arc::new(Workers::default());
+
+        // Both connections panic when their request is logged, the second
+        // waiting in the queue for the only worker
+        let clients = (0..2)
+            .map(|_| {
+                let client = connect(address);
+                (&client)
+                    .write_all(b"BREW /pot HTTP/1.1\r\n\r\n")
+                    .expect("Failed to write");
+
+                let (stream, _) = listener.accept().expect("Failed to accept");
+                let job = Job {
+                    stream,
+                    slot: ConnectionSlot::detached(),
+                    proxy: proxy.clone(),
+                };
+
+                assert!(workers.dispatch(job).is_ok());
+
+                client
+            })
+            .collect::<Vec<_>>();
+
+        // The second is only answered if the worker survived the first
+        for client in &clients {
+            let mut response = String::new();
+            (&*client)
+                .read_to_string(&mut response)
+                .expect("Failed to read");
+
+            assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        }
+
+        // The worker gave up its place rather than dying while counted as running
+        let deadline = Instant::now() + Duration::from_secs(4);
+
+        while workers.state.lock().expect("Failed to lock").running > 0 {
+            assert!(Instant::now() < deadline, "The worker is still counted");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_capture_and_replay() {
+        let dir = std::env::temp_dir().join(format!("server_capture_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Failed to create directory");
+
+        let path = dir.join("capture.har");
+        let mut server = test_server();
+        server.capture.file = Some(path.clone());
+
+        let mut running = ServerHandle::spawn(server).expect("Failed to start server");
+
+        let stream = connect(running.local_address());
+        let mut reader = BufReader::new(&stream);
+
+        (&stream)
+            .write_all(b"GET /a?x=1 HTTP/1.1\r\nHost: test\r\n\r\nPOST /b HTTP/1.1\r\nHost: test\r\nContent-Length: 4\r\n\r\n\xff\x00\x01\x02")
+            .expect("Failed to write");
+
+        assert!(read_response(&mut reader).ends_with("\r\n\r\nok"));
+        assert!(read_response(&mut reader).ends_with("\r\n\r\nok"));
+
+        running.stop_accepting();
+        assert_eq!(running.wait(Some(Duration::from_secs(2))), 0);
+
+        let har = fs::read_to_string(&path).expect("Failed to read HAR file");
+        let entries = har
+            .lines()
+            .filter(|l| l.starts_with("{\"startedDateTime\""))
+            .collect::<Vec<_>>();
+
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].contains(r#""url":"http://test/a?x=1""#));
+        assert!(entries[0].contains(r#""queryString":[{"name":"x","value":"1"}]"#));
+        assert!(entries[0].contains(r#""content":{"size":2,"mimeType":"x-unknown","text":"ok"}"#));
+        assert!(entries[1].contains(r#""text":"/wABAg==","_encoding":"base64"}"#));
+        assert!(har.ends_with("}\n]}}\n"));
+
+        // The recorded responses are replayed without reaching the remote server
+        let mut server = Server::new(
+            "replay",
+            Address::new("127.0.0.1", 0),
+            Address::new("127.0.0.1", 9),
+        );
+        server.log = false;
+        server.replay.file = Some(path);
+
+        let running = ServerHandle::spawn(server).expect("Failed to start server");
+
+        let stream = connect(running.local_address());
+        let mut reader = BufReader::new(&stream);
+
+        (&stream)
+            .write_all(b"POST /b HTTP/1.1\r\nContent-Length: 4\r\n\r\n\xff\x00\x01\x02GET /c HTTP/1.1\r\n\r\n")
+            .expect("Failed to write");
+
+        assert!(read_response(&mut reader).ends_with("\r\n\r\nok"));
+
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line).expect("Failed to read");
+
+        assert_eq!(status_line, "HTTP/1.1 404 Not Found\r\n");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_forward_proxy() {
+        let upstream = upstream();
+
+        let mut server = test_server();
+        server.remote_address = Address::new("127.0.0.1", 9);
+        server.forward_proxy.enabled = true;
+        server.forward_proxy.destinations.deny = vec!["*:9".parse().expect("Invalid rule")];
+
+        let running = ServerHandle::spawn(server).expect("Failed to start server");
+
+        let stream = connect(running.local_address());
+        let mut reader = BufReader::new(&stream);
+
+        // Both are sent to the upstream server named by the URL and the Host header
+        (&stream)
+            .write_all(
+                format!(
+                    "GET http://{0}/a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\nHost: {0}\r\n\r\n",
+                    upstream
+                )
+                .as_bytes(),
+            )
+            .expect("Failed to write");
+
+        assert!(read_response(&mut reader).starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(read_response(&mut reader).starts_with("HTTP/1.1 200 OK\r\n"));
+
+        (&stream)
+            .write_all(b"GET http://127.0.0.1:9/ HTTP/1.1\r\n\r\n")
+            .expect("Failed to write");
+
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line).expect("Failed to read");
+
+        assert_eq!(status_line, "HTTP/1.1 403 Forbidden\r\n");
+    }
+
+    #[test]
+    fn test_forward_proxy_deny_by_address() {
+        let upstream = upstream();
+
+        let mut server = test_server();
+        server.forward_proxy.enabled = true;
+        server.forward_proxy.destinations.deny = ["127.0.0.1", "[::1]"]
+            .iter()
+            .map(|rule| rule.parse().expect("Invalid rule"))
+            .collect();
+
+        let running = ServerHandle::spawn(server).expect("Failed to start server");
+
+        // Other spellings of a denied address, and names that resolve to one
+        for host in [
+            "127.0.0.1.",
+            "127.1",
+            "0x7f000001",
+            "[::ffff:127.0.0.1]",
+            "localhost",
+        ] {
+            let stream = connect(running.local_address());
+
+            (&stream)
+                .write_all(
+                    format!("GET http://{}:{}/ HTTP/1.1\r\n\r\n", host, upstream.port).as_bytes(),
+                )
+                .expect("Failed to write");
+
+            let mut status_line = String::new();
+            BufReader::new(&stream)
+                .read_line(&mut status_line)
+                .expect("Failed to read");
+
+            assert_eq!(status_line, "HTTP/1.1 403 Forbidden\r\n", "{}", host);
+        }
+    }
+
+    #[test]
+    fn test_serve_router() {
+        let router = Router::new().post("/echo/:value", |request| {
+            let body = request.body.as_bytes().unwrap_or_default();
+
+            ResponseBuilder::new()
+                .status_code(200)
+                .body([request.param("value").unwrap_or_default().as_bytes(), body].concat())
+                .build()
+                .expect("Failed to build response")
+        });
+
+        let mut server = test_server();
+        server.remote_address = Address::new("127.0.0.1", 9);
+
+        let running = server.serve(router).expect("Failed to start server");
+
+        let stream = connect(running.local_address());
+        let mut reader = BufReader::new(&stream);
+
+        (&stream)
+            .write_all(
+                b"POST /echo/o HTTP/1.1\r\nContent-Length: 1\r\n\r\nkGET /echo/o HTTP/1.1\r\n\r\n",
+            )
+            .expect("Failed to write");
+
+        let response = read_response(&mut reader);
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Connection: keep-alive\r\n"));
+        assert!(response.ends_with("\r\n\r\nok"));
+
+        let response = read_response(&mut reader);
+
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(response.contains("Allow: POST\r\n"));
+
+        running.shutdown();
+    }
+
+    #[test]
+    fn test_router_body_too_large() {
+        let router = Router::new().post("/", |_| {
+            ResponseBuilder::new()
+                .status_code(200)
+                .body(b"ok".to_vec())
+                .build()
+                .expect("Failed to build response")
+        });
+
+        let mut server = test_server();
+        server.remote_address = Address::new("127.0.0.1", 9);
+        server.max_body_size = 4;
+
+        let running = server.serve(router).expect("Failed to start server");
+
+        for request in [
+            &b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nabcd"[..],
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nabcd\r\n0\r\n\r\n",
+        ] {
+            let stream = connect(running.local_address());
+            (&stream).write_all(request).expect("Failed to write");
+
+            let response = read_response(&mut BufReader::new(&stream));
+
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        }
+
+        // The chunked body is rejected once it passes the limit, without reading the rest
+        for request in [
+            &b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n"[..],
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n",
+        ] {
+            let stream = connect(running.local_address());
+            (&stream).write_all(request).expect("Failed to write");
+
+            let response = read_response(&mut BufReader::new(&stream));
+
+            assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+            assert!(response.contains("Connection: close\r\n"));
+        }
+
+        running.shutdown();
+    }
+
+    // Asserts the proxy closed the connection after the responses already read
+    fn assert_closed(reader: &mut BufReader<&TcpStream>) {
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).expect("Failed to read");
+
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_client_connection_close() {
+        let running = running_server();
+
+        let stream = connect(running.local_address());
+        let mut reader = BufReader::new(&stream);
+
+        (&stream)
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .expect("Failed to write");
+
+        let response = read_response(&mut reader);
+
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(!response.contains("Keep-Alive"));
+        assert_closed(&mut reader);
+
+        running.shutdown();
+    }
+
+    #[test]
+    fn test_http10_client_not_reused() {
+        let running = running_server();
+
+        let stream = connect(running.local_address());
+        let mut reader = BufReader::new(&stream);
+
+        (&stream)
+            .write_all(b"GET / HTTP/1.0\r\n\r\n")
+            .expect("Failed to write");
+
+        let response = read_response(&mut reader);
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert_closed(&mut reader);
+
+        // HTTP/1.0 clients that ask for keep-alive keep the connection
+        let stream = connect(running.local_address());
+        let mut reader = BufReader::new(&stream);
+
+        (&stream)
+            .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .expect("Failed to write");
+
+        assert!(read_response(&mut reader).contains("Connection: keep-alive\r\n"));
+
+        running.shutdown();
+    }
+
+    #[test]
+    fn test_keep_alive_counts_down_to_max_requests() {
+        let mut server = test_server();
+        server.max_requests = 3;
+
+        let running = ServerHandle::spawn(server).expect("Failed to start server");
+
+        let stream = connect(running.local_address());
+        let mut reader = BufReader::new(&stream);
+
+        for remaining in (0..3).rev() {
+            (&stream)
+                .write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n")
+                .expect("Failed to write");
+
+            let response = read_response(&mut reader);
+
+            assert!(response.ends_with("\r\n\r\nok"));
+
+            if remaining > 0 {
+                assert!(response.contains("Connection: keep-alive\r\n"));
+                assert!(response.contains(&format!("Keep-Alive: timeout=4, max={}\r\n", remaining)));
+            } else {
+                assert!(response.contains("Connection: close\r\n"));
+            }
+        }
+
+        // The last allowed request closes the connection
+        assert_closed(&mut reader);
+
+        running.shutdown();
+    }
+
+    #[test]
+    fn test_client_stalls_during_body() {
+        let mut server = test_server();
+        server.timeout = 1;
+
+        let running = ServerHandle::spawn(server).expect("Failed to start server");
+
+        let stream = connect(running.local_address());
+
+        (&stream)
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab")
+            .expect("Failed to write");
+
+        // The client is blamed rather than the remote server
+        let mut response = String::new();
+        BufReader::new(&stream)
+            .read_to_string(&mut response)
+            .expect("Failed to read");
+
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+
+        // A client that leaves part way through a body gets no response
+        let stream = connect(running.local_address());
+
+        (&stream)
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab")
+            .expect("Failed to write");
+        stream
+            .shutdown(Shutdown::Write)
+            .expect("Failed to shut down");
+
+        assert_closed(&mut BufReader::new(&stream));
+
+        running.shutdown();
+    }
+
+    #[test]
+    fn test_stop_closes_idle_connections() {
+        let mut running = running_server();
+
+        let stream = connect(running.local_address());
+        let mut reader = BufReader::new(&stream);
+
+        (&stream)
+            .write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n")
+            .expect("Failed to write");
+
+        assert!(read_response(&mut reader).ends_with("\r\n\r\nok"));
+
+        running.stop_accepting();
+
+        assert_eq!(running.wait(Some(Duration::from_secs(2))), 0);
+
+        // The proxy closes the connection rather than waiting for another request
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).expect("Failed to read");
+
+        assert!(rest.is_empty());
+        assert!(TcpStream::connect(running.local_address()).is_err());
+    }
+
+    #[test]
+    fn test_stop_finishes_busy_connections() {
+        let mut running = running_server();
+
+        let stream = connect(running.local_address());
+        let mut reader = BufReader::new(&stream);
+
+        (&stream)
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab")
+            .expect("Failed to write");
+
+        // Give the proxy time to read the header and start forwarding
+        thread::sleep(Duration::from_millis(200));
+
+        running.stop_accepting();
+
+        (&stream).write_all(b"cde").expect("Failed to write");
+
+        let response = read_response(&mut reader);
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(running.wait(Some(Duration::from_secs(2))), 0);
+    }
+
+    #[test]
+    fn test_shutdown_timeout_closes_busy_connections() {
+        let running = running_server();
+
+        let stream = connect(running.local_address());
+
+        (&stream)
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab")
+            .expect("Failed to write");
+
+        thread::sleep(Duration::from_millis(200));
+
+        assert_eq!(running.shutdown_timeout(Duration::from_millis(100)), 1);
+    }
+
+    #[test]
+    fn test_overload_reject() {
+        let mut server = test_server();
+        server.max_connections = 1;
+        server.accept_backlog = 1;
+        server.overload = OverloadPolicy::Reject;
+
+        let running = ServerHandle::spawn(server).expect("Failed to start server");
+
+        // One connection is served and one waits for the worker
+        let _served = connect(running.local_address());
+        let _waiting = connect(running.local_address());
+
+        thread::sleep(Duration::from_millis(200));
+
+        let rejected = connect(running.local_address());
+        let mut response = String::new();
+        BufReader::new(&rejected)
+            .read_to_string(&mut response)
+            .expect("Failed to read");
+
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert_eq!(running.shutdown_timeout(Duration::from_secs(2)), 0);
+    }
+
+    #[test]
+    fn test_overload_queue() {
+        let mut server = test_server();
+        server.max_connections = 1;
+        server.accept_backlog = 1;
+
+        let running = ServerHandle::spawn(server).expect("Failed to start server");
+
+        let first = connect(running.local_address());
+        let queued = (0..2)
+            .map(|_| connect(running.local_address()))
+            .collect::<Vec<_>>();
+
+        // Each connection keeps the only worker until it is closed
+        for stream in &queued {
+            (&*stream)
+                .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+                .expect("Failed to write");
+        }
+
+        thread::sleep(Duration::from_millis(200));
+
+        // The waiting connections are served once the worker is free
+        drop(first);
+
+        for stream in &queued {
+            assert!(read_response(&mut BufReader::new(stream)).ends_with("\r\n\r\nok"));
+        }
+
+        running.shutdown();
+    }
+}
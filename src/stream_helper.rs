@@ -1,21 +1,203 @@
-use std::io::{BufReader, BufWriter, Write};
-use std::net::{SocketAddr, SocketAddrV4, TcpStream};
-use std::time::Duration;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+use crate::address::Address;
+use crate::body::{Body, ChunkedWriter};
+use crate::body_type::BodyType;
+use crate::error::TcpIpError;
+use crate::error_page::ErrorPage;
+use crate::forward_proxy::Destinations;
 use crate::header_item::HeaderItem;
 use crate::http_item::HttpItem;
+use crate::http_version::HttpVersion;
+use crate::replay::Replay;
 use crate::request::Request;
+use crate::response::response_header::ResponseHeader;
+use crate::response::response_status::ResponseStatus;
 use crate::response::Response;
+use crate::router::Router;
 use crate::Result;
 
-pub fn connect_remote(address: &SocketAddrV4, timeout_seconds: u64) -> Result<TcpStream> {
-    let address = SocketAddr::from(*address);
+pub const DEFAULT_POOL_MAX_IDLE: usize = 8;
+pub const DEFAULT_POOL_IDLE_TIMEOUT: u64 = 60;
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 10 * 1024 * 1024;
 
-    let remote_server = TcpStream::connect_timeout(&address, Duration::from_secs(timeout_seconds))?;
+struct IdleConnection<S> {
+    stream: S,
+    idle_since: Instant,
+}
+
+pub struct PooledConnection<S = TcpStream> {
+    pub stream: S,
+    /// True if the connection was taken from the pool rather than newly connected.
+    pub reused: bool,
+}
+
+/// Keeps connections to remote servers open between requests.
+///
+/// Connections are blocking `TcpStream`s unless the pool is used by the async forwarder.
+pub struct ConnectionPool<S = TcpStream> {
+    idle: Mutex<HashMap<Address, Vec<IdleConnection<S>>>>,
+    max_idle: usize,
+    idle_timeout: Duration,
+}
+
+impl<S> ConnectionPool<S> {
+    /// Creates a pool keeping up to `max_idle` connections per remote address,
+    /// each for at most `idle_timeout`.
+    pub fn new(max_idle: usize, idle_timeout: Duration) -> Self {
+        Self {
+            idle: Mutex::new(HashMap::new()),
+            max_idle,
+            idle_timeout,
+        }
+    }
+
+    /// Returns a connection to the pool once the response on it has been fully read.
+    pub fn checkin(&self, address: &Address, stream: S) {
+        if let Ok(mut idle) = self.idle.lock() {
+            let connections = idle.entry(address.clone()).or_default();
+
+            connections.retain(|c| c.idle_since.elapsed() < self.idle_timeout);
+
+            if connections.len() < self.max_idle {
+                connections.push(IdleConnection {
+                    stream,
+                    idle_since: Instant::now(),
+                });
+            }
+        }
+    }
+
+    pub fn idle_count(&self, address: &Address) -> usize {
+        self.idle
+            .lock()
+            .ok()
+            .and_then(|idle| idle.get(address).map(|c| c.len()))
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn take_idle(&self, address: &Address) -> Option<S> {
+        let mut idle = self.idle.lock().ok()?;
+        let connections = idle.get_mut(address)?;
+
+        // Most recently used connections are the least likely to have been closed
+        while let Some(connection) = connections.pop() {
+            if connection.idle_since.elapsed() < self.idle_timeout {
+                return Some(connection.stream);
+            }
+        }
+
+        None
+    }
+}
+
+impl ConnectionPool {
+    /// Returns a healthy idle connection to `address` or connects a new one.
+    pub fn checkout(&self, address: &Address, timeout_seconds: u64) -> Result<PooledConnection> {
+        self.checkout_or(address, || connect_remote(address, timeout_seconds))
+    }
+
+    /// Returns a healthy idle connection to `address` or connects a new one to
+    /// the socket addresses it was already resolved to.
+    pub fn checkout_resolved(
+        &self,
+        address: &Address,
+        resolved: &[SocketAddr],
+        timeout_seconds: u64,
+    ) -> Result<PooledConnection> {
+        self.checkout_or(address, || {
+            connect_resolved(address, resolved, timeout_seconds)
+        })
+    }
+
+    fn checkout_or<F>(&self, address: &Address, connect: F) -> Result<PooledConnection>
+    where
+        F: FnOnce() -> Result<TcpStream>,
+    {
+        while let Some(stream) = self.take_idle(address) {
+            if is_healthy(&stream) {
+                return Ok(PooledConnection {
+                    stream,
+                    reused: true,
+                });
+            }
+        }
+
+        Ok(PooledConnection {
+            stream: connect()?,
+            reused: false,
+        })
+    }
+}
 
-    setup_stream(&remote_server, timeout_seconds)?;
+impl<S> Default for ConnectionPool<S> {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_POOL_MAX_IDLE,
+            Duration::from_secs(DEFAULT_POOL_IDLE_TIMEOUT),
+        )
+    }
+}
+
+// An idle connection is healthy if the remote server has neither closed it
+// nor sent anything unexpected on it
+fn is_healthy(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+
+    let healthy = matches!(stream.peek(&mut [0; 1]), Err(e) if e.kind() == ErrorKind::WouldBlock);
+
+    stream.set_nonblocking(false).is_ok() && healthy
+}
+
+/// Connects to `address`, trying each address it resolves to in order.
+pub fn connect_remote(address: &Address, timeout_seconds: u64) -> Result<TcpStream> {
+    let resolved = address
+        .resolve()
+        .map_err(|e| upstream_connect_error(address, e))?;
+
+    connect_resolved(address, &resolved, timeout_seconds)
+}
+
+/// Connects to the socket addresses `address` resolved to, trying each in order.
+pub fn connect_resolved(
+    address: &Address,
+    resolved: &[SocketAddr],
+    timeout_seconds: u64,
+) -> Result<TcpStream> {
+    let mut last_error = None;
+
+    for socket_address in resolved {
+        match TcpStream::connect_timeout(socket_address, Duration::from_secs(timeout_seconds)) {
+            Ok(remote_server) => {
+                setup_stream(&remote_server, timeout_seconds)?;
+
+                return Ok(remote_server);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(upstream_connect_error(
+        address,
+        last_error.unwrap_or_else(|| ErrorKind::NotFound.into()),
+    ))
+}
 
-    Ok(remote_server)
+pub(crate) fn upstream_connect_error(address: &Address, source: io::Error) -> TcpIpError {
+    if source.kind() == ErrorKind::TimedOut {
+        TcpIpError::UpstreamTimeout
+    } else {
+        TcpIpError::UpstreamConnect {
+            address: address.to_string(),
+            source,
+        }
+    }
 }
 
 pub fn setup_stream(stream: &TcpStream, timeout_seconds: u64) -> Result<()> {
@@ -26,40 +208,934 @@ pub fn setup_stream(stream: &TcpStream, timeout_seconds: u64) -> Result<()> {
     Ok(())
 }
 
-pub fn forward_request(
-    proxy_server_name: &str,
-    request: &mut Request,
-    local_writer: &mut BufWriter<&TcpStream>,
-    remote_address: &SocketAddrV4,
-    timeout_seconds: u64,
-) -> Result<()> {
-    let remote_server = connect_remote(remote_address, timeout_seconds)?;
+/// How long and for how many more requests, including the current one, the
+/// local connection may be kept open.
+#[derive(Debug, Clone, Copy)]
+pub struct KeepAlive {
+    pub timeout_seconds: u64,
+    pub remaining_requests: usize,
+}
+
+/// Forwards requests from a local server to a remote server.
+pub struct Forwarder<S = TcpStream> {
+    pub name: String,
+    pub remote_address: Address,
+    pub timeout_seconds: u64,
+    pub pool: ConnectionPool<S>,
+    pub error_page: ErrorPage,
+    /// Largest request body read into memory to replay or route a request,
+    /// forwarded bodies are streamed and not limited.
+    pub max_body_size: u64,
+}
+
+/// Whether the connections used to forward a request can be reused.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Relayed {
+    /// Status of the response sent to the client.
+    pub status_code: u16,
+    pub remote_keep_alive: bool,
+    pub local_keep_alive: bool,
+}
+
+/// What happened to a forwarded request, for the access log.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Forwarded {
+    /// Status of the response sent to the client.
+    pub status_code: u16,
+    /// Time from sending the request to the remote server to receiving the
+    /// header of its response, if it was reached.
+    pub upstream_latency: Option<Duration>,
+    /// True if the local connection can be reused for another request.
+    pub keep_alive: bool,
+    /// The error an error page was sent for.
+    pub error: Option<String>,
+}
+
+impl Forwarded {
+    /// An error page was sent for `e` instead of a response from the remote server.
+    pub(crate) fn failed(e: &TcpIpError) -> Self {
+        Self {
+            status_code: e.to_status() as u16,
+            upstream_latency: None,
+            keep_alive: false,
+            error: Some(e.to_string()),
+        }
+    }
+}
+
+impl Forwarder {
+    /// Forwards `request` to the remote server and streams the response back.
+    ///
+    /// If the remote server can't be reached or fails to respond, a 502 Bad Gateway
+    /// or 504 Gateway Timeout response is sent instead.
+    pub fn forward_request<L: Write>(
+        &self,
+        request: &mut Request,
+        local_writer: &mut L,
+        keep_alive: KeepAlive,
+    ) -> Result<Forwarded> {
+        self.forward_to(
+            &self.remote_address,
+            None,
+            request,
+            local_writer,
+            keep_alive,
+        )
+    }
+
+    /// Forwards `request` to the server it names, sending a 403 Forbidden response
+    /// if `destinations` doesn't allow it or a 400 Bad Request if it names an invalid one.
+    ///
+    /// Names are resolved before connecting so the addresses can be checked
+    /// with `Destinations::check_resolved`.
+    pub fn proxy_request<L: Write>(
+        &self,
+        destinations: &Destinations,
+        request: &mut Request,
+        local_writer: &mut L,
+        keep_alive: KeepAlive,
+    ) -> Result<Forwarded> {
+        let request_version = request.header.version;
+        let address = match destinations.resolve(&mut request.header, &self.remote_address) {
+            Ok(address) => address,
+            Err(e) => return self.forward_error(&e, local_writer, request_version),
+        };
+
+        // The remote server is trusted, others are connected to at the addresses
+        // that were checked, as resolving the name again could give different ones
+        if address == self.remote_address {
+            return self.forward_to(&address, None, request, local_writer, keep_alive);
+        }
+
+        let resolved = address
+            .resolve()
+            .map_err(|e| upstream_connect_error(&address, e))
+            .and_then(|resolved| {
+                destinations.check_resolved(&address, &resolved)?;
+
+                Ok(resolved)
+            });
+
+        match resolved {
+            Ok(resolved) => {
+                self.forward_to(&address, Some(&resolved), request, local_writer, keep_alive)
+            }
+            Err(e) => self.forward_error(&e, local_writer, request_version),
+        }
+    }
+
+    // Connects to `remote_address`, or to the addresses it was already resolved to if given
+    fn connect(
+        &self,
+        remote_address: &Address,
+        resolved: Option<&[SocketAddr]>,
+    ) -> Result<TcpStream> {
+        match resolved {
+            Some(resolved) => connect_resolved(remote_address, resolved, self.timeout_seconds),
+            None => connect_remote(remote_address, self.timeout_seconds),
+        }
+    }
+
+    fn forward_to<L: Write>(
+        &self,
+        remote_address: &Address,
+        resolved: Option<&[SocketAddr]>,
+        request: &mut Request,
+        local_writer: &mut L,
+        keep_alive: KeepAlive,
+    ) -> Result<Forwarded> {
+        let reuse_local = request.header.is_keep_alive() && keep_alive.remaining_requests > 1;
+        let request_version = prepare_request(request);
+
+        let checkout = match resolved {
+            Some(resolved) => {
+                self.pool
+                    .checkout_resolved(remote_address, resolved, self.timeout_seconds)
+            }
+            None => self.pool.checkout(remote_address, self.timeout_seconds),
+        };
+
+        let mut connection = match checkout {
+            Ok(connection) => connection,
+            Err(e) => return self.forward_error(&e, local_writer, request_version),
+        };
+
+        loop {
+            let mut remote_reader = BufReader::new(&connection.stream);
+            let mut remote_writer = BufWriter::new(&connection.stream);
+            let sent = Instant::now();
+
+            let response_header = match exchange_header(
+                &mut remote_reader,
+                &mut remote_writer,
+                request,
+                local_writer,
+                request_version,
+            ) {
+                Ok(response_header) => response_header,
+                // A pooled connection may have been closed by the remote server while it was
+                // idle, in which case requests that are safe to repeat are sent on a new one
+                Err(e) if connection.reused && is_retryable(request, &e) => {
+                    drop(remote_writer);
+                    request.body.rewind();
+
+                    connection = match self.connect(remote_address, resolved) {
+                        Ok(stream) => PooledConnection {
+                            stream,
+                            reused: false,
+                        },
+                        Err(e) => return self.forward_error(&e, local_writer, request_version),
+                    };
+
+                    continue;
+                }
+                Err(e) => return self.forward_error(&e, local_writer, request_version),
+            };
+
+            let upstream_latency = sent.elapsed();
+            let local_keep_alive = if reuse_local { Some(keep_alive) } else { None };
+
+            let relayed = self.relay_response(
+                request,
+                response_header,
+                &mut remote_reader,
+                local_writer,
+                request_version,
+                local_keep_alive,
+            )?;
+
+            drop(remote_writer);
+
+            if relayed.remote_keep_alive && remote_reader.buffer().is_empty() {
+                self.pool.checkin(remote_address, connection.stream);
+            }
+
+            return Ok(Forwarded {
+                status_code: relayed.status_code,
+                upstream_latency: Some(upstream_latency),
+                keep_alive: relayed.local_keep_alive,
+                error: None,
+            });
+        }
+    }
+
+    /// Answers `request` with a recorded response from `replay` instead of
+    /// forwarding it, or with a 404 Not Found response if nothing was recorded for it.
+    pub fn replay_request<L: Write>(
+        &self,
+        replay: &Replay,
+        request: &mut Request,
+        local_writer: &mut L,
+        keep_alive: KeepAlive,
+    ) -> Result<Forwarded> {
+        self.respond_locally(request, local_writer, keep_alive, |request, keep_alive| {
+            replay.respond(request, keep_alive, &self.error_page)
+        })
+    }
+
+    /// Answers `request` with the handler `router` has for it.
+    ///
+    /// A 500 Internal Server Error response is sent if the handler panics.
+    pub fn route_request<L: Write>(
+        &self,
+        router: &Router,
+        request: &mut Request,
+        local_writer: &mut L,
+        keep_alive: KeepAlive,
+    ) -> Result<Forwarded> {
+        self.respond_locally(request, local_writer, keep_alive, |request, keep_alive| {
+            router.respond(request, keep_alive, &self.error_page)
+        })
+    }
+
+    // Sends the response `respond` builds for `request` once its body has been read
+    fn respond_locally<L, F>(
+        &self,
+        request: &mut Request,
+        local_writer: &mut L,
+        keep_alive: KeepAlive,
+        respond: F,
+    ) -> Result<Forwarded>
+    where
+        L: Write,
+        F: FnOnce(&mut Request, Option<KeepAlive>) -> Result<Response<'static>>,
+    {
+        let reuse_local = request.header.is_keep_alive() && keep_alive.remaining_requests > 1;
+        let request_version = request.header.version;
+
+        if let Err(e) = request.buffer_body_with_limit(self.max_body_size) {
+            return self.forward_error(&e, local_writer, request_version);
+        }
+
+        let local_keep_alive = if reuse_local { Some(keep_alive) } else { None };
+        let mut response = match respond(request, local_keep_alive) {
+            Ok(response) => response,
+            Err(e) => return self.forward_error(&e, local_writer, request_version),
+        };
+
+        response.write_to(local_writer, request_version)?;
+        local_writer.flush()?;
+
+        Ok(Forwarded {
+            status_code: response.header.status_code,
+            upstream_latency: None,
+            keep_alive: !response.header.has_connection_option("close"),
+            error: None,
+        })
+    }
+
+    fn forward_error<L: Write>(
+        &self,
+        e: &TcpIpError,
+        local_writer: &mut L,
+        request_version: HttpVersion,
+    ) -> Result<Forwarded> {
+        self.send_error_response(e, local_writer, request_version)?;
+
+        Ok(Forwarded::failed(e))
+    }
+
+    /// Streams the response with `response_header` from `remote_reader` to `local_writer`.
+    ///
+    /// The local connection is kept open with the `keep_alive` limits if they are
+    /// given, otherwise it is closed once the response has been sent.
+    pub fn relay_response<R: BufRead, L: Write>(
+        &self,
+        request: &Request,
+        response_header: ResponseHeader,
+        remote_reader: &mut R,
+        local_writer: &mut L,
+        request_version: HttpVersion,
+        keep_alive: Option<KeepAlive>,
+    ) -> Result<Relayed> {
+        let body_type = match response_header.body_type_for(&request.header.method) {
+            Ok(body_type) => body_type,
+            Err(e) => {
+                let e = e.into_upstream();
+
+                self.send_error_response(&e, local_writer, request_version)?;
+
+                return Ok(Relayed {
+                    status_code: e.to_status() as u16,
+                    remote_keep_alive: false,
+                    local_keep_alive: false,
+                });
+            }
+        };
+
+        // The connection can only be reused once the whole response has been read
+        // and if the remote server has not closed it to end the body
+        let remote_keep_alive =
+            response_header.is_keep_alive() && body_type != Some(BodyType::UntilClose);
+
+        let body = if let Some(b) = body_type {
+            Body::from_reader(remote_reader, &b)
+        } else {
+            Body::Empty
+        };
+
+        let mut response = Response::new(response_header, body);
+
+        response.header.strip_hop_by_hop();
+        response.header.version = HttpVersion::Http11;
+
+        set_connection_headers(&mut response.header, keep_alive);
+
+        response.write_to(local_writer, request_version)?;
+        local_writer.flush()?;
+
+        // A body without a known length may also have been delimited by closing the connection
+        Ok(Relayed {
+            status_code: response.header.status_code,
+            remote_keep_alive,
+            local_keep_alive: !response.header.has_connection_option("close"),
+        })
+    }
+}
+
+impl<S> Forwarder<S> {
+    /// Sends the error page for `e` with the status given by `TcpIpError::to_status`.
+    ///
+    /// The request body may not have been read so the local connection is always closed.
+    /// Nothing is sent if the client has closed the connection, as 499 is only for logs.
+    pub(crate) fn send_error_response<L: Write>(
+        &self,
+        e: &TcpIpError,
+        local_writer: &mut L,
+        request_version: HttpVersion,
+    ) -> Result<()> {
+        let status = e.to_status();
+
+        if status == ResponseStatus::ClientClosedRequest {
+            return Ok(());
+        }
+
+        let mut response = self.error_page.render(status, &e.to_string())?;
 
-    let mut remote_reader = BufReader::new(&remote_server);
-    let mut remote_writer = BufWriter::new(&remote_server);
+        response.header.insert_header("Connection", "close");
 
+        response.write_to(local_writer, request_version)?;
+        local_writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Sets the `Connection` and `Keep-Alive` headers of a response to keep the
+/// local connection open with the `keep_alive` limits, or to close it if `None`.
+pub(crate) fn set_connection_headers(
+    response_header: &mut ResponseHeader,
+    keep_alive: Option<KeepAlive>,
+) {
+    if let Some(keep_alive) = keep_alive {
+        response_header.insert_header("Connection", "keep-alive");
+        response_header.insert_header(
+            "Keep-Alive",
+            &format!(
+                "timeout={}, max={}",
+                keep_alive.timeout_seconds,
+                keep_alive.remaining_requests - 1
+            ),
+        );
+    } else {
+        response_header.insert_header("Connection", "close");
+    }
+}
+
+/// Strips the hop by hop headers from a request that is about to be forwarded,
+/// returning the HTTP version it was sent with.
+pub fn prepare_request(request: &mut Request) -> HttpVersion {
     request.header.strip_hop_by_hop();
 
-    remote_writer.write_all(&request.to_bytes()?)?;
-    remote_writer.flush()?;
+    // Intermediaries forward messages with their own HTTP version, see RFC 9110 section 6.2
+    let request_version = request.header.version;
+    request.header.version = HttpVersion::Http11;
 
-    let mut response = Response::from_reader(&mut remote_reader)?;
+    request_version
+}
 
-    response.header.strip_hop_by_hop();
+/// Sends the request and reads the header of the final response, relaying any
+/// interim 1xx responses. They can't be sent to HTTP/1.0 clients so are dropped for them.
+///
+/// Errors talking to the remote server are returned as upstream errors, and errors
+/// relaying an interim response as client errors.
+pub fn exchange_header<R: BufRead, W: Write, L: Write>(
+    remote_reader: &mut R,
+    remote_writer: &mut W,
+    request: &mut Request,
+    local_writer: &mut L,
+    request_version: HttpVersion,
+) -> Result<ResponseHeader> {
+    write_request(request, remote_writer)
+        .and_then(|()| Ok(remote_writer.flush()?))
+        .map_err(|e| upstream_error(e, false))?;
+
+    let mut responded = false;
 
-    let body = response.body();
+    loop {
+        let mut response_header =
+            ResponseHeader::from_reader(remote_reader).map_err(|e| upstream_error(e, responded))?;
 
-    if let Some(response_headers) = response.header.headers_mut() {
-        if let Some(body) = body {
-            response_headers.insert("Content-Length", &body.len().to_string());
+        if !response_header.is_informational() || response_header.status_code == 101 {
+            return Ok(response_header);
         }
+
+        responded = true;
+
+        if request_version >= HttpVersion::Http11 {
+            response_header.strip_hop_by_hop();
+            response_header.version = HttpVersion::Http11;
+
+            local_writer
+                .write_all(&response_header.to_bytes()?)
+                .and_then(|()| local_writer.flush())
+                .map_err(client_error)?;
+        }
+    }
+}
+
+// Converts an error sending a request or reading its response. The request can
+// only be sent again if the connection closed before any response, interim or
+// final, was received.
+pub(crate) fn upstream_error(e: TcpIpError, responded: bool) -> TcpIpError {
+    match e {
+        TcpIpError::ConnectionClosed if !responded => TcpIpError::UpstreamClosed,
+        e => e.into_upstream(),
+    }
+}
+
+// Converts an error writing to the client while forwarding a request
+pub(crate) fn client_error(e: io::Error) -> TcpIpError {
+    match TcpIpError::from(e) {
+        e if e.is_timeout() => TcpIpError::ClientTimeout,
+        e => e,
     }
+}
 
-    local_writer.write_all(&response.to_bytes()?)?;
-    local_writer.flush()?;
+// Sends `request` like `HttpItem::write_to`, but errors reading a streamed body
+// from the client are returned as client errors so they aren't blamed on the
+// remote server
+fn write_request<W: Write>(request: &mut Request, remote_writer: &mut W) -> Result<()> {
+    // Framed for the version sent upstream, so a body of unknown length is chunked
+    // rather than delimited by closing, which isn't possible for a request
+    let chunked = if request.body.is_empty() {
+        false
+    } else {
+        let content_length = request.body.content_length();
+        let upstream_version = request.header.version;
 
-    request.pretty_print(proxy_server_name);
-    response.pretty_print(proxy_server_name);
+        request.header.frame_body(content_length, upstream_version)
+    };
 
-    Ok(())
+    remote_writer.write_all(&request.header.to_bytes()?)?;
+
+    let mut body = ClientBody {
+        body: &mut request.body,
+        error: None,
+    };
+
+    let written = if chunked {
+        let mut chunked_writer = ChunkedWriter::new(&mut *remote_writer);
+
+        io::copy(&mut body, &mut chunked_writer).and_then(|_| chunked_writer.finish().map(drop))
+    } else {
+        io::copy(&mut body, remote_writer).map(drop)
+    };
+
+    match (written, body.error) {
+        (Ok(()), _) => Ok(()),
+        (Err(_), Some(e)) => Err(TcpIpError::from_client_body(e)),
+        (Err(e), None) => Err(e.into()),
+    }
+}
+
+// Keeps the error reading a request body, as copying it loses which side failed
+struct ClientBody<'b, 'a> {
+    body: &'b mut Body<'a>,
+    error: Option<io::Error>,
+}
+
+impl Read for ClientBody<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf).map_err(|e| {
+            let copy = io::Error::new(e.kind(), e.to_string());
+
+            self.error = Some(e);
+            copy
+        })
+    }
+}
+
+pub(crate) fn is_retryable(request: &Request, e: &TcpIpError) -> bool {
+    request.header.method.is_idempotent()
+        && request.body.is_replayable()
+        && matches!(e, TcpIpError::UpstreamClosed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, BufReader, BufWriter, Cursor, ErrorKind, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    use crate::address::Address;
+    use crate::body::Body;
+    use crate::body_type::BodyType;
+    use crate::error::TcpIpError;
+    use crate::error_page::ErrorPage;
+    use crate::header_item::HeaderItem;
+    use crate::http_item::HttpItem;
+    use crate::http_version::HttpVersion;
+    use crate::request::request_header::RequestHeader;
+    use crate::request::request_method::RequestMethod;
+    use crate::request::{Request, RequestBuilder};
+    use crate::stream_helper::{
+        connect_remote, exchange_header, prepare_request, ConnectionPool, Forwarded, Forwarder,
+        KeepAlive, Relayed, DEFAULT_MAX_BODY_SIZE,
+    };
+
+    fn forwarder(remote_address: Address) -> Forwarder {
+        Forwarder {
+            name: "test".to_owned(),
+            remote_address,
+            timeout_seconds: 4,
+            pool: ConnectionPool::default(),
+            error_page: ErrorPage::new("text/plain", "{status_code} {reason_phrase}"),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    // Forwards a raw request to a remote server that replies with `raw_response`,
+    // returning what was sent to the remote server and to the client
+    fn relay_in_memory(
+        raw_request: &[u8],
+        raw_response: &[u8],
+        keep_alive: Option<KeepAlive>,
+    ) -> (Vec<u8>, Vec<u8>, Relayed) {
+        let forwarder = forwarder(listen().1);
+
+        let mut local_reader = Cursor::new(raw_request.to_vec());
+        let mut remote_reader = Cursor::new(raw_response.to_vec());
+        let mut remote_writer = Vec::new();
+        let mut local_writer = Vec::new();
+
+        let mut request = Request::from_reader(&mut local_reader).expect("Failed to read request");
+        let request_version = prepare_request(&mut request);
+
+        let response_header = exchange_header(
+            &mut remote_reader,
+            &mut remote_writer,
+            &mut request,
+            &mut local_writer,
+            request_version,
+        )
+        .expect("Failed to exchange header");
+
+        let relayed = forwarder
+            .relay_response(
+                &request,
+                response_header,
+                &mut remote_reader,
+                &mut local_writer,
+                request_version,
+                keep_alive,
+            )
+            .expect("Failed to relay response");
+
+        (remote_writer, local_writer, relayed)
+    }
+
+    fn listen() -> (TcpListener, Address) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+        let address = listener.local_addr().expect("Failed to read local address");
+
+        (listener, Address::from(address))
+    }
+
+    #[test]
+    fn test_pool_reuses_healthy_connections() {
+        let (listener, address) = listen();
+        let pool = ConnectionPool::new(1, Duration::from_secs(60));
+
+        let connection = pool.checkout(&address, 4).expect("Failed to connect");
+        assert!(!connection.reused);
+
+        let (remote, _) = listener.accept().expect("Failed to accept");
+
+        pool.checkin(&address, connection.stream);
+        assert_eq!(pool.idle_count(&address), 1);
+
+        let connection = pool.checkout(&address, 4).expect("Failed to connect");
+        assert!(connection.reused);
+        assert_eq!(pool.idle_count(&address), 0);
+
+        pool.checkin(&address, connection.stream);
+
+        // Once the remote server closes the connection the health check must fail
+        drop(remote);
+        std::thread::sleep(Duration::from_millis(50));
+
+        let connection = pool.checkout(&address, 4).expect("Failed to connect");
+        assert!(!connection.reused);
+    }
+
+    #[test]
+    fn test_pool_limits() {
+        let (_listener, address) = listen();
+        let pool = ConnectionPool::new(1, Duration::from_secs(60));
+
+        let first = pool.checkout(&address, 4).expect("Failed to connect");
+        let second = pool.checkout(&address, 4).expect("Failed to connect");
+
+        pool.checkin(&address, first.stream);
+        pool.checkin(&address, second.stream);
+        assert_eq!(pool.idle_count(&address), 1);
+
+        let pool = ConnectionPool::new(1, Duration::from_secs(0));
+
+        let connection = pool.checkout(&address, 4).expect("Failed to connect");
+        pool.checkin(&address, connection.stream);
+
+        let connection = pool.checkout(&address, 4).expect("Failed to connect");
+        assert!(!connection.reused);
+    }
+
+    #[test]
+    fn test_connect_remote_tries_every_address() {
+        let (listener, address) = listen();
+
+        // localhost may resolve to ::1 first, which nothing is listening on
+        let remote =
+            connect_remote(&Address::new("localhost", address.port), 4).expect("Failed to connect");
+
+        assert_eq!(
+            remote.peer_addr().expect("Failed to read peer address"),
+            listener.local_addr().expect("Failed to read local address")
+        );
+
+        // IPv6 may be unavailable in which case only the IPv4 addresses are checked
+        if let Ok(listener) = TcpListener::bind("[::1]:0") {
+            let address = Address::from(listener.local_addr().expect("Failed to read address"));
+
+            assert_eq!(address.to_string(), format!("[::1]:{}", address.port));
+            assert!(connect_remote(&address, 4).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_bad_gateway_response() {
+        // Nothing listens on the remote address once the listener is dropped
+        let remote_address = listen().1;
+
+        let forwarder = forwarder(remote_address);
+
+        let (listener, local_address) = listen();
+        let mut client = TcpStream::connect((local_address.host.as_str(), local_address.port))
+            .expect("Failed to connect");
+        let (local, _) = listener.accept().expect("Failed to accept");
+
+        let mut request = RequestBuilder::new()
+            .method(RequestMethod::Get)
+            .uri("/")
+            .build()
+            .expect("Failed to build request");
+
+        let keep_alive = KeepAlive {
+            timeout_seconds: 4,
+            remaining_requests: 100,
+        };
+
+        let forwarded = forwarder
+            .forward_request(&mut request, &mut BufWriter::new(&local), keep_alive)
+            .expect("Failed to forward request");
+
+        assert_eq!(
+            forwarded,
+            Forwarded {
+                status_code: 502,
+                upstream_latency: None,
+                keep_alive: false,
+                error: forwarded.error.clone(),
+            }
+        );
+        assert!(forwarded
+            .error
+            .is_some_and(|e| e.starts_with("Failed to connect to ")));
+
+        drop(local);
+
+        let mut response = String::new();
+        client
+            .read_to_string(&mut response)
+            .expect("Failed to read response");
+
+        assert_eq!(
+            response,
+            "HTTP/1.1 502 Bad Gateway\r\nContent-Type: text/plain\r\nConnection: close\r\nContent-Length: 15\r\n\r\n502 Bad Gateway"
+        );
+    }
+
+    #[test]
+    fn test_exchange_header_error_origin() {
+        struct HungUp;
+
+        impl Write for HungUp {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let exchange = |raw_response: &[u8], mut local_writer: &mut dyn Write| {
+            let mut request = RequestBuilder::new()
+                .method(RequestMethod::Get)
+                .uri("/")
+                .build()
+                .expect("Failed to build request");
+
+            exchange_header(
+                &mut Cursor::new(raw_response.to_vec()),
+                &mut Vec::new(),
+                &mut request,
+                &mut local_writer,
+                HttpVersion::Http11,
+            )
+            .expect_err("Exchanged a header")
+        };
+
+        // Only a connection closed before any response may be retried
+        assert_eq!(exchange(b"", &mut Vec::new()), TcpIpError::UpstreamClosed);
+        assert_eq!(
+            exchange(b"HTTP/1.1 100 Continue\r\n\r\n", &mut Vec::new()),
+            TcpIpError::UpstreamResponse(Box::new(TcpIpError::ConnectionClosed))
+        );
+        assert_eq!(
+            exchange(b"HTTP/1.1 100 Continue\r\n\r\n", &mut HungUp),
+            TcpIpError::ConnectionClosed
+        );
+    }
+
+    #[test]
+    fn test_no_retry_after_interim_response() {
+        let (listener, address) = listen();
+        let forwarder = forwarder(address.clone());
+
+        let connection = forwarder
+            .pool
+            .checkout(&address, 4)
+            .expect("Failed to connect");
+        forwarder.pool.checkin(&address, connection.stream);
+
+        let remote = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("Failed to accept");
+            let mut reader = BufReader::new(&stream);
+
+            RequestHeader::from_reader(&mut reader).expect("Failed to read request");
+            (&stream)
+                .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                .expect("Failed to write");
+
+            listener
+        });
+
+        let mut request = RequestBuilder::new()
+            .method(RequestMethod::Get)
+            .uri("/")
+            .build()
+            .expect("Failed to build request");
+        let mut local_writer = Vec::new();
+
+        let forwarded = forwarder
+            .forward_request(
+                &mut request,
+                &mut local_writer,
+                KeepAlive {
+                    timeout_seconds: 4,
+                    remaining_requests: 10,
+                },
+            )
+            .expect("Failed to forward request");
+
+        assert_eq!(forwarded.status_code, 502);
+
+        let local = String::from_utf8_lossy(&local_writer);
+
+        assert_eq!(local.matches("100 Continue").count(), 1);
+        assert!(local.contains("HTTP/1.1 502 Bad Gateway\r\n"));
+
+        // The request wasn't sent again on a new connection
+        let listener = remote.join().expect("Remote server panicked");
+        listener
+            .set_nonblocking(true)
+            .expect("Failed to set non-blocking");
+        assert!(listener.accept().is_err());
+    }
+
+    #[test]
+    fn test_no_response_to_closed_client() {
+        let forwarder = forwarder(listen().1);
+        let mut local_writer = Vec::new();
+
+        // 499 is only logged, as the client is no longer there to receive it
+        forwarder
+            .send_error_response(
+                &TcpIpError::ConnectionClosed,
+                &mut local_writer,
+                HttpVersion::Http11,
+            )
+            .expect("Failed to send error response");
+
+        assert!(local_writer.is_empty());
+    }
+
+    #[test]
+    fn test_relay_in_memory_keep_alive() {
+        let (remote, local, relayed) = relay_in_memory(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n",
+            b"HTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+            Some(KeepAlive {
+                timeout_seconds: 4,
+                remaining_requests: 10,
+            }),
+        );
+
+        assert_eq!(
+            remote,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".as_ref()
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&local),
+            "HTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\nHTTP/1.1 200 OK\r\nConnection: keep-alive\r\nKeep-Alive: timeout=4, max=9\r\nContent-Length: 2\r\n\r\nok"
+        );
+        assert_eq!(
+            relayed,
+            Relayed {
+                status_code: 200,
+                remote_keep_alive: true,
+                local_keep_alive: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_http10_request_of_unknown_length() {
+        let mut chunks = Cursor::new(b"5\r\nhello\r\n0\r\n\r\n".to_vec());
+        let header = RequestBuilder::new()
+            .method(RequestMethod::Post)
+            .uri("/x")
+            .version(HttpVersion::Http10)
+            .header("Host", "a")
+            .build()
+            .expect("Failed to build request")
+            .header;
+        let mut request = Request::new(header, Body::from_reader(&mut chunks, &BodyType::Chunked));
+
+        let request_version = prepare_request(&mut request);
+        let mut remote_writer = Vec::new();
+
+        exchange_header(
+            &mut Cursor::new(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".to_vec()),
+            &mut remote_writer,
+            &mut request,
+            &mut Vec::new(),
+            request_version,
+        )
+        .expect("Failed to exchange header");
+
+        // The body is chunked for the remote server rather than delimited by closing
+        assert_eq!(
+            String::from_utf8_lossy(&remote_writer),
+            "POST /x HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_relay_in_memory_http10_client() {
+        // HTTP/1.0 clients get no interim responses and can't receive a chunked body
+        let (remote, local, relayed) = relay_in_memory(
+            b"POST /echo HTTP/1.0\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello",
+            b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nSet-Cookie: a=1\r\nTransfer-Encoding: chunked\r\nSet-Cookie: b=2\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+            None,
+        );
+
+        assert_eq!(
+            String::from_utf8_lossy(&remote),
+            "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello"
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&local),
+            "HTTP/1.1 200 OK\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\nConnection: close\r\n\r\nhello"
+        );
+        assert_eq!(
+            relayed,
+            Relayed {
+                status_code: 200,
+                remote_keep_alive: true,
+                local_keep_alive: false,
+            }
+        );
+    }
 }
@@ -1,3 +1,9 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, Weak};
+
 pub fn slice_find_to_end(main: &[u8], end: &[u8]) -> Option<usize> {
     let len = end.len();
 
@@ -11,9 +17,121 @@ pub fn slice_find_to_end(main: &[u8], end: &[u8]) -> Option<usize> {
     }
 }
 
+/// Returns the byte offset of `part` within `main`, where `part` is a slice of `main`.
+pub fn str_offset(main: &str, part: &str) -> usize {
+    (part.as_ptr() as usize).saturating_sub(main.as_ptr() as usize)
+}
+
+/// Decodes the `%XX` escapes in `value`, leaving invalid escapes as they are.
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let hex = |i: usize| bytes.get(i).and_then(|b| (*b as char).to_digit(16));
+
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match (bytes[i], hex(i + 1), hex(i + 2)) {
+            (b'%', Some(high), Some(low)) => {
+                decoded.push((high * 16 + low) as u8);
+                i += 3;
+            }
+            (b, _, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Encodes every byte of `value` except the unreserved characters of RFC 3986,
+/// so the result can be used as any component of a URI.
+pub fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(b as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", b);
+        }
+    }
+
+    encoded
+}
+
+/// Quotes `value` as a JSON string.
+pub fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+
+    escaped.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+/// Quotes `value` as a JSON string, or returns `null`.
+pub fn json_option(value: Option<&str>) -> String {
+    value.map(json_string).unwrap_or_else(|| "null".to_owned())
+}
+
+/// Files written by more than one server, so that each path is only opened
+/// once while any server uses it.
+pub(crate) struct SharedFiles<T> {
+    open: OnceLock<Mutex<HashMap<PathBuf, Weak<T>>>>,
+}
+
+impl<T> SharedFiles<T> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            open: OnceLock::new(),
+        }
+    }
+
+    /// Returns the file open at `path`, or opens it with `open`.
+    pub(crate) fn get_or_open<F>(&self, path: &Path, open: F) -> io::Result<Arc<T>>
+    where
+        F: FnOnce(&Path) -> io::Result<T>,
+    {
+        let mut files = self
+            .open
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        files.retain(|_, file| file.strong_count() > 0);
+
+        if let Some(file) = files.get(path).and_then(Weak::upgrade) {
+            return Ok(file);
+        }
+
+        let file = Arc::new(open(path)?);
+
+        files.insert(path.to_owned(), Arc::downgrade(&file));
+
+        Ok(file)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::util::slice_find_to_end;
+    use crate::util::{percent_decode, percent_encode, slice_find_to_end, str_offset};
 
     #[test]
     fn test_slice_find_to_end() {
@@ -23,4 +141,27 @@ mod tests {
 
         assert_eq!(&slice[..res], [72, 84, 84, 80, 47, 50]);
     }
+
+    #[test]
+    fn test_str_offset() {
+        let line = "GET / HTTP/1.1";
+        let version = line.split(' ').nth(2).expect("Missing version");
+
+        assert_eq!(str_offset(line, version), 6);
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b%2Fc"), "a b/c");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%e2%82%ac"), "%zz\u{20ac}");
+    }
+
+    #[test]
+    fn test_percent_encode() {
+        let value = "a b/c?d=e&f~\u{20ac}";
+
+        assert_eq!(percent_encode(value), "a%20b%2Fc%3Fd%3De%26f~%E2%82%AC");
+        assert_eq!(percent_decode(&percent_encode(value)), value);
+    }
 }